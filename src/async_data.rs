@@ -4,6 +4,15 @@ use std::future::Future;
 use tokio::sync::mpsc;
 use tokio::sync::oneshot::{self, error::TryRecvError};
 
+/// Runs a future on the background runtime without waiting for its result.
+pub fn detach<F>(m_tx: &mpsc::UnboundedSender<Message>, fut: F)
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    let msg = Message::Execute(fut.boxed());
+    m_tx.send(msg).ok().expect("Message receiver closed");
+}
+
 pub enum AsyncData<T> {
     NotStarted,
     InProgress(oneshot::Receiver<T>),
@@ -26,17 +35,20 @@ impl<T> AsyncData<T> {
         T: Send + 'static,
         F: Future<Output = T> + Send + 'static,
     {
-        assert!(matches!(self, Self::NotStarted));
+        assert!(!self.is_in_progress());
 
         let (tx, rx) = oneshot::channel();
         let tx_fut = async move {
             tx.send(fut.await).ok().expect("Receiver closed");
         };
-        let msg = Message::Execute(tx_fut.boxed());
-        m_tx.send(msg).ok().expect("Message receiver closed");
+        detach(m_tx, tx_fut);
         *self = Self::InProgress(rx);
     }
 
+    pub fn is_in_progress(&self) -> bool {
+        matches!(self, Self::InProgress(_))
+    }
+
     pub fn poll(&mut self) -> Option<T> {
         if let Self::InProgress(rx) = self {
             match rx.try_recv() {
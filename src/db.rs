@@ -0,0 +1,485 @@
+//! The local database, holding everything fetched from the API along with the library,
+//! read progress and downloads.
+//!
+//! Endpoints write what they fetch through to it, so the UI can show what it knew last time
+//! straight away, and while offline.
+
+use chrono::Utc;
+use once_cell::sync::Lazy;
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{de::DeserializeOwned, Serialize};
+use snafu::ResultExt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use uuid::Uuid;
+
+use crate::error::{DbErr, NoDbErr};
+use crate::schema::{ChapterId, CoverId, Filename, Language, MangaId, TagId};
+use crate::{store, types, Result};
+
+/// Each entry brings the schema from the previous version to the next.
+/// They're applied in order, and must never be changed once released.
+const MIGRATIONS: &[&str] = &[r#"
+    CREATE TABLE manga (
+        id TEXT PRIMARY KEY,
+        attributes TEXT NOT NULL,
+        relationships TEXT NOT NULL,
+        fetched_at INTEGER NOT NULL
+    );
+    CREATE INDEX manga_by_fetched_at ON manga (fetched_at);
+
+    CREATE TABLE tags (
+        id TEXT PRIMARY KEY,
+        attributes TEXT NOT NULL
+    );
+    CREATE TABLE manga_tags (
+        manga_id TEXT NOT NULL,
+        tag_id TEXT NOT NULL,
+        PRIMARY KEY (manga_id, tag_id)
+    );
+
+    CREATE TABLE chapters (
+        id TEXT PRIMARY KEY,
+        manga_id TEXT NOT NULL,
+        language TEXT NOT NULL,
+        position INTEGER NOT NULL,
+        attributes TEXT NOT NULL,
+        relationships TEXT NOT NULL
+    );
+    CREATE INDEX chapters_by_manga ON chapters (manga_id, language);
+
+    CREATE TABLE covers (
+        id TEXT PRIMARY KEY,
+        manga_id TEXT NOT NULL,
+        file_name TEXT NOT NULL
+    );
+
+    CREATE TABLE library (
+        profile TEXT NOT NULL,
+        manga_id TEXT NOT NULL,
+        following INTEGER NOT NULL,
+        status TEXT,
+        PRIMARY KEY (profile, manga_id)
+    );
+
+    CREATE TABLE progress (
+        profile TEXT NOT NULL,
+        chapter_id TEXT NOT NULL,
+        manga_id TEXT NOT NULL,
+        read INTEGER NOT NULL,
+        last_page INTEGER NOT NULL,
+        pending INTEGER NOT NULL,
+        PRIMARY KEY (profile, chapter_id)
+    );
+
+    CREATE TABLE downloads (
+        chapter_id TEXT PRIMARY KEY,
+        manga_id TEXT NOT NULL,
+        position INTEGER NOT NULL,
+        job TEXT NOT NULL
+    );
+"#];
+
+fn db_location() -> PathBuf {
+    crate::data_dir().join("md.sqlite3")
+}
+
+fn migrate(conn: &mut Connection) -> rusqlite::Result<()> {
+    let version: i64 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", &(i as i64 + 1))?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+fn open() -> rusqlite::Result<Connection> {
+    let mut conn = Connection::open(db_location())?;
+    migrate(&mut conn)?;
+    Ok(conn)
+}
+
+/// The database, or why it couldn't be opened. Without it, the app just doesn't keep a copy
+/// of anything, so a locked or corrupt file doesn't stop it from starting.
+static DB: Lazy<std::result::Result<Mutex<Connection>, String>> =
+    Lazy::new(|| open().map(Mutex::new).map_err(|e| e.to_string()));
+
+/// Runs `f` with the database to itself.
+pub fn with_db<T>(f: impl FnOnce(&mut Connection) -> rusqlite::Result<T>) -> Result<T> {
+    let db = match &*DB {
+        Ok(db) => db,
+        Err(detail) => return NoDbErr { detail }.fail(),
+    };
+    let mut conn = db.lock().unwrap();
+    f(&mut conn).context(DbErr)
+}
+
+macro_rules! sql_id {
+    ($($name:ident),*) => {$(
+        impl ToSql for $name {
+            fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+                Ok(ToSqlOutput::from(self.0.to_string()))
+            }
+        }
+        impl FromSql for $name {
+            fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+                let text = value.as_str()?;
+                Uuid::parse_str(text)
+                    .map($name)
+                    .map_err(|e| FromSqlError::Other(Box::new(e)))
+            }
+        }
+    )*};
+}
+
+sql_id!(MangaId, ChapterId, CoverId, TagId);
+
+/// Everything stored as JSON is known to serialize, so failing to is a bug.
+pub fn to_json<T: Serialize>(value: &T) -> String {
+    serde_json::to_string(value).expect("Failed to serialize a value for the database")
+}
+
+/// Returns `None` for anything saved by a version that stored it differently.
+pub fn from_json<T: DeserializeOwned>(text: &str) -> Option<T> {
+    serde_json::from_str(text).ok()
+}
+
+/// Moves a store that used to be a JSON file into the database, if the file is still around.
+pub fn import_json<T: DeserializeOwned>(path: &Path, save: impl FnOnce(T) -> Result<()>) {
+    let old = match store::read_json(path) {
+        Some(old) => old,
+        None => return,
+    };
+    if save(old).is_ok() {
+        let _ = fs::remove_file(path);
+    }
+}
+
+fn manga_from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<Option<types::Manga>> {
+    let id: MangaId = row.get(0)?;
+    let attributes: String = row.get(1)?;
+    let relationships: String = row.get(2)?;
+    Ok(from_json(&attributes)
+        .zip(from_json(&relationships))
+        .map(|(attributes, relationships)| types::Manga {
+            id,
+            attributes,
+            relationships,
+        }))
+}
+
+pub fn put_manga<'a>(manga: impl IntoIterator<Item = &'a types::Manga>) -> Result<()> {
+    with_db(|conn| insert_manga(conn, manga))
+}
+
+fn insert_manga<'a>(
+    conn: &mut Connection,
+    manga: impl IntoIterator<Item = &'a types::Manga>,
+) -> rusqlite::Result<()> {
+    let now = Utc::now().timestamp_millis();
+    let tx = conn.transaction()?;
+    {
+        let mut put = tx.prepare_cached(
+            "INSERT OR REPLACE INTO manga (id, attributes, relationships, fetched_at)
+             VALUES (?1, ?2, ?3, ?4)",
+        )?;
+        let mut put_tag =
+            tx.prepare_cached("INSERT OR REPLACE INTO tags (id, attributes) VALUES (?1, ?2)")?;
+        let mut untag = tx.prepare_cached("DELETE FROM manga_tags WHERE manga_id = ?1")?;
+        let mut tag = tx.prepare_cached(
+            "INSERT OR IGNORE INTO manga_tags (manga_id, tag_id) VALUES (?1, ?2)",
+        )?;
+        for manga in manga {
+            let attributes = to_json(&manga.attributes);
+            let relationships = to_json(&manga.relationships);
+            put.execute(params![manga.id, attributes, relationships, now])?;
+            untag.execute(params![manga.id])?;
+            for t in &manga.attributes.tags {
+                put_tag.execute(params![t.id, to_json(&t.attributes)])?;
+                tag.execute(params![manga.id, t.id])?;
+            }
+        }
+    }
+    tx.commit()
+}
+
+/// Whichever of the titles have been fetched before.
+pub fn manga(ids: &[MangaId]) -> Result<Vec<types::Manga>> {
+    with_db(|conn| select_manga(conn, ids))
+}
+
+fn select_manga(conn: &Connection, ids: &[MangaId]) -> rusqlite::Result<Vec<types::Manga>> {
+    let mut stmt =
+        conn.prepare_cached("SELECT id, attributes, relationships FROM manga WHERE id = ?1")?;
+    let mut found = Vec::new();
+    for id in ids {
+        if let Some(Some(manga)) = stmt.query_row(params![id], manga_from_row).optional()? {
+            found.push(manga);
+        }
+    }
+    Ok(found)
+}
+
+/// The most recently fetched titles, newest first.
+pub fn recent_manga(limit: usize) -> Result<Vec<types::Manga>> {
+    with_db(|conn| {
+        let mut stmt = conn.prepare_cached(
+            "SELECT id, attributes, relationships FROM manga ORDER BY fetched_at DESC LIMIT ?1",
+        )?;
+        let rows = stmt.query_map(params![limit as i64], manga_from_row)?;
+        let mut found = Vec::new();
+        for manga in rows {
+            found.extend(manga?);
+        }
+        Ok(found)
+    })
+}
+
+/// Replaces the title's saved chapters in `languages` with a freshly fetched list.
+pub fn put_chapters(
+    manga_id: &MangaId,
+    languages: &[Language],
+    chapters: &[types::Chapter],
+) -> Result<()> {
+    with_db(|conn| insert_chapters(conn, manga_id, languages, chapters))
+}
+
+fn insert_chapters(
+    conn: &mut Connection,
+    manga_id: &MangaId,
+    languages: &[Language],
+    chapters: &[types::Chapter],
+) -> rusqlite::Result<()> {
+    let tx = conn.transaction()?;
+    {
+        let mut clear =
+            tx.prepare_cached("DELETE FROM chapters WHERE manga_id = ?1 AND language = ?2")?;
+        for language in languages {
+            clear.execute(params![manga_id, language])?;
+        }
+        let mut put = tx.prepare_cached(
+            "INSERT OR REPLACE INTO chapters
+             (id, manga_id, language, position, attributes, relationships)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        )?;
+        for (position, chapter) in chapters.iter().enumerate() {
+            put.execute(params![
+                chapter.id,
+                manga_id,
+                chapter.attributes.translated_language,
+                position as i64,
+                to_json(&chapter.attributes),
+                to_json(&chapter.relationships),
+            ])?;
+        }
+    }
+    tx.commit()
+}
+
+/// The title's saved chapters in `languages`, in the order they were listed.
+pub fn chapters(manga_id: &MangaId, languages: &[Language]) -> Result<Vec<types::Chapter>> {
+    with_db(|conn| select_chapters(conn, manga_id, languages))
+}
+
+fn select_chapters(
+    conn: &Connection,
+    manga_id: &MangaId,
+    languages: &[Language],
+) -> rusqlite::Result<Vec<types::Chapter>> {
+    let mut stmt = conn.prepare_cached(
+        "SELECT position, id, attributes, relationships FROM chapters
+         WHERE manga_id = ?1 AND language = ?2",
+    )?;
+    let mut found = Vec::new();
+    for language in languages {
+        let rows = stmt.query_map(params![manga_id, language], |row| {
+            let position: i64 = row.get(0)?;
+            let id: ChapterId = row.get(1)?;
+            let attributes: String = row.get(2)?;
+            let relationships: String = row.get(3)?;
+            Ok((position, id, attributes, relationships))
+        })?;
+        for row in rows {
+            let (position, id, attributes, relationships) = row?;
+            if let (Some(attributes), Some(relationships)) =
+                (from_json(&attributes), from_json(&relationships))
+            {
+                let chapter = types::Chapter {
+                    id,
+                    attributes,
+                    relationships,
+                };
+                found.push((position, chapter));
+            }
+        }
+    }
+    found.sort_by_key(|(position, _)| *position);
+    Ok(found.into_iter().map(|(_, chapter)| chapter).collect())
+}
+
+pub fn put_cover(cover_id: &CoverId, manga_id: &MangaId, file_name: &Filename) -> Result<()> {
+    with_db(|conn| {
+        conn.execute(
+            "INSERT OR REPLACE INTO covers (id, manga_id, file_name) VALUES (?1, ?2, ?3)",
+            params![cover_id, manga_id, file_name.0],
+        )
+        .map(|_| ())
+    })
+}
+
+pub fn cover_file_name(cover_id: &CoverId) -> Option<Filename> {
+    with_db(|conn| {
+        conn.query_row(
+            "SELECT file_name FROM covers WHERE id = ?1",
+            params![cover_id],
+            |row| row.get(0),
+        )
+        .optional()
+    })
+    .ok()
+    .flatten()
+    .map(Filename)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    fn migrated() -> Connection {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+        conn
+    }
+
+    fn manga(n: u128) -> types::Manga {
+        let attributes = json!({
+            "title": { "en": format!("Title {}", n) },
+            "altTitles": [],
+            "description": {},
+            "isLocked": false,
+            "links": {},
+            "originalLanguage": "ja",
+            "lastVolume": null,
+            "lastChapter": null,
+            "publicationDemographic": null,
+            "status": null,
+            "year": null,
+            "contentRating": "safe",
+            "tags": [{
+                "id": Uuid::from_u128(1000 + n),
+                "type": "tag",
+                "attributes": { "name": { "en": "Action" }, "description": [], "group": "genre", "version": 1 },
+            }],
+            "version": 1,
+            "createdAt": "2021-05-24T17:16:14Z",
+            "updatedAt": "2021-05-24T17:16:14Z",
+        });
+        types::Manga {
+            id: MangaId(Uuid::from_u128(n)),
+            attributes: serde_json::from_value(attributes).unwrap(),
+            relationships: HashMap::new(),
+        }
+    }
+
+    fn chapter(n: u128, language: &str) -> types::Chapter {
+        let attributes = json!({
+            "title": "",
+            "volume": null,
+            "chapter": n.to_string(),
+            "translatedLanguage": language,
+            "hash": "abc",
+            "data": ["1.png"],
+            "dataSaver": ["1.jpg"],
+            "uploader": null,
+            "version": 1,
+            "createdAt": "2021-05-24T17:16:14Z",
+            "updatedAt": "2021-05-24T17:16:14Z",
+            "publishAt": "2021-05-24T17:16:14Z",
+        });
+        types::Chapter {
+            id: ChapterId(Uuid::from_u128(n)),
+            attributes: serde_json::from_value(attributes).unwrap(),
+            relationships: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn migrates_a_new_database() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let version = |conn: &Connection| -> i64 {
+            conn.pragma_query_value(None, "user_version", |row| row.get(0))
+                .unwrap()
+        };
+        assert_eq!(version(&conn), 0);
+        migrate(&mut conn).unwrap();
+        assert_eq!(version(&conn), MIGRATIONS.len() as i64);
+    }
+
+    #[test]
+    fn migrating_again_changes_nothing() {
+        let mut conn = migrated();
+        insert_manga(&mut conn, &[manga(1)]).unwrap();
+        migrate(&mut conn).unwrap();
+        assert_eq!(
+            select_manga(&conn, &[MangaId(Uuid::from_u128(1))])
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn manga_round_trips() {
+        let mut conn = migrated();
+        insert_manga(&mut conn, &[manga(1), manga(2)]).unwrap();
+        let ids: Vec<_> = (1..=3).rev().map(|n| MangaId(Uuid::from_u128(n))).collect();
+        let found = select_manga(&conn, &ids).unwrap();
+        let titles: Vec<_> = found
+            .iter()
+            .map(|m| m.attributes.title["en"].as_str())
+            .collect();
+        assert_eq!(titles, ["Title 2", "Title 1"]);
+        assert_eq!(found[0].attributes.tags.len(), 1);
+    }
+
+    #[test]
+    fn chapters_round_trip_in_order() {
+        let mut conn = migrated();
+        let manga_id = MangaId(Uuid::from_u128(1));
+        let languages = ["en".to_owned(), "fr".to_owned()];
+        let listed = [chapter(3, "fr"), chapter(1, "en"), chapter(2, "en")];
+        insert_chapters(&mut conn, &manga_id, &languages, &listed).unwrap();
+
+        let found = select_chapters(&conn, &manga_id, &languages).unwrap();
+        let ids: Vec<_> = found.iter().map(|c| c.id).collect();
+        assert_eq!(ids, listed.iter().map(|c| c.id).collect::<Vec<_>>());
+
+        let english = select_chapters(&conn, &manga_id, &languages[..1]).unwrap();
+        assert_eq!(english.len(), 2);
+    }
+
+    #[test]
+    fn refetching_replaces_a_language() {
+        let mut conn = migrated();
+        let manga_id = MangaId(Uuid::from_u128(1));
+        let english = ["en".to_owned()];
+        insert_chapters(
+            &mut conn,
+            &manga_id,
+            &english,
+            &[chapter(1, "en"), chapter(2, "en")],
+        )
+        .unwrap();
+        insert_chapters(&mut conn, &manga_id, &english, &[chapter(2, "en")]).unwrap();
+
+        let found = select_chapters(&conn, &manga_id, &english).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].id, ChapterId(Uuid::from_u128(2)));
+    }
+}
@@ -1,57 +1,265 @@
+use chrono::Utc;
+use futures::stream::{self, Stream, TryStreamExt};
 use once_cell::sync::Lazy;
 use ratelimit::RateLimiter;
-use reqwest::{Client, IntoUrl};
-use serde::de::DeserializeOwned;
+use reqwest::header::{HeaderName, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use reqwest::{Client, IntoUrl, Request, StatusCode};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
 use snafu::ResultExt;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Duration;
 use tokio::time::Instant;
 use url::Url;
 
 use crate::error::{HttpErr, ImageErr, JsonErr, Result};
-use crate::schema;
+use crate::{db, schema, types};
 
+mod at_home;
 pub mod auth;
+pub mod disk_cache;
+pub mod download;
+pub mod history;
+pub mod image_cache;
+mod json_cache;
+pub mod library;
+mod paginate;
+pub mod progress;
+pub mod updates;
 
-static RATE_LIMIT: Lazy<RateLimiter> = Lazy::new(|| RateLimiter::new(5, Duration::from_secs(1)));
+pub use at_home::{ChapterPages, Quality};
+pub use paginate::{paginate, paginate_pages};
+
+static RATE_LIMIT: Lazy<RateLimiter> = Lazy::new(|| {
+    let per_second = crate::settings::get().rate_limit;
+    RateLimiter::new(per_second as _, Duration::from_secs(1))
+});
 static CLIENT: Lazy<Client> = Lazy::new(Client::new);
+static ONLINE: AtomicBool = AtomicBool::new(true);
 
-pub async fn get_json<U: IntoUrl, T: DeserializeOwned>(url: U) -> Result<T> {
+/// Runs file, database and keyring work, which can each take a while, off the runtime.
+pub(crate) async fn blocking<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> T {
+    tokio::task::spawn_blocking(f)
+        .await
+        .expect("Blocking task panicked")
+}
+
+/// Whether the most recent request got any response from a server at all.
+pub fn is_online() -> bool {
+    ONLINE.load(Ordering::Relaxed)
+}
+
+fn note_connectivity<T>(res: &reqwest::Result<T>) {
+    match res {
+        Err(e) if e.is_builder() => {}
+        // No status means we never got a response, e.g. because we're offline.
+        Err(e) if e.status().is_none() => ONLINE.store(false, Ordering::Relaxed),
+        _ => ONLINE.store(true, Ordering::Relaxed),
+    }
+}
+
+/// Checks whether the API can be reached, updating `is_online`.
+pub async fn ping() {
     let permit = RATE_LIMIT.request().await;
     let resp = CLIENT
-        .get(url)
+        .get("https://api.mangadex.org/ping")
         .send()
         .await
-        .and_then(|r| r.error_for_status())
-        .context(HttpErr)?;
-    let text = resp.text().await.context(HttpErr)?;
+        .and_then(|r| r.error_for_status());
     drop(permit);
-    let val = serde_json::from_str(&text).context(JsonErr {
-        type_name: pretty_type_name::pretty_type_name::<T>(),
-    })?;
-    Ok(val)
+    note_connectivity(&resp);
+}
+
+pub async fn get_json<U: IntoUrl, T: DeserializeOwned>(url: U) -> Result<T> {
+    get_json_with_pairs(url, &[]).await
+}
+
+/// Sends a GET request, answering from the JSON response cache where the endpoint allows.
+/// Stale cache entries are revalidated with `If-None-Match`/`If-Modified-Since`, and are
+/// served as-is if the server can't be reached at all.
+async fn get_text(mut req: Request) -> Result<String> {
+    let freshness = json_cache::freshness(req.url());
+    let cached = freshness.and_then(|_| json_cache::load(req.url()));
+
+    if let (Some(cached), Some(freshness)) = (&cached, freshness) {
+        if cached.is_fresh(freshness) {
+            return Ok(cached.body.clone());
+        }
+        let headers = req.headers_mut();
+        if let Some(etag) = cached.etag.as_deref().and_then(|v| v.parse().ok()) {
+            headers.insert(IF_NONE_MATCH, etag);
+        }
+        if let Some(date) = cached.last_modified.as_deref().and_then(|v| v.parse().ok()) {
+            headers.insert(IF_MODIFIED_SINCE, date);
+        }
+    }
+
+    let url = req.url().clone();
+    let permit = RATE_LIMIT.request().await;
+    let resp = CLIENT.execute(req).await.and_then(|r| r.error_for_status());
+    note_connectivity(&resp);
+    let resp = match resp {
+        Ok(resp) => resp,
+        Err(_) if !is_online() && cached.is_some() => {
+            return Ok(cached.unwrap().body);
+        }
+        Err(e) => return Err(e).context(HttpErr),
+    };
+
+    if resp.status() == StatusCode::NOT_MODIFIED {
+        if let Some(mut cached) = cached {
+            cached.fetched_at = Utc::now();
+            json_cache::store(&url, &cached);
+            return Ok(cached.body);
+        }
+    }
+
+    let header = |name: HeaderName| {
+        resp.headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_owned)
+    };
+    let etag = header(ETAG);
+    let last_modified = header(LAST_MODIFIED);
+    let body = resp.text().await.context(HttpErr)?;
+    drop(permit);
+
+    if freshness.is_some() {
+        let entry = json_cache::CachedResponse {
+            etag,
+            last_modified,
+            fetched_at: Utc::now(),
+            body,
+        };
+        json_cache::store(&url, &entry);
+        return Ok(entry.body);
+    }
+    Ok(body)
+}
+
+/// Flattens a serialized query into the `key[]=value` and `key[sub]=value` pairs the API expects.
+/// Unset (`None`) fields serialize as `null` and are dropped entirely.
+fn query_pairs<Q: Serialize>(query: &Q) -> Vec<(String, String)> {
+    fn flatten(key: String, val: Value, out: &mut Vec<(String, String)>) {
+        match val {
+            Value::Null => {}
+            Value::Bool(b) => out.push((key, b.to_string())),
+            Value::Number(n) => out.push((key, n.to_string())),
+            Value::String(s) => out.push((key, s)),
+            Value::Array(items) => {
+                let key = format!("{}[]", key);
+                for item in items {
+                    flatten(key.clone(), item, out);
+                }
+            }
+            Value::Object(fields) => {
+                for (k, v) in fields {
+                    flatten(format!("{}[{}]", key, k), v, out);
+                }
+            }
+        }
+    }
+
+    let mut pairs = Vec::new();
+    match serde_json::to_value(query).expect("Failed to serialize query") {
+        Value::Object(fields) => {
+            for (k, v) in fields {
+                flatten(k, v, &mut pairs);
+            }
+        }
+        Value::Null => {}
+        _ => panic!("Query must serialize as a struct"),
+    }
+    pairs
 }
 
-/*
 pub async fn query_json<U: IntoUrl, T: DeserializeOwned, Q: Serialize>(
     url: U,
-    query: Q,
+    query: &Q,
 ) -> Result<T> {
-    let permit = RATE_LIMIT.request().await;
-    let resp = CLIENT
-        .get(url)
-        .query(&query)
-        .send()
-        .await
-        .and_then(|r| r.error_for_status())
-        .context(HttpErr)?;
-    let text = resp.text().await.context(HttpErr)?;
-    drop(permit);
+    get_json_with_pairs(url, &query_pairs(query)).await
+}
+
+async fn get_json_with_pairs<U: IntoUrl, T: DeserializeOwned>(
+    url: U,
+    pairs: &[(String, String)],
+) -> Result<T> {
+    let req = CLIENT.get(url).query(pairs).build().context(HttpErr)?;
+    let text = get_text(req).await?;
     let val = serde_json::from_str(&text).context(JsonErr {
         type_name: pretty_type_name::pretty_type_name::<T>(),
     })?;
     Ok(val)
 }
-*/
+
+/// Fetches a single page of search results.
+pub async fn search_manga(query: &schema::MangaListQuery) -> Result<types::MangaList> {
+    let list: types::MangaList = query_json("https://api.mangadex.org/manga", query).await?;
+    Ok(blocking(move || {
+        let _ = db::put_manga(list.series.values());
+        list
+    })
+    .await)
+}
+
+pub fn search_manga_all(
+    query: &schema::MangaListQuery,
+) -> impl Stream<Item = Result<types::Manga>> + Send + 'static {
+    paginate_pages::<schema::MangaResponse, _>("https://api.mangadex.org/manga", query)
+        .and_then(|page| {
+            blocking(move || {
+                let manga: Vec<types::Manga> = page.into_iter().map(types::Manga::from).collect();
+                // The database is only a copy, so failing to update it shouldn't fail the fetch.
+                let _ = db::put_manga(&manga);
+                Ok(stream::iter(manga.into_iter().map(Ok)))
+            })
+        })
+        .try_flatten()
+}
+
+pub fn manga_feed(
+    manga_id: &schema::MangaId,
+    query: &schema::MangaFeedQuery,
+) -> impl Stream<Item = Result<types::Chapter>> + Send + 'static {
+    let url = format!("https://api.mangadex.org/manga/{}/feed", manga_id);
+    paginate::<schema::ChapterResponse, _>(&url, query).map_ok(types::Chapter::from)
+}
+
+pub async fn get_chapters(
+    manga_id: &schema::MangaId,
+    languages: Vec<schema::Language>,
+) -> Result<Vec<types::Chapter>> {
+    let query = schema::MangaFeedQuery {
+        limit: Some(500),
+        translated_language: Some(languages.clone()),
+        order: Some(schema::ChapterOrder {
+            volume: schema::SortDirection::Asc,
+            chapter: schema::SortDirection::Asc,
+        }),
+        ..Default::default()
+    };
+    let chapters: Vec<types::Chapter> = manga_feed(manga_id, &query).try_collect().await?;
+    let manga_id = *manga_id;
+    Ok(blocking(move || {
+        let _ = db::put_chapters(&manga_id, &languages, &chapters);
+        chapters
+    })
+    .await)
+}
+
+/// Lists the volumes of a manga and the chapter numbers in each.
+pub async fn get_aggregate(
+    manga_id: &schema::MangaId,
+    languages: &[schema::Language],
+) -> Result<schema::MangaAggregateResponse> {
+    let url = format!("https://api.mangadex.org/manga/{}/aggregate", manga_id);
+    let pairs: Vec<_> = languages
+        .iter()
+        .map(|lang| ("translatedLanguage[]".to_owned(), lang.clone()))
+        .collect();
+    get_json_with_pairs(url, &pairs).await
+}
 
 pub async fn report(url: &str, success: bool, cached: bool, bytes: usize, duration: u128) {
     let report = schema::HealthReport {
@@ -68,13 +276,18 @@ pub async fn report(url: &str, success: bool, cached: bool, bytes: usize, durati
         .await;
 }
 
-#[allow(dead_code)]
-pub async fn get_image(
+pub fn decode_image(bytes: &[u8]) -> Result<image::RgbImage> {
+    // TODO: this is super blocking
+    let img = image::load_from_memory(bytes).context(ImageErr)?;
+    Ok(img.to_rgb8())
+}
+
+pub async fn get_image_bytes(
     base_url: &Url,
     quality_mode: &str,
     hash: &schema::ChapterHash,
     filename: &schema::Filename,
-) -> Result<image::RgbImage> {
+) -> Result<Vec<u8>> {
     let url = format!("{}/{}/{}/{}", base_url, quality_mode, hash, filename);
 
     let permit = RATE_LIMIT.request().await;
@@ -83,17 +296,18 @@ pub async fn get_image(
     async fn err<T>(e: reqwest::Error, before: Instant, url: &str) -> Result<T> {
         if !e.is_builder() {
             let duration = (Instant::now() - before).as_millis();
-            report(&url, false, false, 0, duration).await;
+            report(url, false, false, 0, duration).await;
         }
         Err(e).context(HttpErr)
     }
 
-    let resp = match CLIENT
+    let resp = CLIENT
         .get(&url)
         .send()
         .await
-        .and_then(|r| r.error_for_status())
-    {
+        .and_then(|r| r.error_for_status());
+    note_connectivity(&resp);
+    let resp = match resp {
         Ok(r) => r,
         Err(e) => return err(e, before, &url).await,
     };
@@ -110,9 +324,52 @@ pub async fn get_image(
     let duration = (Instant::now() - before).as_millis();
     report(&url, true, cached, bytes.len(), duration).await;
 
-    // TODO: this is super blocking
-    let img = image::load_from_memory(&bytes).context(ImageErr)?;
-    Ok(img.to_rgb8())
+    Ok(bytes.to_vec())
+}
+
+pub async fn get_manga(manga_id: &schema::MangaId) -> Result<types::Manga> {
+    let url = format!("https://api.mangadex.org/manga/{}", manga_id);
+    let resp = get_json::<_, schema::MangaResponse>(url).await?;
+    let manga = types::Manga::from(resp);
+    Ok(blocking(move || {
+        let _ = db::put_manga(std::iter::once(&manga));
+        manga
+    })
+    .await)
+}
+
+pub async fn get_author(author_id: &schema::AuthorId) -> Result<schema::AuthorAttributes> {
+    let url = format!("https://api.mangadex.org/author/{}", author_id);
+    let resp = get_json::<_, schema::AuthorResponse>(url).await?;
+
+    assert_eq!(resp.result, schema::Success::Ok);
+    assert_eq!(resp.data.item_type, schema::ItemType::Author);
+
+    Ok(resp.data.attributes)
+}
+
+/// Looks up a cover's file name, then fetches it like `get_cover`.
+pub async fn get_cover_by_id(
+    manga_id: &schema::MangaId,
+    cover_id: &schema::CoverId,
+    quality: &str,
+) -> Result<image::RgbImage> {
+    // A cover's file name never changes, so it only needs looking up once.
+    let id = *cover_id;
+    if let Some(file_name) = blocking(move || db::cover_file_name(&id)).await {
+        return get_cover(manga_id, &file_name, quality).await;
+    }
+
+    let url = format!("https://api.mangadex.org/cover/{}", cover_id);
+    let resp = get_json::<_, schema::CoverResponse>(url).await?;
+
+    assert_eq!(resp.result, schema::Success::Ok);
+    assert_eq!(resp.data.item_type, schema::ItemType::CoverArt);
+
+    let file_name = resp.data.attributes.file_name;
+    let (id, manga, name) = (*cover_id, *manga_id, file_name.clone());
+    let _ = blocking(move || db::put_cover(&id, &manga, &name)).await;
+    get_cover(manga_id, &file_name, quality).await
 }
 
 pub async fn get_cover(
@@ -120,6 +377,18 @@ pub async fn get_cover(
     filename: &schema::Filename,
     quality: &str,
 ) -> Result<image::RgbImage> {
+    let key = image_cache::ImageKey::Cover(*manga_id, filename.clone(), quality.to_owned());
+    if let Some(img) = image_cache::get(&key) {
+        return Ok(img);
+    }
+    if let Some(img) = disk_cache::get(&key)
+        .await
+        .and_then(|bytes| decode_image(&bytes).ok())
+    {
+        image_cache::insert(key, img.clone());
+        return Ok(img);
+    }
+
     let url = format!(
         "https://uploads.mangadex.org/covers/{}/{}{}",
         manga_id, filename, quality,
@@ -127,19 +396,71 @@ pub async fn get_cover(
 
     let permit = RATE_LIMIT.request().await;
 
-    let bytes = CLIENT
+    let resp = CLIENT
         .get(&url)
         .send()
         .await
-        .and_then(|r| r.error_for_status())
-        .context(HttpErr)?
-        .bytes()
-        .await
-        .context(HttpErr)?;
+        .and_then(|r| r.error_for_status());
+    note_connectivity(&resp);
+    let bytes = resp.context(HttpErr)?.bytes().await.context(HttpErr)?;
 
     drop(permit);
 
-    // TODO: this is super blocking
-    let img = image::load_from_memory(&bytes).context(ImageErr)?;
-    Ok(img.to_rgb8())
+    let img = decode_image(&bytes)?;
+    disk_cache::insert(&key, bytes.to_vec()).await;
+    image_cache::insert(key, img.clone());
+    Ok(img)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use schema::{MangaListQuery, SortDirection, SortOrder};
+
+    fn sorted(mut pairs: Vec<(String, String)>) -> Vec<(String, String)> {
+        pairs.sort();
+        pairs
+    }
+
+    fn pair(k: &str, v: &str) -> (String, String) {
+        (k.to_owned(), v.to_owned())
+    }
+
+    #[test]
+    fn unset_fields_are_left_out() {
+        assert!(query_pairs(&MangaListQuery::default()).is_empty());
+    }
+
+    #[test]
+    fn arrays_repeat_their_key() {
+        let query = MangaListQuery {
+            title: Some("Yotsuba".into()),
+            included_tags: Some(vec!["a".into(), "b".into()]),
+            ..Default::default()
+        };
+        assert_eq!(
+            sorted(query_pairs(&query)),
+            vec![
+                pair("includedTags[]", "a"),
+                pair("includedTags[]", "b"),
+                pair("title", "Yotsuba"),
+            ]
+        );
+    }
+
+    #[test]
+    fn order_uses_bracketed_keys() {
+        let query = MangaListQuery {
+            limit: Some(10),
+            order: Some(SortOrder {
+                created_at: Some(SortDirection::Desc),
+                updated_at: None,
+            }),
+            ..Default::default()
+        };
+        assert_eq!(
+            sorted(query_pairs(&query)),
+            vec![pair("limit", "10"), pair("order[createdAt]", "desc")]
+        );
+    }
 }
@@ -0,0 +1,174 @@
+use serde::{Deserialize, Serialize};
+use snafu::ensure;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::Instant;
+use url::Url;
+
+use super::image_cache::{self, ImageKey};
+use super::{blocking, decode_image, disk_cache, download, get_image_bytes, get_json};
+use crate::error::{Error, PageErr};
+use crate::{schema, types, Result};
+
+/// MD@Home base URLs are only guaranteed to stay valid for this long.
+const BASE_URL_TTL: Duration = Duration::from_secs(15 * 60);
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Quality {
+    #[default]
+    Data,
+    DataSaver,
+}
+
+impl Quality {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Data => "data",
+            Self::DataSaver => "data-saver",
+        }
+    }
+}
+
+pub async fn get_base_url(chapter_id: &schema::ChapterId) -> Result<Url> {
+    let url = format!("https://api.mangadex.org/at-home/server/{}", chapter_id);
+    let resp = get_json::<_, schema::BaseUrl>(url).await?;
+    Ok(resp.base_url)
+}
+
+pub struct ChapterPages {
+    chapter_id: schema::ChapterId,
+    hash: schema::ChapterHash,
+    filenames: Vec<schema::Filename>,
+    quality: Quality,
+    base_url: Mutex<Option<(Url, Instant)>>,
+}
+
+impl ChapterPages {
+    pub fn new(chapter: &types::Chapter, quality: Quality) -> Self {
+        let attrs = &chapter.attributes;
+        let filenames = match quality {
+            Quality::Data => attrs.data.clone(),
+            Quality::DataSaver => attrs.data_saver.clone(),
+        };
+        Self::from_parts(chapter.id, attrs.hash.clone(), filenames, quality)
+    }
+
+    pub fn from_parts(
+        chapter_id: schema::ChapterId,
+        hash: schema::ChapterHash,
+        filenames: Vec<schema::Filename>,
+        quality: Quality,
+    ) -> Self {
+        Self {
+            chapter_id,
+            hash,
+            filenames,
+            quality,
+            base_url: Mutex::new(None),
+        }
+    }
+
+    pub fn hash(&self) -> &schema::ChapterHash {
+        &self.hash
+    }
+
+    pub fn len(&self) -> usize {
+        self.filenames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filenames.is_empty()
+    }
+
+    pub fn filenames(&self) -> &[schema::Filename] {
+        &self.filenames
+    }
+
+    pub fn filename(&self, index: usize) -> Result<&schema::Filename> {
+        let count = self.len();
+        ensure!(index < count, PageErr { index, count });
+        Ok(&self.filenames[index])
+    }
+
+    fn key(&self, filename: &schema::Filename) -> ImageKey {
+        ImageKey::Page(self.hash.clone(), filename.clone(), self.quality)
+    }
+
+    /// Whether every page is stored locally, i.e. the chapter can be read offline.
+    pub fn is_saved(&self) -> bool {
+        self.filenames.iter().all(|filename| {
+            download::contains(&self.chapter_id, filename)
+                || disk_cache::contains(&self.key(filename))
+        })
+    }
+
+    async fn base_url(&self) -> Result<Url> {
+        let cached = self.base_url.lock().unwrap().clone();
+        if let Some((url, fetched)) = cached {
+            if fetched.elapsed() < BASE_URL_TTL {
+                return Ok(url);
+            }
+        }
+
+        let fetched = Instant::now();
+        let url = get_base_url(&self.chapter_id).await?;
+        *self.base_url.lock().unwrap() = Some((url.clone(), fetched));
+        Ok(url)
+    }
+
+    fn forget_base_url(&self) {
+        *self.base_url.lock().unwrap() = None;
+    }
+
+    /// Downloads the encoded bytes of a page, skipping both caches.
+    /// If the assigned MD@Home node fails to deliver it, a new node is requested and the
+    /// download is attempted once more.
+    async fn fetch_page(&self, filename: &schema::Filename) -> Result<Vec<u8>> {
+        let mode = self.quality.as_str();
+        let base_url = self.base_url().await?;
+        match get_image_bytes(&base_url, mode, &self.hash, filename).await {
+            Err(Error::HttpErr { .. }) => {
+                self.forget_base_url();
+                let base_url = self.base_url().await?;
+                get_image_bytes(&base_url, mode, &self.hash, filename).await
+            }
+            res => res,
+        }
+    }
+
+    /// Downloads the encoded bytes of page `index`, skipping both caches.
+    pub async fn download_page(&self, index: usize) -> Result<Vec<u8>> {
+        let filename = self.filename(index)?;
+        self.fetch_page(filename).await
+    }
+
+    /// Loads page `index` (zero-based) of the chapter.
+    /// Decoded pages are kept in memory, and the encoded files on disk.
+    /// Downloaded chapters are read from the download directory instead.
+    pub async fn load_page(&self, index: usize) -> Result<image::RgbImage> {
+        let filename = self.filename(index)?;
+        let key = self.key(filename);
+        if let Some(img) = image_cache::get(&key) {
+            return Ok(img);
+        }
+
+        let (chapter_id, saved) = (self.chapter_id, filename.clone());
+        let stored = match blocking(move || download::saved_page(&chapter_id, &saved)).await {
+            Some(bytes) => Some(bytes),
+            None => disk_cache::get(&key).await,
+        };
+        let img = match stored.and_then(|bytes| decode_image(&bytes).ok()) {
+            Some(img) => img,
+            None => {
+                let bytes = self.fetch_page(filename).await?;
+                let img = decode_image(&bytes)?;
+                disk_cache::insert(&key, bytes).await;
+                img
+            }
+        };
+
+        image_cache::insert(key, img.clone());
+        Ok(img)
+    }
+}
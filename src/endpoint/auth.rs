@@ -1,31 +1,123 @@
-#![allow(dead_code)]
-
 use chrono::{DateTime, Duration, Utc};
 use once_cell::sync::Lazy;
-use serde::{Deserialize, Serialize};
-use std::fs::File;
-use std::path::PathBuf;
+use reqwest::{Method, StatusCode};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use snafu::{ensure, OptionExt, ResultExt};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tokio::sync::Notify;
+
+use super::{blocking, note_connectivity, CLIENT, RATE_LIMIT};
+use crate::error::{
+    ApiErr, DecryptErr, Error, HttpErr, JsonErr, NotLoggedInErr, PassphraseNeededErr,
+};
+use crate::{schema, Result};
+
+pub mod profile;
+mod vault;
+
+use vault::{Key, Sealed};
+
+fn auth_file_location() -> Result<PathBuf> {
+    Ok(profile::dir()?.join("auth.json"))
+}
+
+/// The secret store entry holding the active profile's key.
+fn keyring_account() -> String {
+    match profile::active().as_str() {
+        // This one predates profiles.
+        profile::DEFAULT => "session-key".into(),
+        name => format!("session-key:{}", name),
+    }
+}
+
+#[derive(Default)]
+struct Session {
+    token: Option<Token>,
+    /// Encrypts the token when it's saved. Without one, the session ends when the app closes.
+    key: Option<Key>,
+    /// A saved session that can't be used until the user enters its passphrase.
+    locked: Option<Sealed>,
+}
+
+impl Session {
+    fn load() -> Self {
+        let bytes = match auth_file_location().map(fs::read) {
+            Ok(Ok(bytes)) => bytes,
+            _ => return Self::default(),
+        };
+
+        if let Ok(sealed) = serde_json::from_slice::<Sealed>(&bytes) {
+            if sealed.needs_passphrase() {
+                return Self {
+                    locked: Some(sealed),
+                    ..Default::default()
+                };
+            }
+            let key = sealed.stored_key(&keyring_account());
+            let token = key
+                .as_ref()
+                .and_then(|key| sealed.open(key).ok())
+                .and_then(|json| serde_json::from_slice(&json).ok());
+            return Self {
+                token,
+                key,
+                locked: None,
+            };
+        }
 
-use super::{CLIENT, RATE_LIMIT};
+        // Tokens used to be saved in plain text. Keep using this one, but get it off the disk.
+        let session = Self {
+            token: serde_json::from_slice(&bytes).ok(),
+            key: Key::keyring(&keyring_account()),
+            locked: None,
+        };
+        let _ = session.save();
+        session
+    }
 
-fn auth_file_location() -> PathBuf {
-    crate::data_dir().join("auth.json")
+    /// The encrypted token as it's written to disk,
+    /// or `None` if there's no token or no key to keep it with.
+    fn sealed(&self) -> Result<Option<Vec<u8>>> {
+        match (&self.token, &self.key) {
+            (Some(token), Some(key)) => {
+                let json = serde_json::to_vec(token).context(JsonErr {
+                    type_name: pretty_type_name::pretty_type_name::<Token>(),
+                })?;
+                let sealed = serde_json::to_vec(&key.seal(&json)).context(JsonErr {
+                    type_name: pretty_type_name::pretty_type_name::<Sealed>(),
+                })?;
+                Ok(Some(sealed))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Encrypts and writes out the token, replacing the file atomically.
+    fn save(&self) -> Result<()> {
+        write_sealed(&auth_file_location()?, self.sealed()?)
+    }
 }
 
-fn open_or_create_token() -> Token {
-    let loc = auth_file_location();
-    if loc.exists() {
-        let f = File::open(loc).expect("Failed to open auth token file");
-        serde_json::from_reader(f).expect("Failed to deserialize auth token")
-    } else {
-        todo!()
+fn write_sealed(path: &Path, sealed: Option<Vec<u8>>) -> Result<()> {
+    match sealed {
+        Some(sealed) => vault::write_private(path, &sealed),
+        None => vault::shred(path),
     }
 }
 
-static TOKEN: Lazy<Token> = Lazy::new(|| todo!());
+/// Empty until `load` has read the saved session.
+static SESSION: Lazy<Mutex<Session>> = Lazy::new(Default::default);
+/// Held while refreshing, so that concurrent requests don't each use up the refresh token.
+static REFRESHING: Lazy<tokio::sync::Mutex<()>> = Lazy::new(Default::default);
+/// Signalled when the session changes hands, so the refresher can recalculate its deadline.
+static CHANGED: Lazy<Notify> = Lazy::new(Notify::new);
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 struct Token {
+    #[serde(default)]
+    username: String,
     session_token: String,
     refresh_token: String,
     session_expiry: DateTime<Utc>,
@@ -34,10 +126,67 @@ struct Token {
 
 #[derive(Debug, Deserialize)]
 struct TokenResponse {
+    token: SessionTokens,
+}
+
+#[derive(Debug, Deserialize)]
+struct SessionTokens {
     session: String,
     refresh: String,
 }
 
+/// Sends a request to one of the `/auth` endpoints,
+/// turning error responses into errors that say what went wrong.
+async fn post_auth<B: Serialize>(url: &str, body: &B) -> Result<SessionTokens> {
+    let permit = RATE_LIMIT.request().await;
+    let resp = CLIENT.post(url).json(body).send().await;
+    note_connectivity(&resp);
+    let resp = resp.context(HttpErr)?;
+    let status = resp.status();
+    let text = resp.text().await.context(HttpErr)?;
+    drop(permit);
+
+    if status.is_success() {
+        let resp: TokenResponse = serde_json::from_str(&text).context(JsonErr {
+            type_name: pretty_type_name::pretty_type_name::<TokenResponse>(),
+        })?;
+        return Ok(resp.token);
+    }
+
+    Err(classify(status, &text))
+}
+
+/// Turns an error response from one of the `/auth` endpoints into an error.
+///
+/// A refused login is 401 when the credentials are wrong, and 403 when they're right
+/// but the account can't be used, e.g. because it's banned or not yet activated.
+fn classify(status: StatusCode, body: &str) -> Error {
+    let (status, detail) = parse_error(status, body);
+    match status {
+        StatusCode::UNAUTHORIZED => Error::BadCredentialsErr {},
+        StatusCode::FORBIDDEN => Error::AccountLockedErr { detail },
+        _ => Error::ApiErr {
+            status: status.as_u16(),
+            detail,
+        },
+    }
+}
+
+/// The status an error response gives for itself, and its most useful message,
+/// falling back to the HTTP status where the body doesn't say.
+fn parse_error(status: StatusCode, body: &str) -> (StatusCode, String) {
+    let error = serde_json::from_str::<schema::ErrorResponse>(body)
+        .ok()
+        .and_then(|resp| resp.errors.into_iter().next());
+    match error {
+        Some(e) => (
+            StatusCode::from_u16(e.status).unwrap_or(status),
+            e.detail.unwrap_or(e.title),
+        ),
+        None => (status, status.to_string()),
+    }
+}
+
 impl Token {
     fn needs_refresh(&self) -> bool {
         self.session_expiry - Utc::now() < Duration::seconds(30)
@@ -47,14 +196,9 @@ impl Token {
         self.refresh_expiry - Utc::now() < Duration::zero()
     }
 
-    async fn refresh(&mut self) -> reqwest::Result<()> {
-        if !self.needs_refresh() {
-            return Ok(());
-        }
-        if self.needs_relogin() {
-            *self = Self::login().await?;
-            return Ok(());
-        }
+    async fn refresh(&mut self) -> Result<()> {
+        // Only the user can fix this, by logging in again.
+        ensure!(!self.needs_relogin(), NotLoggedInErr);
 
         #[derive(Serialize)]
         struct Refresh<'a> {
@@ -64,36 +208,23 @@ impl Token {
             token: &self.refresh_token,
         };
 
-        let permit = RATE_LIMIT.request().await;
         let now = Utc::now();
-        let new_token = CLIENT
-            .post("https://api.mangadex.org/auth/refresh")
-            .json(&refresh)
-            .send()
-            .await?
-            .error_for_status()?
-            .json::<TokenResponse>()
-            .await?;
+        let new_token = post_auth("https://api.mangadex.org/auth/refresh", &refresh)
+            .await
+            .map_err(|e| match e {
+                // A rejected refresh token means the session is over.
+                Error::BadCredentialsErr {} => Error::NotLoggedInErr {},
+                e => e,
+            })?;
 
         self.session_token = new_token.session;
         self.refresh_token = new_token.refresh;
         self.session_expiry = now + Duration::minutes(15);
 
-        drop(permit);
-
         Ok(())
     }
 
-    async fn login() -> reqwest::Result<Self> {
-        fn read(prompt: &str) -> String {
-            println!("{}", prompt);
-            let mut s = String::new();
-            std::io::stdin().read_line(&mut s).unwrap();
-            s
-        }
-        let username = read("username:");
-        let password = read("password:");
-
+    async fn login(username: &str, password: &str) -> Result<Self> {
         #[derive(Serialize)]
         struct Login<'a> {
             username: &'a str,
@@ -101,23 +232,14 @@ impl Token {
         }
         let login = Login {
             username: username.trim(),
-            password: password.trim(),
+            password,
         };
 
-        let permit = RATE_LIMIT.request().await;
         let now = Utc::now();
-        let token = CLIENT
-            .post("https://api.mangadex.org/auth/login")
-            .json(&login)
-            .send()
-            .await?
-            .error_for_status()?
-            .json::<TokenResponse>()
-            .await?;
-
-        drop(permit);
+        let token = post_auth("https://api.mangadex.org/auth/login", &login).await?;
 
         Ok(Self {
+            username: username.trim().to_owned(),
             session_token: token.session,
             refresh_token: token.refresh,
             session_expiry: now + Duration::minutes(15),
@@ -125,3 +247,283 @@ impl Token {
         })
     }
 }
+
+/// Logs in with a username and password, replacing the current session if there is one.
+///
+/// The session is saved encrypted with a key from the system keyring,
+/// or with one derived from `passphrase` if that isn't empty.
+pub async fn login(username: String, password: String, passphrase: String) -> Result<()> {
+    let account = keyring_account();
+    let key = blocking(move || {
+        if passphrase.is_empty() {
+            Key::keyring(&account)
+        } else {
+            Some(Key::from_passphrase(&passphrase))
+        }
+    })
+    .await
+    .context(PassphraseNeededErr)?;
+    let token = Token::login(&username, &password).await?;
+
+    let (path, sealed) = {
+        let mut session = SESSION.lock().unwrap();
+        *session = Session {
+            token: Some(token),
+            key: Some(key),
+            locked: None,
+        };
+        CHANGED.notify_one();
+        (auth_file_location(), session.sealed())
+    };
+    blocking(move || write_sealed(&path?, sealed?)).await
+}
+
+/// Whether there's a saved session waiting for `unlock`.
+pub fn is_locked() -> bool {
+    SESSION.lock().unwrap().locked.is_some()
+}
+
+/// Decrypts the saved session with the passphrase it was saved with.
+pub async fn unlock(passphrase: String) -> Result<()> {
+    let sealed = SESSION.lock().unwrap().locked.clone();
+    let sealed = sealed.context(NotLoggedInErr)?;
+    let (key, json) = blocking(move || {
+        let key = sealed.passphrase_key(&passphrase).context(DecryptErr)?;
+        let json = sealed.open(&key)?;
+        Ok::<_, Error>((key, json))
+    })
+    .await?;
+    let token = serde_json::from_slice(&json).ok().context(DecryptErr)?;
+
+    *SESSION.lock().unwrap() = Session {
+        token: Some(token),
+        key: Some(key),
+        locked: None,
+    };
+    CHANGED.notify_one();
+    Ok(())
+}
+
+/// Logs out and securely removes the saved session, along with its key.
+pub async fn forget() -> Result<()> {
+    let (token, path, account) = {
+        // Hold on to the session so that the profile can't be switched out from under us.
+        let mut session = SESSION.lock().unwrap();
+        let token = std::mem::take(&mut *session).token;
+        (token, auth_file_location(), keyring_account())
+    };
+    CHANGED.notify_one();
+    let removed = blocking(move || {
+        vault::forget_keyring(&account);
+        vault::shred(&path?)
+    })
+    .await;
+
+    // Also end the session on the server, in case the token was copied before it was removed.
+    if let Some(token) = token {
+        let permit = RATE_LIMIT.request().await;
+        let resp = CLIENT
+            .post("https://api.mangadex.org/auth/logout")
+            .bearer_auth(&token.session_token)
+            .send()
+            .await;
+        drop(permit);
+        note_connectivity(&resp);
+    }
+    removed
+}
+
+/// Makes `name` the active profile, ending the current session.
+/// The one saved for the new profile is then read by `load`.
+pub fn switch_profile(name: &str) -> Result<()> {
+    let mut session = SESSION.lock().unwrap();
+    profile::set_active(name)?;
+    *session = Session::default();
+    CHANGED.notify_one();
+    Ok(())
+}
+
+/// Reads the active profile's saved session, which may mean waiting on the keyring.
+pub async fn load() {
+    let profile = profile::active();
+    let loaded = blocking(Session::load).await;
+    let mut session = SESSION.lock().unwrap();
+    // Don't clobber a login, or a session from a profile switched to in the meantime.
+    let untouched = session.token.is_none() && session.locked.is_none();
+    if untouched && profile::active() == profile {
+        *session = loaded;
+        CHANGED.notify_one();
+    }
+}
+
+/// The name of the logged-in user, if any.
+pub fn username() -> Option<String> {
+    let session = SESSION.lock().unwrap();
+    session.token.as_ref().map(|t| t.username.clone())
+}
+
+/// The bearer token for authenticated requests, if anyone is logged in.
+pub fn session_token() -> Option<String> {
+    let session = SESSION.lock().unwrap();
+    session.token.as_ref().map(|t| t.session_token.clone())
+}
+
+/// Refreshes the session and saves the result.
+///
+/// With `stale`, the refresh is forced unless the session has already moved on from
+/// that token; without it, the session is only refreshed if it's about to expire.
+async fn refresh_session(stale: Option<&str>) -> Result<()> {
+    let _guard = REFRESHING.lock().await;
+    let token = SESSION.lock().unwrap().token.clone();
+    let mut token = token.context(NotLoggedInErr)?;
+    let up_to_date = match stale {
+        Some(stale) => token.session_token != stale,
+        None => !token.needs_refresh(),
+    };
+    if up_to_date {
+        return Ok(());
+    }
+
+    let old_refresh = token.refresh_token.clone();
+    token.refresh().await?;
+    let (path, sealed) = {
+        let mut session = SESSION.lock().unwrap();
+        // Don't clobber a login that happened in the meantime.
+        if session.token.as_ref().map(|t| &t.refresh_token) != Some(&old_refresh) {
+            return Ok(());
+        }
+        session.token = Some(token);
+        (auth_file_location(), session.sealed())
+    };
+    blocking(move || write_sealed(&path?, sealed?)).await
+}
+
+/// Keeps the session alive for as long as the app is open,
+/// refreshing it a minute before it would expire.
+pub async fn run() {
+    loop {
+        let session = SESSION
+            .lock()
+            .unwrap()
+            .token
+            .as_ref()
+            .map(|t| (t.session_token.clone(), t.session_expiry));
+        let (session_token, expiry) = match session {
+            Some(session) => session,
+            None => {
+                CHANGED.notified().await;
+                continue;
+            }
+        };
+
+        let wait = (expiry - Duration::minutes(1) - Utc::now())
+            .to_std()
+            .unwrap_or_default();
+        tokio::select! {
+            _ = tokio::time::sleep(wait) => {
+                if refresh_session(Some(&session_token)).await.is_err() {
+                    // Most likely offline; try again in a bit.
+                    tokio::time::sleep(std::time::Duration::from_secs(60)).await;
+                }
+            }
+            _ = CHANGED.notified() => {}
+        }
+    }
+}
+
+/// Sends a request as the logged-in user, returning the response body.
+/// If the session has expired anyway, it's refreshed and the request is sent once more.
+async fn send<B: Serialize>(method: Method, url: &str, body: Option<&B>) -> Result<String> {
+    refresh_session(None).await?;
+    let mut retried = false;
+    loop {
+        let token = session_token().context(NotLoggedInErr)?;
+        let mut req = CLIENT.request(method.clone(), url).bearer_auth(&token);
+        if let Some(body) = body {
+            req = req.json(body);
+        }
+
+        let permit = RATE_LIMIT.request().await;
+        let resp = req.send().await;
+        note_connectivity(&resp);
+        let resp = resp.context(HttpErr)?;
+        let status = resp.status();
+        let text = resp.text().await.context(HttpErr)?;
+        drop(permit);
+
+        if status == StatusCode::UNAUTHORIZED && !retried {
+            refresh_session(Some(&token)).await?;
+            retried = true;
+        } else if status == StatusCode::UNAUTHORIZED {
+            return NotLoggedInErr.fail();
+        } else if !status.is_success() {
+            return ApiErr {
+                status: status.as_u16(),
+                detail: parse_error(status, &text).1,
+            }
+            .fail();
+        } else {
+            return Ok(text);
+        }
+    }
+}
+
+/// Sends a request with a JSON body as the logged-in user, and parses the response.
+pub async fn request_json<B: Serialize, T: DeserializeOwned>(
+    method: Method,
+    url: &str,
+    body: Option<&B>,
+) -> Result<T> {
+    let text = send(method, url, body).await?;
+    serde_json::from_str(&text).context(JsonErr {
+        type_name: pretty_type_name::pretty_type_name::<T>(),
+    })
+}
+
+/// Like `endpoint::get_json`, but as the logged-in user.
+/// These responses are specific to the user, so they're never cached.
+pub async fn get_json<T: DeserializeOwned>(url: &str) -> Result<T> {
+    request_json::<(), T>(Method::GET, url, None).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn body(status: u16, detail: &str) -> String {
+        format!(
+            r#"{{"result":"error","errors":[{{"id":"b3f3b43e-3c73-4a0b-9d23-8e8e8a1d6b7c","status":{},"title":"Error","detail":"{}"}}]}}"#,
+            status, detail
+        )
+    }
+
+    #[test]
+    fn wrong_credentials() {
+        let e = classify(
+            StatusCode::UNAUTHORIZED,
+            &body(401, "User / Password does not match"),
+        );
+        assert!(matches!(e, Error::BadCredentialsErr {}));
+    }
+
+    #[test]
+    fn locked_accounts_go_by_status_not_wording() {
+        let e = classify(StatusCode::FORBIDDEN, &body(403, "Account not activated"));
+        assert!(
+            matches!(e, Error::AccountLockedErr { detail } if detail == "Account not activated")
+        );
+
+        // A message that happens to mention a ban isn't enough on its own.
+        let e = classify(
+            StatusCode::BAD_REQUEST,
+            &body(400, "Username banana is taken"),
+        );
+        assert!(matches!(e, Error::ApiErr { status: 400, .. }));
+    }
+
+    #[test]
+    fn falls_back_to_the_http_status() {
+        let e = classify(StatusCode::FORBIDDEN, "<html>Forbidden</html>");
+        assert!(matches!(e, Error::AccountLockedErr { detail } if detail == "403 Forbidden"));
+    }
+}
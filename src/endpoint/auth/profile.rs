@@ -0,0 +1,115 @@
+//! Named profiles, each keeping its session, library and settings in its own directory.
+
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use snafu::{ensure, ResultExt};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use crate::error::{IoErr, NoProfileErr, ProfileExistsErr, ProfileNameErr};
+use crate::{store, Result};
+
+pub const DEFAULT: &str = "default";
+
+fn list_location() -> PathBuf {
+    crate::data_dir().join("profiles.json")
+}
+
+fn dir_of(name: &str) -> PathBuf {
+    crate::data_dir().join("profiles").join(name)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Profiles {
+    active: String,
+    names: Vec<String>,
+}
+
+impl Default for Profiles {
+    fn default() -> Self {
+        Self {
+            active: DEFAULT.into(),
+            names: vec![DEFAULT.into()],
+        }
+    }
+}
+
+impl Profiles {
+    fn load() -> Self {
+        let mut profiles: Self = store::read_json(&list_location()).unwrap_or_default();
+        if !profiles.names.contains(&profiles.active) {
+            profiles.active = profiles.names.first().cloned().unwrap_or_default();
+        }
+        if profiles.names.is_empty() {
+            profiles = Self::default();
+        }
+
+        // Before profiles, the session was saved at the top level.
+        let old_auth = crate::data_dir().join("auth.json");
+        let new_auth = dir_of(DEFAULT).join("auth.json");
+        if old_auth.exists() && !new_auth.exists() && fs::create_dir_all(dir_of(DEFAULT)).is_ok() {
+            let _ = fs::rename(old_auth, new_auth);
+        }
+        profiles
+    }
+
+    fn save(&self) -> Result<()> {
+        store::write_json(&list_location(), self)
+    }
+}
+
+static PROFILES: Lazy<Mutex<Profiles>> = Lazy::new(|| Mutex::new(Profiles::load()));
+
+/// Names end up as directory names, so they're kept to characters that are safe everywhere.
+fn is_valid(name: &str) -> bool {
+    let len = name.chars().count();
+    (1..=32).contains(&len)
+        && name.trim() == name
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == ' ' || c == '-' || c == '_')
+}
+
+/// The name of the active profile.
+pub fn active() -> String {
+    PROFILES.lock().unwrap().active.clone()
+}
+
+/// The names of all profiles, in the order they were created.
+pub fn list() -> Vec<String> {
+    PROFILES.lock().unwrap().names.clone()
+}
+
+/// The active profile's directory, for anything that belongs to that profile alone.
+pub fn dir() -> Result<PathBuf> {
+    let dir = dir_of(&active());
+    fs::create_dir_all(&dir).context(IoErr { path: &dir })?;
+    Ok(dir)
+}
+
+pub fn create(name: &str) -> Result<()> {
+    ensure!(is_valid(name), ProfileNameErr { name });
+    let mut profiles = PROFILES.lock().unwrap();
+    // Some filesystems ignore case, so names that differ only in case would share a directory.
+    let taken = profiles
+        .names
+        .iter()
+        .any(|n| n.to_lowercase() == name.to_lowercase());
+    ensure!(!taken, ProfileExistsErr { name });
+
+    fs::create_dir_all(dir_of(name)).context(IoErr { path: dir_of(name) })?;
+    profiles.names.push(name.to_owned());
+    profiles.save()
+}
+
+/// Only meant for `auth::switch_profile`, which also swaps out the session.
+pub(super) fn set_active(name: &str) -> Result<()> {
+    let mut profiles = PROFILES.lock().unwrap();
+    ensure!(
+        profiles.names.iter().any(|n| n == name),
+        NoProfileErr { name }
+    );
+    profiles.active = name.to_owned();
+    profiles.save()
+}
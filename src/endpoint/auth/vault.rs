@@ -0,0 +1,201 @@
+//! Keeps the saved session encrypted at rest.
+//!
+//! The key is either random and kept in the OS secret store,
+//! or derived from a passphrase that the user has to enter on each start.
+
+use chacha20poly1305::aead::{Aead, NewAead};
+use chacha20poly1305::Key as CipherKey;
+use chacha20poly1305::{ChaCha20Poly1305, Nonce};
+use hmac::Hmac;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use snafu::{OptionExt, ResultExt};
+use std::convert::TryFrom;
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::Path;
+
+use crate::error::{DecryptErr, IoErr};
+use crate::Result;
+
+const ROUNDS: u32 = 200_000;
+
+fn random_bytes(buf: &mut [u8]) {
+    getrandom::getrandom(buf).expect("No source of randomness available");
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn keyring(account: &str) -> keyring::Keyring<'_> {
+    keyring::Keyring::new("md-rs", account)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum KeySource {
+    Keyring,
+    Passphrase { salt: String, rounds: u32 },
+}
+
+pub(super) struct Key {
+    source: KeySource,
+    bytes: [u8; 32],
+}
+
+impl Key {
+    /// The key kept in the OS secret store under `account`, created if there isn't one yet.
+    /// `None` if there's no secret store to keep it in.
+    pub(super) fn keyring(account: &str) -> Option<Self> {
+        if let Some(key) = Self::from_keyring(account) {
+            return Some(key);
+        }
+        let mut bytes = [0; 32];
+        random_bytes(&mut bytes);
+        keyring(account).set_password(&to_hex(&bytes)).ok()?;
+        Some(Self {
+            source: KeySource::Keyring,
+            bytes,
+        })
+    }
+
+    fn from_keyring(account: &str) -> Option<Self> {
+        let bytes = from_hex(&keyring(account).get_password().ok()?)?;
+        Some(Self {
+            source: KeySource::Keyring,
+            bytes: <[u8; 32]>::try_from(&bytes[..]).ok()?,
+        })
+    }
+
+    /// A key derived from `passphrase` with a fresh salt.
+    pub(super) fn from_passphrase(passphrase: &str) -> Self {
+        let mut salt = [0; 16];
+        random_bytes(&mut salt);
+        Self::derive(passphrase, &salt, ROUNDS)
+    }
+
+    fn derive(passphrase: &str, salt: &[u8], rounds: u32) -> Self {
+        let mut bytes = [0; 32];
+        pbkdf2::pbkdf2::<Hmac<Sha256>>(passphrase.as_bytes(), salt, rounds, &mut bytes);
+        Self {
+            source: KeySource::Passphrase {
+                salt: to_hex(salt),
+                rounds,
+            },
+            bytes,
+        }
+    }
+
+    pub(super) fn seal(&self, plaintext: &[u8]) -> Sealed {
+        let mut nonce = [0; 12];
+        random_bytes(&mut nonce);
+        let cipher = ChaCha20Poly1305::new(&CipherKey::from(self.bytes));
+        let ciphertext = cipher
+            .encrypt(&Nonce::from(nonce), plaintext)
+            .expect("Encryption can't fail for inputs this small");
+        Sealed {
+            key: self.source.clone(),
+            nonce: to_hex(&nonce),
+            ciphertext: to_hex(&ciphertext),
+        }
+    }
+}
+
+/// What actually gets written to disk.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(super) struct Sealed {
+    key: KeySource,
+    nonce: String,
+    ciphertext: String,
+}
+
+impl Sealed {
+    pub(super) fn needs_passphrase(&self) -> bool {
+        matches!(self.key, KeySource::Passphrase { .. })
+    }
+
+    /// The key for this file, if it's the one in the secret store.
+    pub(super) fn stored_key(&self, account: &str) -> Option<Key> {
+        if self.needs_passphrase() {
+            return None;
+        }
+        Key::from_keyring(account)
+    }
+
+    pub(super) fn passphrase_key(&self, passphrase: &str) -> Option<Key> {
+        match &self.key {
+            KeySource::Passphrase { salt, rounds } => {
+                Some(Key::derive(passphrase, &from_hex(salt)?, *rounds))
+            }
+            KeySource::Keyring => None,
+        }
+    }
+
+    /// Decrypts the contents, which fails if the key (or passphrase) is wrong.
+    pub(super) fn open(&self, key: &Key) -> Result<Vec<u8>> {
+        let nonce = from_hex(&self.nonce).and_then(|n| <[u8; 12]>::try_from(&n[..]).ok());
+        let nonce = nonce.context(DecryptErr)?;
+        let ciphertext = from_hex(&self.ciphertext).context(DecryptErr)?;
+        let cipher = ChaCha20Poly1305::new(&CipherKey::from(key.bytes));
+        cipher
+            .decrypt(&Nonce::from(nonce), &ciphertext[..])
+            .ok()
+            .context(DecryptErr)
+    }
+}
+
+/// Removes the key from the secret store, if it's there.
+pub(super) fn forget_keyring(account: &str) {
+    let _ = keyring(account).delete_password();
+}
+
+/// Atomically replaces the file at `path` with one only the current user can read.
+pub(super) fn write_private(path: &Path, bytes: &[u8]) -> Result<()> {
+    let tmp = path.with_extension("tmp");
+    // A leftover from a crash could have looser permissions, which `mode` won't fix.
+    let _ = fs::remove_file(&tmp);
+
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(&tmp).context(IoErr { path: &tmp })?;
+    file.write_all(bytes).context(IoErr { path: &tmp })?;
+    file.sync_all().context(IoErr { path: &tmp })?;
+    drop(file);
+    fs::rename(&tmp, path).context(IoErr { path })
+}
+
+/// Overwrites the file before removing it, so its contents don't linger on disk.
+/// Filesystems that copy on write may keep the old blocks anyway,
+/// which is why the file is encrypted in the first place.
+pub(super) fn shred(path: &Path) -> Result<()> {
+    let len = match fs::metadata(path) {
+        Ok(meta) => meta.len(),
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e).context(IoErr { path }),
+    };
+    let mut file = OpenOptions::new()
+        .write(true)
+        .open(path)
+        .context(IoErr { path })?;
+    file.write_all(&vec![0; len as usize])
+        .context(IoErr { path })?;
+    file.sync_all().context(IoErr { path })?;
+    drop(file);
+    fs::remove_file(path).context(IoErr { path })
+}
@@ -0,0 +1,249 @@
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use snafu::ResultExt;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use super::image_cache::ImageKey;
+use crate::error::IoErr;
+use crate::Result;
+
+/// How often changes to the index are written out.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(30);
+
+fn cache_dir() -> PathBuf {
+    crate::data_dir().join("image_cache")
+}
+
+fn index_location() -> PathBuf {
+    cache_dir().join("index.json")
+}
+
+fn object_location(digest: &str) -> PathBuf {
+    cache_dir().join("objects").join(&digest[..2]).join(digest)
+}
+
+fn index_key(key: &ImageKey) -> String {
+    match key {
+        ImageKey::Page(hash, filename, quality) => {
+            format!("page/{}/{}/{}", hash, quality.as_str(), filename)
+        }
+        ImageKey::Cover(manga_id, filename, quality) => {
+            format!("cover/{}/{}{}", manga_id, filename, quality)
+        }
+    }
+}
+
+pub(super) fn digest(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct IndexEntry {
+    digest: String,
+    size: u64,
+    last_used: SystemTime,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Index {
+    entries: HashMap<String, IndexEntry>,
+    /// How many entries refer to each object, since identical images under different keys
+    /// share one object on disk.
+    #[serde(skip)]
+    refs: HashMap<String, usize>,
+    /// The size of all objects on disk.
+    #[serde(skip)]
+    total_size: u64,
+    #[serde(skip)]
+    size_cap: u64,
+    /// Whether there are changes that haven't been written out yet.
+    #[serde(skip)]
+    dirty: bool,
+}
+
+impl Index {
+    fn load() -> Self {
+        let mut index: Self = File::open(index_location())
+            .ok()
+            .and_then(|f| serde_json::from_reader(f).ok())
+            .unwrap_or_default();
+        for entry in index.entries.values() {
+            let refs = index.refs.entry(entry.digest.clone()).or_insert(0);
+            if *refs == 0 {
+                index.total_size += entry.size;
+            }
+            *refs += 1;
+        }
+        index.size_cap = crate::settings::get().disk_cache_bytes();
+        index
+    }
+
+    fn save(&self) -> io::Result<()> {
+        fs::create_dir_all(cache_dir())?;
+        let tmp = index_location().with_extension("json.tmp");
+        serde_json::to_writer(File::create(&tmp)?, self)?;
+        fs::rename(tmp, index_location())
+    }
+
+    fn add(&mut self, key: String, entry: IndexEntry) {
+        self.remove(&key);
+        let refs = self.refs.entry(entry.digest.clone()).or_insert(0);
+        if *refs == 0 {
+            self.total_size += entry.size;
+        }
+        *refs += 1;
+        self.entries.insert(key, entry);
+        self.dirty = true;
+    }
+
+    /// Deletes `key`'s entry, and its object too unless another entry still refers to it.
+    fn remove(&mut self, key: &str) {
+        let entry = match self.entries.remove(key) {
+            Some(entry) => entry,
+            None => return,
+        };
+        self.dirty = true;
+        let refs = self.refs.entry(entry.digest.clone()).or_insert(1);
+        *refs -= 1;
+        if *refs == 0 {
+            self.refs.remove(&entry.digest);
+            self.total_size -= entry.size;
+            let _ = fs::remove_file(object_location(&entry.digest));
+        }
+    }
+
+    /// Evicts the least recently used entries until the cache fits under the cap.
+    fn prune(&mut self) {
+        if self.total_size <= self.size_cap {
+            return;
+        }
+        let mut by_age: Vec<(SystemTime, String)> = self
+            .entries
+            .iter()
+            .map(|(key, entry)| (entry.last_used, key.clone()))
+            .collect();
+        by_age.sort();
+        for (_, key) in by_age {
+            if self.total_size <= self.size_cap {
+                break;
+            }
+            self.remove(&key);
+        }
+    }
+}
+
+static INDEX: Lazy<Mutex<Index>> = Lazy::new(|| Mutex::new(Index::load()));
+
+fn get_blocking(key: &ImageKey) -> Option<Vec<u8>> {
+    let key = index_key(key);
+    let mut index = INDEX.lock().unwrap();
+    let entry = index.entries.get_mut(&key)?;
+
+    match fs::read(object_location(&entry.digest)) {
+        Ok(bytes) if bytes.len() as u64 == entry.size && digest(&bytes) == entry.digest => {
+            entry.last_used = SystemTime::now();
+            index.dirty = true;
+            Some(bytes)
+        }
+        _ => {
+            index.remove(&key);
+            None
+        }
+    }
+}
+
+/// Returns the encoded image stored for `key`, if there is one and it is intact.
+/// Corrupt or missing objects are dropped from the index.
+pub async fn get(key: &ImageKey) -> Option<Vec<u8>> {
+    let key = key.clone();
+    tokio::task::spawn_blocking(move || get_blocking(&key))
+        .await
+        .ok()
+        .flatten()
+}
+
+/// Whether `key` is in the index, without reading (or verifying) the object itself.
+pub fn contains(key: &ImageKey) -> bool {
+    INDEX.lock().unwrap().entries.contains_key(&index_key(key))
+}
+
+fn insert_blocking(key: &ImageKey, bytes: &[u8]) {
+    let digest = digest(bytes);
+    let location = object_location(&digest);
+
+    let mut index = INDEX.lock().unwrap();
+    if !location.exists() {
+        let tmp = location.with_extension("tmp");
+        let written = fs::create_dir_all(location.parent().unwrap())
+            .and_then(|_| fs::write(&tmp, bytes))
+            .and_then(|_| fs::rename(&tmp, &location));
+        if written.is_err() {
+            let _ = fs::remove_file(tmp);
+            return;
+        }
+    }
+
+    let entry = IndexEntry {
+        digest,
+        size: bytes.len() as u64,
+        last_used: SystemTime::now(),
+    };
+    index.add(index_key(key), entry);
+    index.prune();
+}
+
+pub async fn insert(key: &ImageKey, bytes: Vec<u8>) {
+    let key = key.clone();
+    let _ = tokio::task::spawn_blocking(move || insert_blocking(&key, &bytes)).await;
+}
+
+/// Sets the size cap in bytes, pruning immediately if the cache is now over it.
+pub fn set_size_cap(bytes: u64) {
+    let mut index = INDEX.lock().unwrap();
+    index.size_cap = bytes;
+    index.prune();
+}
+
+/// Writes out the index, if anything changed since it was last written.
+pub fn flush() {
+    let mut index = INDEX.lock().unwrap();
+    if index.dirty && index.save().is_ok() {
+        index.dirty = false;
+    }
+}
+
+/// Writes out the index every so often, rather than on every change, for as long as the app
+/// is open.
+pub async fn run() {
+    loop {
+        tokio::time::sleep(FLUSH_INTERVAL).await;
+        let _ = tokio::task::spawn_blocking(flush).await;
+    }
+}
+
+/// Deletes every cached image from disk.
+pub async fn clear() -> Result<()> {
+    let res = tokio::task::spawn_blocking(|| {
+        let mut index = INDEX.lock().unwrap();
+        index.entries.clear();
+        index.refs.clear();
+        index.total_size = 0;
+        index.dirty = false;
+        match fs::remove_dir_all(cache_dir()) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    })
+    .await
+    .unwrap_or_else(|e| Err(io::Error::other(e)));
+    res.context(IoErr { path: cache_dir() })
+}
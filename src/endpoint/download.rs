@@ -0,0 +1,407 @@
+use once_cell::sync::Lazy;
+use rusqlite::params;
+use serde::{Deserialize, Serialize};
+use snafu::OptionExt;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use tokio::sync::Notify;
+
+use super::disk_cache::digest;
+use super::{blocking, decode_image, ChapterPages, Quality};
+use crate::error::NotDownloadedErr;
+use crate::{db, schema, types, Result};
+
+fn downloads_dir() -> PathBuf {
+    crate::settings::get().downloads_dir()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum JobState {
+    Queued,
+    Running,
+    Paused,
+    Done,
+    Failed(String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Job {
+    pub manga_id: schema::MangaId,
+    pub chapter_id: schema::ChapterId,
+    pub hash: schema::ChapterHash,
+    pub filenames: Vec<schema::Filename>,
+    pub quality: Quality,
+    /// SHA-256 of each page that has been written to disk so far.
+    digests: Vec<Option<String>>,
+    pub state: JobState,
+    /// The download location when the job was queued. Changing the location only affects
+    /// chapters queued after that, so nothing has to be moved while it might be in use.
+    #[serde(default = "downloads_dir")]
+    root: PathBuf,
+}
+
+impl Job {
+    pub fn downloaded(&self) -> usize {
+        self.digests.iter().filter(|d| d.is_some()).count()
+    }
+
+    pub fn page_count(&self) -> usize {
+        self.filenames.len()
+    }
+
+    fn dir(&self) -> PathBuf {
+        self.root.join(self.chapter_id.to_string())
+    }
+
+    fn page_location(&self, index: usize) -> PathBuf {
+        self.dir().join(&self.filenames[index].0)
+    }
+
+    /// Whether page `index` is on disk and matches what was downloaded.
+    fn has_page(&self, index: usize) -> bool {
+        match &self.digests[index] {
+            Some(expected) => fs::read(self.page_location(index))
+                .map(|bytes| digest(&bytes) == *expected)
+                .unwrap_or(false),
+            None => false,
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Queue {
+    jobs: Vec<Job>,
+}
+
+impl Queue {
+    fn load() -> Self {
+        // Before the database, the queue was kept in a JSON file.
+        db::import_json(&downloads_dir().join("queue.json"), |old: Self| old.save());
+
+        let jobs = db::with_db(|conn| {
+            let mut stmt = conn.prepare_cached("SELECT job FROM downloads ORDER BY position")?;
+            let rows = stmt.query_map(params![], |row| row.get::<_, String>(0))?;
+            let mut jobs: Vec<Job> = Vec::new();
+            for job in rows {
+                jobs.extend(db::from_json(&job?));
+            }
+            Ok(jobs)
+        });
+        let mut queue = Self {
+            jobs: jobs.unwrap_or_default(),
+        };
+        // Whatever was running when the app last exited picks up where it left off.
+        for job in &mut queue.jobs {
+            if job.state == JobState::Running {
+                job.state = JobState::Queued;
+            }
+        }
+        queue
+    }
+
+    fn save(&self) -> Result<()> {
+        db::with_db(|conn| {
+            let tx = conn.transaction()?;
+            tx.execute("DELETE FROM downloads", params![])?;
+            {
+                let mut put = tx.prepare_cached(
+                    "INSERT INTO downloads (chapter_id, manga_id, position, job)
+                     VALUES (?1, ?2, ?3, ?4)",
+                )?;
+                for (position, job) in self.jobs.iter().enumerate() {
+                    let position = position as i64;
+                    put.execute(params![
+                        job.chapter_id,
+                        job.manga_id,
+                        position,
+                        db::to_json(job)
+                    ])?;
+                }
+            }
+            tx.commit()
+        })
+    }
+
+    /// Saves just the one job, which is all that changes as it downloads.
+    fn save_job(&self, chapter_id: &schema::ChapterId) -> Result<()> {
+        let job = match self.get(chapter_id) {
+            Some(job) => job,
+            None => return Ok(()),
+        };
+        db::with_db(|conn| {
+            // New jobs go to the back of the queue; existing ones keep their place.
+            conn.prepare_cached(
+                "INSERT INTO downloads (chapter_id, manga_id, position, job)
+                 VALUES (?1, ?2, (SELECT IFNULL(MAX(position), -1) + 1 FROM downloads), ?3)
+                 ON CONFLICT (chapter_id) DO UPDATE SET job = excluded.job",
+            )?
+            .execute(params![job.chapter_id, job.manga_id, db::to_json(job)])?;
+            Ok(())
+        })
+    }
+
+    fn get(&self, chapter_id: &schema::ChapterId) -> Option<&Job> {
+        self.jobs.iter().find(|job| job.chapter_id == *chapter_id)
+    }
+
+    fn get_mut(&mut self, chapter_id: &schema::ChapterId) -> Option<&mut Job> {
+        self.jobs
+            .iter_mut()
+            .find(|job| job.chapter_id == *chapter_id)
+    }
+}
+
+static QUEUE: Lazy<Mutex<Queue>> = Lazy::new(|| Mutex::new(Queue::load()));
+static WAKE: Lazy<Notify> = Lazy::new(Notify::new);
+
+fn update(chapter_id: &schema::ChapterId, f: impl FnOnce(&mut Job)) {
+    let mut queue = QUEUE.lock().unwrap();
+    if let Some(job) = queue.get_mut(chapter_id) {
+        f(job);
+        let _ = queue.save_job(chapter_id);
+    }
+}
+
+/// Adds a chapter to the download queue.
+/// A chapter that is already queued is left alone, unless it was paused or failed,
+/// in which case it is resumed.
+pub fn enqueue(manga_id: schema::MangaId, chapter: &types::Chapter, quality: Quality) {
+    let mut queue = QUEUE.lock().unwrap();
+    match queue.get_mut(&chapter.id) {
+        Some(job) => {
+            if matches!(job.state, JobState::Paused | JobState::Failed(_)) {
+                job.state = JobState::Queued;
+            }
+        }
+        None => {
+            let pages = ChapterPages::new(chapter, quality);
+            queue.jobs.push(Job {
+                manga_id,
+                chapter_id: chapter.id,
+                hash: pages.hash().clone(),
+                filenames: pages.filenames().to_vec(),
+                quality,
+                digests: vec![None; pages.len()],
+                state: JobState::Queued,
+                root: downloads_dir(),
+            });
+        }
+    }
+    let _ = queue.save_job(&chapter.id);
+    WAKE.notify_one();
+}
+
+/// Stops a chapter's download after the page currently in flight.
+pub fn pause(chapter_id: &schema::ChapterId) {
+    update(chapter_id, |job| {
+        if matches!(job.state, JobState::Queued | JobState::Running) {
+            job.state = JobState::Paused;
+        }
+    });
+}
+
+/// Puts a paused or failed download back in the queue.
+/// Pages that were already downloaded are kept.
+pub fn resume(chapter_id: &schema::ChapterId) {
+    update(chapter_id, |job| {
+        if matches!(job.state, JobState::Paused | JobState::Failed(_)) {
+            job.state = JobState::Queued;
+        }
+    });
+    WAKE.notify_one();
+}
+
+/// Removes a chapter from the queue and deletes whatever was downloaded of it.
+pub fn cancel(chapter_id: &schema::ChapterId) {
+    let mut queue = QUEUE.lock().unwrap();
+    let dir = queue.get(chapter_id).map(Job::dir);
+    queue.jobs.retain(|job| job.chapter_id != *chapter_id);
+    let _ = db::with_db(|conn| {
+        conn.execute(
+            "DELETE FROM downloads WHERE chapter_id = ?1",
+            params![chapter_id],
+        )
+    });
+    if let Some(dir) = dir {
+        let _ = fs::remove_dir_all(dir);
+    }
+}
+
+pub fn status(chapter_id: &schema::ChapterId) -> Option<Job> {
+    QUEUE.lock().unwrap().get(chapter_id).cloned()
+}
+
+/// The quality to fetch a chapter's pages at. A downloaded chapter keeps the quality it was
+/// downloaded at, since its pages are only found on disk by their filenames at that quality.
+pub fn quality_for(chapter_id: &schema::ChapterId) -> Quality {
+    let downloaded = QUEUE.lock().unwrap().get(chapter_id).map(|job| job.quality);
+    downloaded.unwrap_or_else(|| crate::settings::get().quality())
+}
+
+/// Whether `filename` has been downloaded, without reading (or verifying) the file itself.
+pub fn contains(chapter_id: &schema::ChapterId, filename: &schema::Filename) -> bool {
+    let queue = QUEUE.lock().unwrap();
+    queue.get(chapter_id).is_some_and(|job| {
+        let index = job.filenames.iter().position(|f| f == filename);
+        index.is_some_and(|i| job.digests[i].is_some())
+    })
+}
+
+/// Returns a downloaded page, if there is one and it is intact.
+pub fn saved_page(chapter_id: &schema::ChapterId, filename: &schema::Filename) -> Option<Vec<u8>> {
+    let (expected, location) = {
+        let queue = QUEUE.lock().unwrap();
+        let job = queue.get(chapter_id)?;
+        let index = job.filenames.iter().position(|f| f == filename)?;
+        (job.digests[index].clone()?, job.page_location(index))
+    };
+    let bytes = fs::read(location).ok()?;
+    if digest(&bytes) == expected {
+        Some(bytes)
+    } else {
+        None
+    }
+}
+
+/// Reads every page of a completely downloaded chapter, in order.
+pub fn read_chapter(chapter_id: &schema::ChapterId) -> Result<Vec<(schema::Filename, Vec<u8>)>> {
+    let chapter_id = *chapter_id;
+    let filenames = match status(&chapter_id) {
+        Some(job) if job.state == JobState::Done => job.filenames,
+        _ => return NotDownloadedErr { chapter_id }.fail(),
+    };
+    filenames
+        .into_iter()
+        .map(|filename| {
+            let bytes =
+                saved_page(&chapter_id, &filename).context(NotDownloadedErr { chapter_id })?;
+            Ok((filename, bytes))
+        })
+        .collect()
+}
+
+/// Writes a page to disk, unless its job was cancelled in the meantime.
+fn store_page(chapter_id: &schema::ChapterId, index: usize, bytes: &[u8]) -> bool {
+    // The queue is only locked around the bookkeeping, not the write itself.
+    let location = match QUEUE.lock().unwrap().get(chapter_id) {
+        Some(job) => job.page_location(index),
+        None => return false,
+    };
+    let tmp = location.with_extension("tmp");
+    let written = fs::create_dir_all(location.parent().unwrap())
+        .and_then(|_| fs::write(&tmp, bytes))
+        .and_then(|_| fs::rename(&tmp, &location));
+
+    let mut queue = QUEUE.lock().unwrap();
+    let job = match queue.get_mut(chapter_id) {
+        Some(job) => job,
+        None => {
+            let _ = fs::remove_file(if written.is_ok() { location } else { tmp });
+            return false;
+        }
+    };
+    let stored = match written {
+        Ok(()) => {
+            job.digests[index] = Some(digest(bytes));
+            true
+        }
+        Err(e) => {
+            let _ = fs::remove_file(tmp);
+            job.state = JobState::Failed(e.to_string());
+            false
+        }
+    };
+    let _ = queue.save_job(chapter_id);
+    stored
+}
+
+fn next_job() -> Option<Job> {
+    let mut queue = QUEUE.lock().unwrap();
+    let job = queue
+        .jobs
+        .iter_mut()
+        .find(|job| job.state == JobState::Queued)?;
+    job.state = JobState::Running;
+    let job = job.clone();
+    let _ = queue.save_job(&job.chapter_id);
+    Some(job)
+}
+
+fn is_running(chapter_id: &schema::ChapterId) -> bool {
+    let queue = QUEUE.lock().unwrap();
+    queue
+        .get(chapter_id)
+        .is_some_and(|job| job.state == JobState::Running)
+}
+
+async fn download(job: Job) {
+    let pages = ChapterPages::from_parts(
+        job.chapter_id,
+        job.hash.clone(),
+        job.filenames.clone(),
+        job.quality,
+    );
+
+    // Anything left over from an earlier run is kept, as long as it's intact.
+    let (job, kept) = blocking(move || {
+        let kept: Vec<bool> = (0..job.page_count()).map(|i| job.has_page(i)).collect();
+        (job, kept)
+    })
+    .await;
+
+    for (index, kept) in kept.into_iter().enumerate() {
+        if !is_running(&job.chapter_id) {
+            return;
+        }
+        if kept {
+            continue;
+        }
+
+        // A page only counts as downloaded if it actually decodes.
+        let res = pages
+            .download_page(index)
+            .await
+            .and_then(|bytes| decode_image(&bytes).map(|_| bytes));
+        match res {
+            Ok(bytes) => {
+                let chapter_id = job.chapter_id;
+                if !blocking(move || store_page(&chapter_id, index, &bytes)).await {
+                    return;
+                }
+            }
+            Err(e) => {
+                let (chapter_id, message) = (job.chapter_id, e.to_string());
+                blocking(move || {
+                    update(&chapter_id, |job| {
+                        if job.state == JobState::Running {
+                            job.state = JobState::Failed(message);
+                        }
+                    })
+                })
+                .await;
+                return;
+            }
+        }
+    }
+
+    let chapter_id = job.chapter_id;
+    blocking(move || {
+        update(&chapter_id, |job| {
+            if job.state == JobState::Running {
+                job.state = JobState::Done;
+            }
+        })
+    })
+    .await;
+}
+
+/// Works through the download queue one chapter at a time, forever.
+pub async fn run() {
+    loop {
+        match blocking(next_job).await {
+            Some(job) => download(job).await,
+            None => WAKE.notified().await,
+        }
+    }
+}
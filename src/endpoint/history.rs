@@ -0,0 +1,152 @@
+//! A log of the chapters opened, and the page each was left at.
+//!
+//! Kept in the active profile's directory, and never sent anywhere.
+
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+use super::auth::profile;
+use crate::schema::{ChapterId, Language, MangaId};
+use crate::store::{self, PerProfile};
+use crate::Result;
+
+/// Entries beyond this many are dropped, oldest first.
+const KEEP: usize = 1000;
+
+fn history_location() -> Result<PathBuf> {
+    Ok(profile::dir()?.join("history.json"))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    pub id: u64,
+    pub manga_id: MangaId,
+    pub manga_title: String,
+    pub chapter_id: ChapterId,
+    pub chapter_label: String,
+    /// Needed to find the chapter's siblings again when resuming.
+    pub language: Language,
+    pub page: usize,
+    /// When the chapter was last looked at.
+    pub read_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct History {
+    next_id: u64,
+    /// Oldest first.
+    entries: Vec<Entry>,
+    /// Whether there are page turns that haven't been written yet.
+    #[serde(skip)]
+    dirty: bool,
+}
+
+impl History {
+    fn load() -> Self {
+        history_location()
+            .ok()
+            .and_then(|path| store::read_json(&path))
+            .unwrap_or_default()
+    }
+
+    fn save(&mut self) -> Result<()> {
+        store::write_json(&history_location()?, self)?;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+static HISTORY: Lazy<PerProfile<History>> = Lazy::new(|| PerProfile::new(History::load));
+
+fn with_history<T>(f: impl FnOnce(&mut History) -> T) -> T {
+    HISTORY.with(f)
+}
+
+/// Every entry, newest first.
+pub fn entries() -> Vec<Entry> {
+    with_history(|history| history.entries.iter().rev().cloned().collect())
+}
+
+pub fn get(id: u64) -> Option<Entry> {
+    with_history(|history| history.entries.iter().find(|e| e.id == id).cloned())
+}
+
+/// Notes that a chapter was opened, returning the new entry's id.
+/// Opening the same chapter again right away just brings its entry up to date.
+pub fn open(
+    manga_id: MangaId,
+    manga_title: String,
+    chapter_id: ChapterId,
+    chapter_label: String,
+    language: Language,
+    page: usize,
+) -> u64 {
+    with_history(|history| {
+        let now = Utc::now();
+        if let Some(last) = history.entries.last_mut() {
+            if last.chapter_id == chapter_id {
+                last.page = page;
+                last.read_at = now;
+                let id = last.id;
+                let _ = history.save();
+                return id;
+            }
+        }
+        let id = history.next_id;
+        history.next_id += 1;
+        history.entries.push(Entry {
+            id,
+            manga_id,
+            manga_title,
+            chapter_id,
+            chapter_label,
+            language,
+            page,
+            read_at: now,
+        });
+        if history.entries.len() > KEEP {
+            let excess = history.entries.len() - KEEP;
+            history.entries.drain(..excess);
+        }
+        // Like page positions, history isn't worth interrupting anyone over.
+        let _ = history.save();
+        id
+    })
+}
+
+/// Moves an entry to the page the reader is now on. This isn't written until `flush`.
+pub fn set_page(id: u64, page: usize) {
+    with_history(|history| {
+        if let Some(entry) = history.entries.iter_mut().find(|e| e.id == id) {
+            entry.page = page;
+            entry.read_at = Utc::now();
+            history.dirty = true;
+        }
+    })
+}
+
+/// Writes any page turns made since the history was last saved.
+pub fn flush() {
+    with_history(|history| {
+        if history.dirty {
+            let _ = history.save();
+        }
+    })
+}
+
+pub fn remove(id: u64) -> Result<()> {
+    with_history(|history| {
+        history.entries.retain(|e| e.id != id);
+        history.save()
+    })
+}
+
+/// Forgets everything read under the active profile.
+pub fn clear() -> Result<()> {
+    with_history(|history| {
+        history.entries.clear();
+        history.save()
+    })
+}
@@ -0,0 +1,90 @@
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use super::Quality;
+use crate::schema::{ChapterHash, Filename, MangaId};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ImageKey {
+    Page(ChapterHash, Filename, Quality),
+    Cover(MangaId, Filename, String),
+}
+
+struct Entry {
+    image: image::RgbImage,
+    last_used: u64,
+}
+
+/// Decoded images, evicted least-recently-used first once they exceed the memory budget.
+struct ImageCache {
+    entries: HashMap<ImageKey, Entry>,
+    budget: usize,
+    used: usize,
+    clock: u64,
+}
+
+impl ImageCache {
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    fn get(&mut self, key: &ImageKey) -> Option<image::RgbImage> {
+        let now = self.tick();
+        let entry = self.entries.get_mut(key)?;
+        entry.last_used = now;
+        Some(entry.image.clone())
+    }
+
+    fn insert(&mut self, key: ImageKey, image: image::RgbImage) {
+        let size = image.as_raw().len();
+        if size > self.budget {
+            return;
+        }
+        let last_used = self.tick();
+        if let Some(old) = self.entries.insert(key, Entry { image, last_used }) {
+            self.used -= old.image.as_raw().len();
+        }
+        self.used += size;
+        self.evict();
+    }
+
+    fn evict(&mut self) {
+        while self.used > self.budget {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone());
+            match oldest.and_then(|key| self.entries.remove(&key)) {
+                Some(entry) => self.used -= entry.image.as_raw().len(),
+                None => break,
+            }
+        }
+    }
+}
+
+static CACHE: Lazy<Mutex<ImageCache>> = Lazy::new(|| {
+    Mutex::new(ImageCache {
+        entries: HashMap::new(),
+        budget: crate::settings::get().memory_cache_bytes(),
+        used: 0,
+        clock: 0,
+    })
+});
+
+pub fn get(key: &ImageKey) -> Option<image::RgbImage> {
+    CACHE.lock().unwrap().get(key)
+}
+
+pub fn insert(key: ImageKey, image: image::RgbImage) {
+    CACHE.lock().unwrap().insert(key, image)
+}
+
+/// Sets the memory budget in bytes, evicting immediately if the cache is now over it.
+pub fn set_budget(bytes: usize) {
+    let mut cache = CACHE.lock().unwrap();
+    cache.budget = bytes;
+    cache.evict();
+}
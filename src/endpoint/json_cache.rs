@@ -0,0 +1,63 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::path::PathBuf;
+use url::Url;
+
+/// How long a response from `url` may be served without asking the server again.
+/// `None` means responses from that endpoint are never cached at all.
+pub fn freshness(url: &Url) -> Option<Duration> {
+    if url.host_str() != Some("api.mangadex.org") {
+        return None;
+    }
+    let path = url.path();
+    if path.starts_with("/at-home/") || path.starts_with("/auth/") || path.starts_with("/user") {
+        None
+    } else if path == "/manga/tag" {
+        Some(Duration::days(7))
+    } else if path.ends_with("/feed") || path.ends_with("/aggregate") {
+        Some(Duration::minutes(5))
+    } else if path.starts_with("/cover") {
+        Some(Duration::days(1))
+    } else {
+        Some(Duration::minutes(15))
+    }
+}
+
+fn cache_location(url: &Url) -> PathBuf {
+    let name = format!("{:x}.json", Sha256::digest(url.as_str().as_bytes()));
+    crate::data_dir().join("json_cache").join(name)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedResponse {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub fetched_at: DateTime<Utc>,
+    pub body: String,
+}
+
+impl CachedResponse {
+    pub fn is_fresh(&self, freshness: Duration) -> bool {
+        Utc::now() - self.fetched_at < freshness
+    }
+}
+
+pub fn load(url: &Url) -> Option<CachedResponse> {
+    let f = File::open(cache_location(url)).ok()?;
+    serde_json::from_reader(f).ok()
+}
+
+/// Best-effort: a response that can't be written is simply not cached.
+pub fn store(url: &Url, resp: &CachedResponse) {
+    let loc = cache_location(url);
+    let tmp = loc.with_extension("json.tmp");
+    let written = fs::create_dir_all(loc.parent().unwrap())
+        .and_then(|_| File::create(&tmp))
+        .and_then(|f| serde_json::to_writer(f, resp).map_err(Into::into))
+        .and_then(|_| fs::rename(&tmp, &loc));
+    if written.is_err() {
+        let _ = fs::remove_file(tmp);
+    }
+}
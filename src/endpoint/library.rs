@@ -0,0 +1,220 @@
+//! The logged-in user's follows and reading statuses.
+//!
+//! A copy is kept in the local database for each profile, so the library can still be
+//! shown offline, and so titles elsewhere can say whether they're followed.
+
+use once_cell::sync::Lazy;
+use reqwest::Method;
+use rusqlite::params;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+use super::auth::{self, profile};
+use super::blocking;
+use crate::db;
+use crate::schema::{self, MangaId, ReadingStatus};
+use crate::store::PerProfile;
+use crate::{types, Result};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Library {
+    pub follows: HashSet<MangaId>,
+    pub statuses: HashMap<MangaId, ReadingStatus>,
+}
+
+impl Library {
+    fn load() -> Self {
+        // Before the database, the library was kept in a JSON file.
+        if let Ok(dir) = profile::dir() {
+            db::import_json(&dir.join("library.json"), |old: Self| old.save());
+        }
+
+        let profile = profile::active();
+        db::with_db(|conn| {
+            let mut stmt = conn.prepare_cached(
+                "SELECT manga_id, following, status FROM library WHERE profile = ?1",
+            )?;
+            let rows = stmt.query_map(params![profile], |row| {
+                let status: Option<String> = row.get(2)?;
+                Ok((row.get::<_, MangaId>(0)?, row.get::<_, bool>(1)?, status))
+            })?;
+            let mut library = Self::default();
+            for row in rows {
+                let (id, following, status) = row?;
+                if following {
+                    library.follows.insert(id);
+                }
+                if let Some(status) = status.as_deref().and_then(db::from_json) {
+                    library.statuses.insert(id, status);
+                }
+            }
+            Ok(library)
+        })
+        .unwrap_or_default()
+    }
+
+    fn save(&self) -> Result<()> {
+        let profile = profile::active();
+        db::with_db(|conn| {
+            let tx = conn.transaction()?;
+            tx.execute("DELETE FROM library WHERE profile = ?1", params![profile])?;
+            {
+                let mut put = tx.prepare_cached(
+                    "INSERT INTO library (profile, manga_id, following, status)
+                     VALUES (?1, ?2, ?3, ?4)",
+                )?;
+                for id in self.ids() {
+                    let status = self.statuses.get(&id).map(db::to_json);
+                    put.execute(params![profile, id, self.follows.contains(&id), status])?;
+                }
+            }
+            tx.commit()
+        })
+    }
+
+    /// Saves just the one title, which is all a follow or status change touches.
+    fn save_title(&self, id: &MangaId) -> Result<()> {
+        let profile = profile::active();
+        let following = self.follows.contains(id);
+        let status = self.statuses.get(id).map(db::to_json);
+        db::with_db(|conn| {
+            if following || status.is_some() {
+                conn.execute(
+                    "INSERT OR REPLACE INTO library (profile, manga_id, following, status)
+                     VALUES (?1, ?2, ?3, ?4)",
+                    params![profile, id, following, status],
+                )?;
+            } else {
+                conn.execute(
+                    "DELETE FROM library WHERE profile = ?1 AND manga_id = ?2",
+                    params![profile, id],
+                )?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Every title that's followed or has a status.
+    pub fn ids(&self) -> Vec<MangaId> {
+        let mut ids: Vec<MangaId> = self.follows.iter().copied().collect();
+        let unfollowed = self.statuses.keys().filter(|id| !self.follows.contains(id));
+        ids.extend(unfollowed);
+        ids
+    }
+}
+
+static LIBRARY: Lazy<PerProfile<Library>> = Lazy::new(|| PerProfile::new(Library::load));
+
+fn with_library<T>(f: impl FnOnce(&mut Library) -> T) -> T {
+    LIBRARY.with(f)
+}
+
+pub fn saved() -> Library {
+    with_library(|library| library.clone())
+}
+
+pub fn is_following(manga_id: &MangaId) -> bool {
+    with_library(|library| library.follows.contains(manga_id))
+}
+
+pub fn reading_status(manga_id: &MangaId) -> Option<ReadingStatus> {
+    with_library(|library| library.statuses.get(manga_id).copied())
+}
+
+async fn followed_ids() -> Result<HashSet<MangaId>> {
+    let mut ids = HashSet::new();
+    let mut offset = 0;
+    loop {
+        let url = format!(
+            "https://api.mangadex.org/user/follows/manga?limit=100&offset={}",
+            offset
+        );
+        let page: schema::MangaListResponse = auth::get_json(&url).await?;
+        offset += page.results.len() as u32;
+        let done = page.results.is_empty() || offset >= page.total;
+        ids.extend(page.results.into_iter().map(|manga| manga.data.id));
+        if done {
+            return Ok(ids);
+        }
+    }
+}
+
+/// Fetches the user's follows and reading statuses, replacing the saved copy.
+pub async fn sync() -> Result<Library> {
+    let follows = followed_ids().await?;
+    let statuses: schema::ReadingStatusesResponse =
+        auth::get_json("https://api.mangadex.org/manga/status").await?;
+    let library = Library {
+        follows,
+        statuses: statuses.statuses,
+    };
+    blocking(move || {
+        with_library(|saved| {
+            *saved = library.clone();
+            saved.save()
+        })?;
+        Ok(library)
+    })
+    .await
+}
+
+pub async fn set_following(manga_id: MangaId, follow: bool) -> Result<()> {
+    let url = format!("https://api.mangadex.org/manga/{}/follow", manga_id);
+    let method = if follow { Method::POST } else { Method::DELETE };
+    let _: schema::EmptyResponse = auth::request_json::<(), _>(method, &url, None).await?;
+    blocking(move || {
+        with_library(|library| {
+            if follow {
+                library.follows.insert(manga_id);
+            } else {
+                library.follows.remove(&manga_id);
+            }
+            library.save_title(&manga_id)
+        })
+    })
+    .await
+}
+
+/// Sets the title's reading status, or clears it if `status` is `None`.
+pub async fn set_reading_status(manga_id: MangaId, status: Option<ReadingStatus>) -> Result<()> {
+    let url = format!("https://api.mangadex.org/manga/{}/status", manga_id);
+    let body = schema::UpdateReadingStatus { status };
+    let _: schema::EmptyResponse = auth::request_json(Method::POST, &url, Some(&body)).await?;
+    blocking(move || {
+        with_library(|library| {
+            match status {
+                Some(status) => library.statuses.insert(manga_id, status),
+                None => library.statuses.remove(&manga_id),
+            };
+            library.save_title(&manga_id)
+        })
+    })
+    .await
+}
+
+/// Whichever titles in the library were saved when last fetched.
+pub fn saved_titles() -> Vec<types::Manga> {
+    db::manga(&saved().ids()).unwrap_or_default()
+}
+
+/// Looks up the titles in the library, a hundred (one page) at a time.
+pub async fn titles(ids: &[MangaId]) -> Result<Vec<types::Manga>> {
+    let mut titles = Vec::new();
+    for chunk in ids.chunks(100) {
+        let query = schema::MangaListQuery {
+            limit: Some(100),
+            ids: Some(chunk.iter().map(|id| id.0).collect()),
+            // Unlike searches, the library should show everything that's in it.
+            content_rating: Some(vec![
+                schema::ContentRating::Safe,
+                schema::ContentRating::Suggestive,
+                schema::ContentRating::Erotica,
+                schema::ContentRating::Pornographic,
+            ]),
+            ..Default::default()
+        };
+        let page = super::search_manga(&query).await?;
+        titles.extend(page.series.into_values());
+    }
+    Ok(titles)
+}
@@ -0,0 +1,160 @@
+use futures::stream::{self, Stream, TryStreamExt};
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::VecDeque;
+
+use super::{get_json_with_pairs, query_pairs};
+use crate::schema::ListResponse;
+use crate::Result;
+
+struct PageState<T> {
+    url: String,
+    pairs: Vec<(String, String)>,
+    offset: u32,
+    total: Option<u32>,
+    buffer: VecDeque<T>,
+}
+
+impl<T> PageState<T> {
+    fn exhausted(&self) -> bool {
+        self.total.is_some_and(|total| self.offset >= total)
+    }
+
+    /// The query for the next page.
+    fn next_pairs(&self) -> Vec<(String, String)> {
+        let mut pairs = self.pairs.clone();
+        pairs.push(("offset".into(), self.offset.to_string()));
+        pairs
+    }
+
+    fn absorb(&mut self, page: ListResponse<T>) {
+        if page.results.is_empty() {
+            // Don't trust `total` to keep us out of an infinite loop.
+            self.total = Some(self.offset);
+        } else {
+            self.offset = page.offset + page.results.len() as u32;
+            self.total = Some(page.total);
+            self.buffer.extend(page.results);
+        }
+    }
+}
+
+/// Walks every page of a list endpoint, starting at the query's `offset` (if any).
+/// Each page is only requested once the previous one has been consumed.
+/// The stream ends after the last page or after yielding the first error.
+pub fn paginate<T, Q>(url: &str, query: &Q) -> impl Stream<Item = Result<T>> + Send + 'static
+where
+    T: DeserializeOwned + Send + 'static,
+    Q: Serialize,
+{
+    paginate_pages(url, query)
+        .map_ok(|page| stream::iter(page.into_iter().map(Ok)))
+        .try_flatten()
+}
+
+/// Like `paginate`, but yields each page's results together.
+pub fn paginate_pages<T, Q>(
+    url: &str,
+    query: &Q,
+) -> impl Stream<Item = Result<Vec<T>>> + Send + 'static
+where
+    T: DeserializeOwned + Send + 'static,
+    Q: Serialize,
+{
+    let mut pairs = query_pairs(query);
+    let offset = pairs
+        .iter()
+        .find(|(k, _)| k == "offset")
+        .and_then(|(_, v)| v.parse().ok())
+        .unwrap_or(0);
+    pairs.retain(|(k, _)| k != "offset");
+
+    let state = PageState {
+        url: url.to_owned(),
+        pairs,
+        offset,
+        total: None,
+        buffer: VecDeque::new(),
+    };
+
+    stream::unfold(state, |mut state| async move {
+        loop {
+            if !state.buffer.is_empty() {
+                let page = state.buffer.drain(..).collect();
+                return Some((Ok(page), state));
+            }
+            if state.exhausted() {
+                return None;
+            }
+
+            let pairs = state.next_pairs();
+            match get_json_with_pairs::<_, ListResponse<T>>(&state.url, &pairs).await {
+                Ok(page) => state.absorb(page),
+                Err(e) => {
+                    state.total = Some(state.offset);
+                    return Some((Err(e), state));
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state() -> PageState<u32> {
+        PageState {
+            url: String::new(),
+            pairs: vec![("limit".into(), "2".into())],
+            offset: 0,
+            total: None,
+            buffer: VecDeque::new(),
+        }
+    }
+
+    fn page(results: Vec<u32>, offset: u32, total: u32) -> ListResponse<u32> {
+        ListResponse {
+            results,
+            limit: 2,
+            offset,
+            total,
+        }
+    }
+
+    #[test]
+    fn walks_pages_until_total() {
+        let mut state = state();
+        assert!(!state.exhausted());
+        assert_eq!(state.next_pairs().last().unwrap().1, "0");
+
+        state.absorb(page(vec![1, 2], 0, 3));
+        assert!(!state.exhausted());
+        assert_eq!(state.next_pairs().last().unwrap().1, "2");
+
+        state.absorb(page(vec![3], 2, 3));
+        assert!(state.exhausted());
+        assert_eq!(state.buffer, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn stops_on_an_empty_page() {
+        let mut state = state();
+        state.absorb(page(vec![1, 2], 0, 100));
+        state.absorb(page(vec![], 2, 100));
+        assert!(state.exhausted());
+        assert_eq!(state.buffer.len(), 2);
+    }
+
+    #[test]
+    fn keeps_the_query_apart_from_the_offset() {
+        let mut state = state();
+        state.offset = 40;
+        assert_eq!(
+            state.next_pairs(),
+            vec![
+                ("limit".to_owned(), "2".to_owned()),
+                ("offset".to_owned(), "40".to_owned()),
+            ]
+        );
+    }
+}
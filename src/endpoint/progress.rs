@@ -0,0 +1,250 @@
+//! Which chapters have been read, and the page each one was left at.
+//!
+//! Read markers are kept in the local database for each profile and synced with the API
+//! when logged in. The last page is only ever kept locally.
+
+use once_cell::sync::Lazy;
+use reqwest::Method;
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+use super::auth::{self, profile};
+use super::blocking;
+use crate::db;
+use crate::schema::{self, ChapterId, MangaId};
+use crate::store::PerProfile;
+use crate::Result;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChapterProgress {
+    pub manga_id: MangaId,
+    pub read: bool,
+    /// The page to resume at.
+    pub last_page: usize,
+    /// Whether `read` was changed here and the API hasn't been told yet.
+    #[serde(default)]
+    pending: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Progress {
+    chapters: HashMap<ChapterId, ChapterProgress>,
+}
+
+impl Progress {
+    fn load() -> Self {
+        // Before the database, progress was kept in a JSON file.
+        if let Ok(dir) = profile::dir() {
+            db::import_json(&dir.join("progress.json"), |old: Self| old.save());
+        }
+
+        let profile = profile::active();
+        db::with_db(|conn| {
+            let mut stmt = conn.prepare_cached(
+                "SELECT chapter_id, manga_id, read, last_page, pending FROM progress
+                 WHERE profile = ?1",
+            )?;
+            let rows = stmt.query_map(params![profile], |row| {
+                let last_page: i64 = row.get(3)?;
+                let chapter = ChapterProgress {
+                    manga_id: row.get(1)?,
+                    read: row.get(2)?,
+                    last_page: last_page as usize,
+                    pending: row.get(4)?,
+                };
+                Ok((row.get::<_, ChapterId>(0)?, chapter))
+            })?;
+            let chapters = rows.collect::<rusqlite::Result<_>>()?;
+            Ok(Self { chapters })
+        })
+        .unwrap_or_default()
+    }
+
+    fn put(conn: &Connection, id: &ChapterId, chapter: &ChapterProgress) -> rusqlite::Result<()> {
+        let mut stmt = conn.prepare_cached(
+            "INSERT OR REPLACE INTO progress
+             (profile, chapter_id, manga_id, read, last_page, pending)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        )?;
+        stmt.execute(params![
+            profile::active(),
+            id,
+            chapter.manga_id,
+            chapter.read,
+            chapter.last_page as i64,
+            chapter.pending,
+        ])?;
+        Ok(())
+    }
+
+    fn save(&self) -> Result<()> {
+        db::with_db(|conn| {
+            let tx = conn.transaction()?;
+            for (id, chapter) in &self.chapters {
+                Self::put(&tx, id, chapter)?;
+            }
+            tx.commit()
+        })
+    }
+
+    /// Saves the title's chapters, which is all a sync changes.
+    fn save_manga(&self, manga_id: &MangaId) -> Result<()> {
+        db::with_db(|conn| {
+            let tx = conn.transaction()?;
+            for (id, chapter) in &self.chapters {
+                if chapter.manga_id == *manga_id {
+                    Self::put(&tx, id, chapter)?;
+                }
+            }
+            tx.commit()
+        })
+    }
+
+    /// Saves just the one chapter, which is all that changes while reading.
+    fn save_chapter(&self, id: &ChapterId) -> Result<()> {
+        match self.chapters.get(id) {
+            Some(chapter) => db::with_db(|conn| Self::put(conn, id, chapter)),
+            None => Ok(()),
+        }
+    }
+
+    fn entry(&mut self, manga_id: MangaId, chapter_id: ChapterId) -> &mut ChapterProgress {
+        self.chapters
+            .entry(chapter_id)
+            .or_insert_with(|| ChapterProgress {
+                manga_id,
+                read: false,
+                last_page: 0,
+                pending: false,
+            })
+    }
+}
+
+static PROGRESS: Lazy<PerProfile<Progress>> = Lazy::new(|| PerProfile::new(Progress::load));
+
+fn with_progress<T>(f: impl FnOnce(&mut Progress) -> T) -> T {
+    PROGRESS.with(f)
+}
+
+pub fn get(chapter_id: &ChapterId) -> Option<ChapterProgress> {
+    with_progress(|progress| progress.chapters.get(chapter_id).cloned())
+}
+
+/// Everything known about the title's chapters.
+pub fn for_manga(manga_id: &MangaId) -> HashMap<ChapterId, ChapterProgress> {
+    with_progress(|progress| {
+        progress
+            .chapters
+            .iter()
+            .filter(|(_, chapter)| chapter.manga_id == *manga_id)
+            .map(|(id, chapter)| (*id, chapter.clone()))
+            .collect()
+    })
+}
+
+/// Remembers the page to resume the chapter at, marking it read once it's `finished`.
+/// Returns whether the chapter was just marked read.
+pub fn set_page(manga_id: MangaId, chapter_id: ChapterId, page: usize, finished: bool) -> bool {
+    with_progress(|progress| {
+        let chapter = progress.entry(manga_id, chapter_id);
+        let newly_read = finished && !chapter.read;
+        if chapter.last_page == page && !newly_read {
+            return false;
+        }
+        chapter.last_page = page;
+        if newly_read {
+            chapter.read = true;
+            chapter.pending = true;
+        }
+        // Losing a page position isn't worth interrupting anyone over.
+        let _ = progress.save_chapter(&chapter_id);
+        newly_read
+    })
+}
+
+pub fn set_read(manga_id: MangaId, chapter_id: ChapterId, read: bool) -> Result<()> {
+    with_progress(|progress| {
+        let chapter = progress.entry(manga_id, chapter_id);
+        if chapter.read != read {
+            chapter.read = read;
+            chapter.pending = true;
+            if !read {
+                // Reading it again should start from the beginning.
+                chapter.last_page = 0;
+            }
+        }
+        progress.save_chapter(&chapter_id)
+    })
+}
+
+/// Syncs for each title are kept from interleaving, so that an older fetch of the API's
+/// markers can't overwrite a newer one.
+static SYNCING: Lazy<Mutex<HashMap<MangaId, Arc<tokio::sync::Mutex<()>>>>> =
+    Lazy::new(Default::default);
+
+/// Sends the title's local read markers to the API, then takes on any made elsewhere.
+/// Returns whether that changed any.
+pub async fn sync(manga_id: MangaId) -> Result<bool> {
+    let lock = SYNCING.lock().unwrap().entry(manga_id).or_default().clone();
+    let _syncing = lock.lock().await;
+
+    let pending: Vec<(ChapterId, bool)> = blocking(move || {
+        with_progress(|progress| {
+            progress
+                .chapters
+                .iter()
+                .filter(|(_, chapter)| chapter.manga_id == manga_id && chapter.pending)
+                .map(|(id, chapter)| (*id, chapter.read))
+                .collect()
+        })
+    })
+    .await;
+    let url = format!("https://api.mangadex.org/manga/{}/read", manga_id);
+
+    if !pending.is_empty() {
+        let body = schema::UpdateReadMarkers {
+            chapter_ids_read: pending.iter().filter(|p| p.1).map(|p| p.0).collect(),
+            chapter_ids_unread: pending.iter().filter(|p| !p.1).map(|p| p.0).collect(),
+        };
+        let _: schema::EmptyResponse = auth::request_json(Method::POST, &url, Some(&body)).await?;
+        blocking(move || {
+            with_progress(|progress| {
+                for (id, read) in &pending {
+                    // It may have been toggled again in the meantime.
+                    if let Some(chapter) = progress.chapters.get_mut(id) {
+                        if chapter.read == *read {
+                            chapter.pending = false;
+                        }
+                    }
+                }
+            })
+        })
+        .await;
+    }
+
+    let remote: schema::ReadMarkersResponse = auth::get_json(&url).await?;
+    let remote: HashSet<ChapterId> = remote.data.into_iter().collect();
+    blocking(move || {
+        with_progress(|progress| {
+            let mut changed = false;
+            for (id, chapter) in progress.chapters.iter_mut() {
+                if chapter.manga_id == manga_id && !chapter.pending {
+                    changed |= chapter.read != remote.contains(id);
+                    chapter.read = remote.contains(id);
+                }
+            }
+            for id in remote {
+                let chapter = progress.entry(manga_id, id);
+                if !chapter.pending {
+                    changed |= !chapter.read;
+                    chapter.read = true;
+                }
+            }
+            progress.save_manga(&manga_id)?;
+            Ok(changed)
+        })
+    })
+    .await
+}
@@ -0,0 +1,276 @@
+//! New chapters of followed titles, found by checking the follows feed in the background.
+//!
+//! The inbox is kept in the active profile's directory, along with when each title
+//! was last checked, so nothing is reported twice.
+
+use chrono::{DateTime, Duration, Utc};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::auth::{self, profile};
+use super::library;
+use crate::schema::{self, ChapterId, Language, MangaId};
+use crate::store::{self, PerProfile};
+use crate::{language, types, Result};
+
+/// How many minutes to wait between checks.
+const INTERVAL: i64 = 30;
+/// Read updates beyond this many are dropped, oldest first.
+const KEEP: usize = 500;
+
+fn inbox_location() -> Result<PathBuf> {
+    Ok(profile::dir()?.join("updates.json"))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Update {
+    pub manga_id: MangaId,
+    pub manga_title: String,
+    pub chapter_id: ChapterId,
+    pub chapter: Option<String>,
+    pub title: String,
+    pub language: Language,
+    pub published: DateTime<Utc>,
+    pub unread: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Inbox {
+    /// When the feed was last checked. Nothing is reported from before the first check.
+    checked_at: Option<DateTime<Utc>>,
+    /// The newest publication time seen for each title.
+    last_seen: HashMap<MangaId, DateTime<Utc>>,
+    /// Newest first.
+    updates: Vec<Update>,
+    /// Chapters found ahead of being published, which are reported once they are.
+    #[serde(default)]
+    scheduled: Vec<Update>,
+}
+
+impl Inbox {
+    fn load() -> Self {
+        inbox_location()
+            .ok()
+            .and_then(|path| store::read_json(&path))
+            .unwrap_or_default()
+    }
+
+    fn save(&self) -> Result<()> {
+        store::write_json(&inbox_location()?, self)?;
+        GENERATION.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// The updates in the preferred languages.
+    fn shown(&self) -> impl Iterator<Item = &Update> {
+        let languages = language::preferred();
+        self.updates
+            .iter()
+            .filter(move |update| languages.contains(&update.language))
+    }
+}
+
+static INBOX: Lazy<PerProfile<Inbox>> = Lazy::new(|| PerProfile::new(Inbox::load));
+/// Bumped whenever the inbox is saved, so the UI can tell when to reload it.
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+/// Keeps the background checker and a manual check from reporting the same chapters.
+static CHECKING: Lazy<tokio::sync::Mutex<()>> = Lazy::new(Default::default);
+
+fn with_inbox<T>(f: impl FnOnce(&mut Inbox) -> T) -> T {
+    INBOX.with(f)
+}
+
+pub fn generation() -> u64 {
+    GENERATION.load(Ordering::Relaxed) + INBOX.reloads()
+}
+
+/// The updates in the preferred languages, newest first.
+pub fn updates() -> Vec<Update> {
+    with_inbox(|inbox| inbox.shown().cloned().collect())
+}
+
+pub fn unread_count() -> usize {
+    with_inbox(|inbox| inbox.shown().filter(|update| update.unread).count())
+}
+
+pub fn mark_read(chapter_id: &ChapterId) -> Result<()> {
+    with_inbox(|inbox| {
+        for update in &mut inbox.updates {
+            if update.chapter_id == *chapter_id {
+                update.unread = false;
+            }
+        }
+        inbox.save()
+    })
+}
+
+pub fn mark_all_read() -> Result<()> {
+    with_inbox(|inbox| {
+        for update in &mut inbox.updates {
+            update.unread = false;
+        }
+        inbox.save()
+    })
+}
+
+fn is_due() -> bool {
+    with_inbox(|inbox| {
+        inbox
+            .checked_at
+            .is_none_or(|checked_at| Utc::now() - checked_at >= Duration::minutes(INTERVAL))
+    })
+}
+
+/// Every chapter in the follows feed updated since `since`, oldest first.
+async fn followed_feed(
+    since: DateTime<Utc>,
+    languages: &[Language],
+) -> Result<Vec<types::Chapter>> {
+    let mut chapters = Vec::new();
+    loop {
+        let mut pairs = vec![
+            ("limit".to_owned(), "100".to_owned()),
+            ("offset".to_owned(), chapters.len().to_string()),
+            (
+                "updatedAtSince".to_owned(),
+                since.format("%Y-%m-%dT%H:%M:%S").to_string(),
+            ),
+            ("order[updatedAt]".to_owned(), "asc".to_owned()),
+        ];
+        for language in languages {
+            pairs.push(("translatedLanguage[]".to_owned(), language.clone()));
+        }
+        let url = reqwest::Url::parse_with_params(
+            "https://api.mangadex.org/user/follows/manga/feed",
+            &pairs,
+        )
+        .expect("Failed to build feed URL");
+        let page: schema::MangaFeedResponse = auth::get_json(url.as_str()).await?;
+        let done =
+            page.results.is_empty() || chapters.len() + page.results.len() >= page.total as usize;
+        chapters.extend(page.results.into_iter().map(types::Chapter::from));
+        if done {
+            return Ok(chapters);
+        }
+    }
+}
+
+/// Checks the follows feed for new chapters, adding them to the inbox.
+/// Returns how many were found.
+pub async fn check() -> Result<usize> {
+    let _checking = CHECKING.lock().await;
+    let profile = profile::active();
+    let started = Utc::now();
+    let checked_at = with_inbox(|inbox| inbox.checked_at);
+    // Chapters in newly preferred languages are only found from the next check on.
+    let languages = language::preferred();
+
+    let checked_at = match checked_at {
+        Some(checked_at) => checked_at,
+        None => {
+            // Everything before the first check is old news.
+            return with_inbox(|inbox| {
+                inbox.checked_at = Some(started);
+                inbox.save().map(|()| 0)
+            });
+        }
+    };
+
+    // Chapters can be updated while a check is running, so the windows overlap a little.
+    let chapters = followed_feed(checked_at - Duration::minutes(5), &languages).await?;
+    let last_seen = with_inbox(|inbox| inbox.last_seen.clone());
+    let known: HashSet<ChapterId> = with_inbox(|inbox| {
+        inbox
+            .updates
+            .iter()
+            .chain(&inbox.scheduled)
+            .map(|update| update.chapter_id)
+            .collect()
+    });
+    // Edits to old chapters show up in the feed too, so only newer publications count.
+    // Ones still to be published are kept for later, since they won't be in the feed again
+    // unless they're edited.
+    let new: Vec<(MangaId, types::Chapter)> = chapters
+        .into_iter()
+        .filter_map(|chapter| {
+            let manga_id = MangaId(*chapter.relationships.get(&types::RelationshipType::Manga)?);
+            let seen = last_seen.get(&manga_id).copied().unwrap_or(checked_at);
+            let published = chapter.attributes.publish_at;
+            if published > seen && !known.contains(&chapter.id) {
+                Some((manga_id, chapter))
+            } else {
+                None
+            }
+        })
+        .collect();
+
+    let mut ids: Vec<MangaId> = new.iter().map(|(id, _)| *id).collect();
+    ids.sort_by_key(|id| id.0);
+    ids.dedup();
+    let titles: HashMap<MangaId, String> = library::titles(&ids)
+        .await?
+        .iter()
+        .map(|manga| (manga.id, language::title(&manga.attributes)))
+        .collect();
+
+    if profile::active() != profile {
+        // These belong to a profile that's no longer loaded; it can check again later.
+        return Ok(0);
+    }
+    with_inbox(|inbox| {
+        for (manga_id, chapter) in new {
+            inbox.scheduled.push(Update {
+                manga_id,
+                manga_title: titles
+                    .get(&manga_id)
+                    .cloned()
+                    .unwrap_or_else(|| manga_id.to_string()),
+                chapter_id: chapter.id,
+                chapter: chapter.attributes.chapter,
+                title: chapter.attributes.title,
+                language: chapter.attributes.translated_language,
+                published: chapter.attributes.publish_at,
+                unread: true,
+            });
+        }
+        let (due, later): (Vec<Update>, _) = std::mem::take(&mut inbox.scheduled)
+            .into_iter()
+            .partition(|update| update.published <= started);
+        inbox.scheduled = later;
+        let found = due.len();
+        for update in due {
+            let seen = inbox
+                .last_seen
+                .entry(update.manga_id)
+                .or_insert(update.published);
+            *seen = (*seen).max(update.published);
+            inbox.updates.push(update);
+        }
+        inbox
+            .updates
+            .sort_by_key(|update| Reverse(update.published));
+        while inbox.updates.len() > KEEP {
+            match inbox.updates.iter().rposition(|update| !update.unread) {
+                Some(oldest_read) => inbox.updates.remove(oldest_read),
+                None => break,
+            };
+        }
+        inbox.checked_at = Some(started);
+        inbox.save().map(|()| found)
+    })
+}
+
+/// Checks for updates every so often, for as long as the app is open.
+pub async fn run() {
+    loop {
+        if auth::username().is_some() && super::is_online() && is_due() && check().await.is_err() {
+            // The next check covers the same window, so there's no hurry.
+            tokio::time::sleep(std::time::Duration::from_secs(5 * 60)).await;
+        }
+        tokio::time::sleep(std::time::Duration::from_secs(60)).await;
+    }
+}
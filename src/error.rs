@@ -1,4 +1,7 @@
 use snafu::Snafu;
+use std::path::PathBuf;
+
+use crate::schema::ChapterId;
 
 #[derive(Debug, Snafu)]
 #[snafu(visibility = "pub")]
@@ -17,6 +20,43 @@ pub enum Error {
     },
     #[snafu(display("Failed to decode image: {}", source))]
     ImageErr { source: image::ImageError },
+    #[snafu(display("Page {} is out of range for a chapter with {} pages", index, count))]
+    PageErr { index: usize, count: usize },
+    #[snafu(display("Chapter {} hasn't been downloaded", chapter_id))]
+    NotDownloadedErr { chapter_id: ChapterId },
+    #[snafu(display("I/O error on {}: {}", path.display(), source))]
+    IoErr {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[snafu(display("Local database error: {}", source))]
+    DbErr { source: rusqlite::Error },
+    #[snafu(display("The local database couldn't be opened: {}", detail))]
+    NoDbErr { detail: String },
+    #[snafu(display("Failed to write zip archive: {}", source))]
+    ZipErr { source: zip::result::ZipError },
+    #[snafu(display("Invalid setting: {}", detail))]
+    SettingsErr { detail: String },
+    #[snafu(display("Incorrect username or password"))]
+    BadCredentialsErr {},
+    #[snafu(display("This account can't log in: {}", detail))]
+    AccountLockedErr { detail: String },
+    #[snafu(display("MangaDex rejected the request ({}): {}", status, detail))]
+    ApiErr { status: u16, detail: String },
+    #[snafu(display("Not logged in"))]
+    NotLoggedInErr {},
+    #[snafu(display("Wrong passphrase, or the saved session is damaged"))]
+    DecryptErr {},
+    #[snafu(display(
+        "There's no system keyring to protect the saved session, so please choose a passphrase"
+    ))]
+    PassphraseNeededErr {},
+    #[snafu(display("\"{}\" can't be used as a profile name", name))]
+    ProfileNameErr { name: String },
+    #[snafu(display("There's already a profile called \"{}\"", name))]
+    ProfileExistsErr { name: String },
+    #[snafu(display("There's no profile called \"{}\"", name))]
+    NoProfileErr { name: String },
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,237 @@
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::endpoint::download;
+use crate::{language, schema, types, Result};
+
+mod cbz;
+mod epub;
+mod pdf;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, druid::Data)]
+pub enum Format {
+    Cbz,
+    Epub,
+    Pdf,
+}
+
+impl Format {
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Cbz => "cbz",
+            Self::Epub => "epub",
+            Self::Pdf => "pdf",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Options {
+    pub format: Format,
+    /// Re-encode PDF pages as JPEG at this quality (0-100), rather than embedding the originals.
+    pub jpeg_quality: Option<u8>,
+}
+
+/// How much a release is preferred over others of the same chapter:
+/// first by how preferred its language is, then by whether it's from `group`.
+pub fn release_rank(
+    chapter: &types::Chapter,
+    languages: &[schema::Language],
+    group: Option<Uuid>,
+) -> (Reverse<usize>, bool) {
+    let language = &chapter.attributes.translated_language;
+    let position = languages
+        .iter()
+        .position(|l| l == language)
+        .unwrap_or(languages.len());
+    let release_group = chapter
+        .relationships
+        .get(&types::RelationshipType::ScanlationGroup)
+        .copied();
+    (Reverse(position), group.is_some() && release_group == group)
+}
+
+/// Picks the highest-ranked release of each chapter number, in the order the numbers first
+/// appear in `chapters`. Unnumbered chapters can't be matched up, so each is kept on its own.
+pub fn one_per_chapter<'a, K: Ord>(
+    chapters: impl IntoIterator<Item = &'a types::Chapter>,
+    rank: impl Fn(&types::Chapter) -> K,
+) -> Vec<&'a types::Chapter> {
+    let mut picked: Vec<(&'a types::Chapter, K)> = Vec::new();
+    let mut positions: HashMap<(Option<String>, Option<schema::ChapterId>), usize> = HashMap::new();
+    for chapter in chapters {
+        let key = rank(chapter);
+        let number = &chapter.attributes.chapter;
+        let group = (number.clone(), number.is_none().then_some(chapter.id));
+        match positions.get(&group).copied() {
+            Some(i) if key > picked[i].1 => picked[i] = (chapter, key),
+            Some(_) => {}
+            None => {
+                positions.insert(group, picked.len());
+                picked.push((chapter, key));
+            }
+        }
+    }
+    picked.into_iter().map(|(chapter, _)| chapter).collect()
+}
+
+/// What an exporter needs to know about the manga itself.
+pub struct MangaInfo {
+    pub id: schema::MangaId,
+    pub attributes: Arc<types::MangaAttributes>,
+    /// Author and artist names, where they could be looked up.
+    pub authors: Vec<String>,
+    pub right_to_left: bool,
+    /// Falls back to the first page where a format wants a cover.
+    pub cover: Option<image::RgbImage>,
+}
+
+/// What gets exported: a single chapter, or every chapter of a volume.
+pub enum Selection<'a> {
+    Chapter(&'a types::Chapter),
+    Volume(Option<String>, Vec<&'a types::Chapter>),
+}
+
+impl<'a> Selection<'a> {
+    /// Selects the chapters in `volume` according to the manga's aggregate,
+    /// picking one downloaded release per chapter number from `chapters`,
+    /// preferably in the preferred language and by `group`.
+    /// Also returns the names of the chapters with no downloaded release, which are left out.
+    pub fn volume(
+        aggregate: &schema::MangaAggregateResponse,
+        volume: Option<String>,
+        chapters: &'a [types::Chapter],
+        group: Option<Uuid>,
+    ) -> (Self, Vec<String>) {
+        // The aggregate files chapters without a volume (or number) under "none".
+        let key = |s: &Option<String>| s.clone().unwrap_or_else(|| "none".into());
+        let numbers: HashSet<&String> = aggregate
+            .volumes
+            .get(&key(&volume))
+            .map(|v| v.chapters.keys().collect())
+            .unwrap_or_default();
+
+        let downloaded = |c: &types::Chapter| {
+            download::status(&c.id).is_some_and(|job| job.state == download::JobState::Done)
+        };
+        let languages = language::preferred();
+        let in_volume = chapters
+            .iter()
+            .filter(|c| c.attributes.volume == volume)
+            .filter(|c| numbers.contains(&key(&c.attributes.chapter)));
+        let releases = one_per_chapter(in_volume, |c| {
+            (downloaded(c), release_rank(c, &languages, group))
+        });
+        let (selected, missing): (Vec<_>, Vec<_>) =
+            releases.into_iter().partition(|c| downloaded(c));
+        let missing = missing
+            .into_iter()
+            .map(|c| Selection::Chapter(c).name())
+            .collect();
+        (Self::Volume(volume, selected), missing)
+    }
+
+    pub fn chapters(&self) -> Vec<&'a types::Chapter> {
+        match self {
+            Self::Chapter(chapter) => vec![*chapter],
+            Self::Volume(_, chapters) => chapters.clone(),
+        }
+    }
+
+    /// A short description such as "Vol. 2" or "Vol. 2 Ch. 10".
+    pub fn name(&self) -> String {
+        match self {
+            Self::Chapter(chapter) => {
+                let attrs = &chapter.attributes;
+                let mut name = String::new();
+                if let Some(volume) = &attrs.volume {
+                    name += &format!("Vol. {} ", volume);
+                }
+                match &attrs.chapter {
+                    Some(num) => name += &format!("Ch. {}", num),
+                    None => name += "Oneshot",
+                }
+                name
+            }
+            Self::Volume(Some(volume), _) => format!("Vol. {}", volume),
+            Self::Volume(None, _) => "No Volume".into(),
+        }
+    }
+}
+
+/// A downloaded chapter's pages, in reading order.
+struct ChapterFiles<'a> {
+    chapter: &'a types::Chapter,
+    pages: Vec<(schema::Filename, Vec<u8>)>,
+}
+
+fn load<'a>(selection: &Selection<'a>) -> Result<Vec<ChapterFiles<'a>>> {
+    selection
+        .chapters()
+        .into_iter()
+        .map(|chapter| {
+            let pages = download::read_chapter(&chapter.id)?;
+            Ok(ChapterFiles { chapter, pages })
+        })
+        .collect()
+}
+
+/// Where exported files are written.
+pub fn export_dir() -> PathBuf {
+    directories::UserDirs::new()
+        .and_then(|dirs| dirs.download_dir().map(Path::to_owned))
+        .unwrap_or_else(|| crate::data_dir().join("exports"))
+}
+
+fn file_name(attrs: &schema::MangaAttributes, selection: &Selection<'_>, ext: &str) -> String {
+    let name = format!("{} - {}.{}", language::title(attrs), selection.name(), ext);
+    name.chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c => c,
+        })
+        .collect()
+}
+
+fn extension(filename: &schema::Filename) -> &str {
+    filename.0.rsplit('.').next().unwrap_or("png")
+}
+
+fn media_type(ext: &str) -> &'static str {
+    match ext {
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        _ => "image/png",
+    }
+}
+
+fn escape_xml(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped += "&amp;",
+            '<' => escaped += "&lt;",
+            '>' => escaped += "&gt;",
+            '"' => escaped += "&quot;",
+            '\'' => escaped += "&apos;",
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Packs the selected downloaded chapters into a file in `export_dir()`, returning its path.
+pub fn export(options: Options, info: &MangaInfo, selection: &Selection<'_>) -> Result<PathBuf> {
+    let name = file_name(&info.attributes, selection, options.format.extension());
+    let path = export_dir().join(name);
+    match options.format {
+        Format::Cbz => cbz::write(info, selection, &path)?,
+        Format::Epub => epub::write(info, selection, &path)?,
+        Format::Pdf => pdf::write(info, selection, options.jpeg_quality, &path)?,
+    }
+    Ok(path)
+}
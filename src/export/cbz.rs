@@ -0,0 +1,163 @@
+use chrono::Datelike;
+use snafu::ResultExt;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use super::{escape_xml, extension, load, ChapterFiles, MangaInfo, Selection};
+use crate::error::{IoErr, ZipErr};
+use crate::{language, schema, Result};
+
+/// Kavita and Komga expect one of these, but anything that reads CBZ will do.
+fn age_rating(rating: schema::ContentRating) -> &'static str {
+    match rating {
+        schema::ContentRating::Safe => "Everyone",
+        schema::ContentRating::Suggestive => "Teen",
+        schema::ContentRating::Erotica => "Mature 17+",
+        schema::ContentRating::Pornographic => "Adults Only 18+",
+    }
+}
+
+fn comic_info(
+    info: &MangaInfo,
+    selection: &Selection<'_>,
+    chapters: &[ChapterFiles<'_>],
+) -> String {
+    let attrs = &info.attributes;
+    let mut fields: Vec<(&str, String)> = Vec::new();
+
+    let first = chapters.first().map(|c| &c.chapter.attributes);
+    match selection {
+        Selection::Chapter(chapter) => {
+            let chapter = &chapter.attributes;
+            if !chapter.title.is_empty() {
+                fields.push(("Title", chapter.title.clone()));
+            }
+            fields.push(("Series", language::title(attrs)));
+            if let Some(num) = &chapter.chapter {
+                fields.push(("Number", num.clone()));
+            }
+        }
+        Selection::Volume(..) => {
+            fields.push(("Title", selection.name()));
+            fields.push(("Series", language::title(attrs)));
+        }
+    }
+    // ComicInfo volumes are integers; anything else is left to the title.
+    let volume = first.and_then(|c| c.volume.as_deref());
+    if let Some(volume) = volume.filter(|v| v.parse::<u32>().is_ok()) {
+        fields.push(("Volume", volume.to_owned()));
+    }
+
+    if !info.authors.is_empty() {
+        fields.push(("Writer", info.authors.join(", ")));
+    }
+
+    let summary = language::resolve(&attrs.description);
+    if !summary.is_empty() {
+        fields.push(("Summary", summary));
+    }
+
+    let mut notes = Vec::new();
+    if let Some(status) = attrs.status {
+        notes.push(format!("Status: {:?}", status));
+    }
+    if let Some(year) = attrs.year {
+        notes.push(format!("Serialized since {}", year));
+    }
+    if !notes.is_empty() {
+        fields.push(("Notes", notes.join(". ")));
+    }
+
+    match first {
+        Some(first) => {
+            let date = first.publish_at;
+            fields.push(("Year", date.year().to_string()));
+            fields.push(("Month", date.month().to_string()));
+            fields.push(("Day", date.day().to_string()));
+        }
+        None => {
+            if let Some(year) = attrs.year {
+                fields.push(("Year", year.to_string()));
+            }
+        }
+    }
+
+    let mut genres = Vec::new();
+    let mut tags = Vec::new();
+    if let Some(demographic) = attrs.publication_demographic {
+        genres.push(format!("{:?}", demographic));
+    }
+    for tag in &attrs.tags {
+        let name = language::resolve(&tag.attributes.name);
+        if tag.attributes.group == "genre" {
+            genres.push(name);
+        } else {
+            tags.push(name);
+        }
+    }
+    if !genres.is_empty() {
+        fields.push(("Genre", genres.join(", ")));
+    }
+    if !tags.is_empty() {
+        fields.push(("Tags", tags.join(", ")));
+    }
+
+    let page_count: usize = chapters.iter().map(|c| c.pages.len()).sum();
+    fields.push(("PageCount", page_count.to_string()));
+    if let Some(first) = first {
+        fields.push(("LanguageISO", first.translated_language.clone()));
+    }
+    let manga = if info.right_to_left {
+        "YesAndRightToLeft"
+    } else {
+        "Yes"
+    };
+    fields.push(("Manga", manga.into()));
+    if let Some(rating) = attrs.content_rating {
+        fields.push(("AgeRating", age_rating(rating).into()));
+    }
+
+    let mut xml = String::from(concat!(
+        r#"<?xml version="1.0" encoding="utf-8"?>"#,
+        "\n",
+        r#"<ComicInfo xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" xmlns:xsd="http://www.w3.org/2001/XMLSchema">"#,
+        "\n",
+    ));
+    for (name, value) in fields {
+        xml += &format!("  <{0}>{1}</{0}>\n", name, escape_xml(&value));
+    }
+    xml += "</ComicInfo>\n";
+    xml
+}
+
+pub(super) fn write(info: &MangaInfo, selection: &Selection<'_>, path: &Path) -> Result<()> {
+    let chapters = load(selection)?;
+
+    let io_err = || IoErr { path };
+    fs::create_dir_all(path.parent().unwrap()).context(io_err())?;
+    let file = File::create(path).context(io_err())?;
+    let mut zip = ZipWriter::new(file);
+
+    zip.start_file("ComicInfo.xml", FileOptions::default())
+        .context(ZipErr)?;
+    zip.write_all(comic_info(info, selection, &chapters).as_bytes())
+        .context(io_err())?;
+
+    // Pages are numbered straight through, zero-padded so they sort in reading order.
+    let page_count: usize = chapters.iter().map(|c| c.pages.len()).sum();
+    let width = page_count.to_string().len().max(3);
+    // The images are already compressed, so there's nothing to gain from deflating them.
+    let stored = FileOptions::default().compression_method(CompressionMethod::Stored);
+    let pages = chapters.iter().flat_map(|c| &c.pages);
+    for (number, (filename, bytes)) in (1..).zip(pages) {
+        let name = format!("{:0width$}.{}", number, extension(filename), width = width);
+        zip.start_file(name, stored).context(ZipErr)?;
+        zip.write_all(bytes).context(io_err())?;
+    }
+
+    zip.finish().context(ZipErr)?;
+    Ok(())
+}
@@ -0,0 +1,303 @@
+use chrono::Utc;
+use snafu::ResultExt;
+use std::fs::{self, File};
+use std::io::{Cursor, Write};
+use std::path::Path;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use super::{escape_xml, extension, load, media_type, MangaInfo, Selection};
+use crate::error::{ImageErr, IoErr, ZipErr};
+use crate::{language, Result};
+
+const CONTAINER: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles>
+    <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+  </rootfiles>
+</container>
+"#;
+
+struct ManifestItem {
+    id: String,
+    href: String,
+    media_type: &'static str,
+    properties: Option<&'static str>,
+}
+
+/// One fixed-layout page showing `image` (relative to `OEBPS/`) at its natural size.
+fn page_xhtml(title: &str, image: &str, (width, height): (u32, u32)) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE html>
+<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops">
+<head>
+  <title>{title}</title>
+  <meta name="viewport" content="width={width}, height={height}"/>
+  <style>html, body {{ margin: 0; padding: 0; }} img {{ display: block; width: {width}px; height: {height}px; }}</style>
+</head>
+<body><img src="../{image}" alt="{title}"/></body>
+</html>
+"#,
+        title = escape_xml(title),
+        image = image,
+        width = width,
+        height = height,
+    )
+}
+
+fn nav_xhtml(title: &str, entries: &[(String, String)]) -> String {
+    let mut items = String::new();
+    for (label, href) in entries {
+        items += &format!(
+            "      <li><a href=\"{}\">{}</a></li>\n",
+            href,
+            escape_xml(label)
+        );
+    }
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE html>
+<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops">
+<head><title>{title}</title></head>
+<body>
+  <nav epub:type="toc" id="toc">
+    <h1>Contents</h1>
+    <ol>
+{items}    </ol>
+  </nav>
+</body>
+</html>
+"#,
+        title = escape_xml(title),
+        items = items,
+    )
+}
+
+fn content_opf(
+    info: &MangaInfo,
+    selection: &Selection<'_>,
+    title: &str,
+    manifest: &[ManifestItem],
+    spine: &[String],
+) -> String {
+    let attrs = &info.attributes;
+    let chapters = selection.chapters();
+    let identifier = match selection {
+        Selection::Chapter(chapter) => format!("urn:uuid:{}", chapter.id),
+        Selection::Volume(volume, _) => format!(
+            "urn:md-rs:{}:volume:{}",
+            info.id,
+            volume.as_deref().unwrap_or("none")
+        ),
+    };
+    let language = chapters
+        .first()
+        .map_or("en", |c| c.attributes.translated_language.as_str());
+
+    let mut metadata = vec![
+        format!(
+            r#"<dc:identifier id="book-id">{}</dc:identifier>"#,
+            escape_xml(&identifier)
+        ),
+        format!("<dc:title>{}</dc:title>", escape_xml(title)),
+        format!("<dc:language>{}</dc:language>", escape_xml(language)),
+    ];
+    for author in &info.authors {
+        metadata.push(format!("<dc:creator>{}</dc:creator>", escape_xml(author)));
+    }
+    let description = language::resolve(&attrs.description);
+    if !description.is_empty() {
+        metadata.push(format!(
+            "<dc:description>{}</dc:description>",
+            escape_xml(&description)
+        ));
+    }
+    for tag in &attrs.tags {
+        let name = language::resolve(&tag.attributes.name);
+        metadata.push(format!("<dc:subject>{}</dc:subject>", escape_xml(&name)));
+    }
+    if let Some(first) = chapters.first() {
+        let date = first.attributes.publish_at.format("%Y-%m-%d");
+        metadata.push(format!("<dc:date>{}</dc:date>", date));
+    }
+    metadata.push(format!(
+        r#"<meta property="dcterms:modified">{}</meta>"#,
+        Utc::now().format("%Y-%m-%dT%H:%M:%SZ")
+    ));
+    metadata.push(r#"<meta property="rendition:layout">pre-paginated</meta>"#.into());
+    metadata.push(r#"<meta property="rendition:spread">landscape</meta>"#.into());
+    if manifest.iter().any(|item| item.id == "cover-image") {
+        metadata.push(r#"<meta name="cover" content="cover-image"/>"#.into());
+    }
+
+    let manifest: Vec<String> = manifest
+        .iter()
+        .map(|item| {
+            let properties = item
+                .properties
+                .map_or_else(String::new, |p| format!(r#" properties="{}""#, p));
+            format!(
+                r#"<item id="{}" href="{}" media-type="{}"{}/>"#,
+                item.id, item.href, item.media_type, properties
+            )
+        })
+        .collect();
+    let spine: Vec<String> = spine
+        .iter()
+        .map(|id| format!(r#"<itemref idref="{}"/>"#, id))
+        .collect();
+    let direction = if info.right_to_left { "rtl" } else { "ltr" };
+
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0" unique-identifier="book-id" prefix="rendition: http://www.idpf.org/vocab/rendition/#">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+    {metadata}
+  </metadata>
+  <manifest>
+    {manifest}
+  </manifest>
+  <spine page-progression-direction="{direction}">
+    {spine}
+  </spine>
+</package>
+"#,
+        metadata = metadata.join("\n    "),
+        manifest = manifest.join("\n    "),
+        direction = direction,
+        spine = spine.join("\n    "),
+    )
+}
+
+pub(super) fn write(info: &MangaInfo, selection: &Selection<'_>, path: &Path) -> Result<()> {
+    let chapters = load(selection)?;
+    let title = format!(
+        "{} - {}",
+        language::title(&info.attributes),
+        selection.name()
+    );
+
+    let io_err = || IoErr { path };
+    fs::create_dir_all(path.parent().unwrap()).context(io_err())?;
+    let file = File::create(path).context(io_err())?;
+    let mut zip = ZipWriter::new(file);
+    let stored = FileOptions::default().compression_method(CompressionMethod::Stored);
+    let deflated = FileOptions::default();
+
+    // Readers only recognize the file if this comes first, uncompressed.
+    zip.start_file("mimetype", stored).context(ZipErr)?;
+    zip.write_all(b"application/epub+zip").context(io_err())?;
+    zip.start_file("META-INF/container.xml", deflated)
+        .context(ZipErr)?;
+    zip.write_all(CONTAINER.as_bytes()).context(io_err())?;
+
+    let mut manifest = vec![ManifestItem {
+        id: "nav".into(),
+        href: "nav.xhtml".into(),
+        media_type: "application/xhtml+xml",
+        properties: Some("nav"),
+    }];
+    let mut spine = Vec::new();
+    let mut toc = Vec::new();
+
+    let cover = match &info.cover {
+        Some(img) => {
+            let mut bytes = Vec::new();
+            image::DynamicImage::ImageRgb8(img.clone())
+                .write_to(&mut bytes, image::ImageOutputFormat::Jpeg(90))
+                .context(ImageErr)?;
+            Some((bytes, "jpg".to_owned()))
+        }
+        None => chapters
+            .first()
+            .and_then(|c| c.pages.first())
+            .map(|(filename, bytes)| (bytes.clone(), extension(filename).to_owned())),
+    };
+    if let Some((bytes, ext)) = cover {
+        let image = format!("images/cover.{}", ext);
+        let size = image::io::Reader::new(Cursor::new(&bytes))
+            .with_guessed_format()
+            .context(io_err())?
+            .into_dimensions()
+            .context(ImageErr)?;
+        zip.start_file(format!("OEBPS/{}", image), stored)
+            .context(ZipErr)?;
+        zip.write_all(&bytes).context(io_err())?;
+        zip.start_file("OEBPS/pages/cover.xhtml", deflated)
+            .context(ZipErr)?;
+        zip.write_all(page_xhtml(&title, &image, size).as_bytes())
+            .context(io_err())?;
+
+        manifest.push(ManifestItem {
+            id: "cover-image".into(),
+            href: image,
+            media_type: media_type(&ext),
+            properties: Some("cover-image"),
+        });
+        manifest.push(ManifestItem {
+            id: "cover".into(),
+            href: "pages/cover.xhtml".into(),
+            media_type: "application/xhtml+xml",
+            properties: None,
+        });
+        spine.push("cover".to_owned());
+    }
+
+    let mut number = 0;
+    for files in &chapters {
+        let mut label = Selection::Chapter(files.chapter).name();
+        if !files.chapter.attributes.title.is_empty() {
+            label += &format!(" - {}", files.chapter.attributes.title);
+        }
+        if !files.pages.is_empty() {
+            toc.push((label.clone(), format!("pages/{:04}.xhtml", number + 1)));
+        }
+        for (filename, bytes) in &files.pages {
+            number += 1;
+            let ext = extension(filename);
+            let image = format!("images/{:04}.{}", number, ext);
+            let page = format!("pages/{:04}.xhtml", number);
+
+            let size = image::io::Reader::new(Cursor::new(bytes))
+                .with_guessed_format()
+                .context(io_err())?
+                .into_dimensions()
+                .context(ImageErr)?;
+            zip.start_file(format!("OEBPS/{}", image), stored)
+                .context(ZipErr)?;
+            zip.write_all(bytes).context(io_err())?;
+            let page_title = format!("{}, page {}", label, number);
+            zip.start_file(format!("OEBPS/{}", page), deflated)
+                .context(ZipErr)?;
+            zip.write_all(page_xhtml(&page_title, &image, size).as_bytes())
+                .context(io_err())?;
+
+            manifest.push(ManifestItem {
+                id: format!("image-{:04}", number),
+                href: image,
+                media_type: media_type(ext),
+                properties: None,
+            });
+            manifest.push(ManifestItem {
+                id: format!("page-{:04}", number),
+                href: page,
+                media_type: "application/xhtml+xml",
+                properties: None,
+            });
+            spine.push(format!("page-{:04}", number));
+        }
+    }
+
+    zip.start_file("OEBPS/nav.xhtml", deflated)
+        .context(ZipErr)?;
+    zip.write_all(nav_xhtml(&title, &toc).as_bytes())
+        .context(io_err())?;
+    zip.start_file("OEBPS/content.opf", deflated)
+        .context(ZipErr)?;
+    let opf = content_opf(info, selection, &title, &manifest, &spine);
+    zip.write_all(opf.as_bytes()).context(io_err())?;
+
+    zip.finish().context(ZipErr)?;
+    Ok(())
+}
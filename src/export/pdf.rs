@@ -0,0 +1,425 @@
+use chrono::Utc;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use image::{ColorType, GenericImageView, ImageOutputFormat};
+use snafu::ResultExt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use super::{load, MangaInfo, Selection};
+use crate::error::{ImageErr, IoErr};
+use crate::{language, Result};
+
+/// A page image, ready to be embedded as an image XObject.
+struct Encoded {
+    data: Vec<u8>,
+    filter: &'static str,
+    color_space: &'static str,
+    /// Set for the CMYK JPEGs Adobe writes, which store every channel inverted.
+    decode: Option<&'static str>,
+    width: u32,
+    height: u32,
+}
+
+/// What a JPEG's frame header says about it.
+#[derive(Debug, PartialEq)]
+struct JpegFrame {
+    width: u32,
+    height: u32,
+    precision: u8,
+    components: u8,
+    /// Whether there's an Adobe APP14 segment, which means CMYK is stored inverted.
+    adobe: bool,
+}
+
+/// Reads the markers up to the first frame header, without decoding anything.
+fn jpeg_frame(bytes: &[u8]) -> Option<JpegFrame> {
+    if !bytes.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+    let mut adobe = false;
+    let mut i = 2;
+    loop {
+        if *bytes.get(i)? != 0xFF {
+            return None;
+        }
+        // Any number of 0xFF may pad the space between segments.
+        while *bytes.get(i + 1)? == 0xFF {
+            i += 1;
+        }
+        let marker = bytes[i + 1];
+        i += 2;
+        if marker == 0x01 || (0xD0..=0xD7).contains(&marker) {
+            continue;
+        }
+        let len = u16::from_be_bytes([*bytes.get(i)?, *bytes.get(i + 1)?]) as usize;
+        let segment = bytes.get(i + 2..i + len)?;
+        match marker {
+            // Every start of frame except DHT, JPG and DAC, which share the range.
+            0xC0..=0xCF if ![0xC4, 0xC8, 0xCC].contains(&marker) => {
+                let frame = segment.get(..6)?;
+                return Some(JpegFrame {
+                    precision: frame[0],
+                    height: u16::from_be_bytes([frame[1], frame[2]]).into(),
+                    width: u16::from_be_bytes([frame[3], frame[4]]).into(),
+                    components: frame[5],
+                    adobe,
+                });
+            }
+            0xEE if segment.starts_with(b"Adobe") => adobe = true,
+            // The image data starts without there having been a frame.
+            0xDA | 0xD9 => return None,
+            _ => {}
+        }
+        i += len;
+    }
+}
+
+/// Embeds JPEGs as they are where PDF can show them directly, and stores everything
+/// else losslessly, unless `jpeg_quality` asks for every page to be re-encoded.
+fn encode_page(bytes: &[u8], jpeg_quality: Option<u8>) -> Result<Encoded> {
+    if jpeg_quality.is_none() {
+        let passthrough = jpeg_frame(bytes)
+            .filter(|frame| frame.precision == 8)
+            .and_then(|frame| {
+                let (color_space, decode) = match frame.components {
+                    1 => ("/DeviceGray", None),
+                    3 => ("/DeviceRGB", None),
+                    4 if frame.adobe => ("/DeviceCMYK", Some("[1 0 1 0 1 0 1 0]")),
+                    4 => ("/DeviceCMYK", None),
+                    _ => return None,
+                };
+                Some(Encoded {
+                    data: bytes.to_vec(),
+                    filter: "/DCTDecode",
+                    color_space,
+                    decode,
+                    width: frame.width,
+                    height: frame.height,
+                })
+            });
+        if let Some(encoded) = passthrough {
+            return Ok(encoded);
+        }
+    }
+
+    let img = image::load_from_memory(bytes).context(ImageErr)?;
+    let (width, height) = img.dimensions();
+    let gray = matches!(
+        img.color(),
+        ColorType::L8 | ColorType::La8 | ColorType::L16 | ColorType::La16
+    );
+    let color_space = if gray { "/DeviceGray" } else { "/DeviceRGB" };
+    let img = if gray {
+        image::DynamicImage::ImageLuma8(img.to_luma8())
+    } else {
+        image::DynamicImage::ImageRgb8(img.to_rgb8())
+    };
+
+    let (data, filter) = match jpeg_quality {
+        None => {
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(img.as_bytes()).unwrap();
+            (encoder.finish().unwrap(), "/FlateDecode")
+        }
+        Some(quality) => {
+            let mut data = Vec::new();
+            img.write_to(&mut data, ImageOutputFormat::Jpeg(quality))
+                .context(ImageErr)?;
+            (data, "/DCTDecode")
+        }
+    };
+    Ok(Encoded {
+        data,
+        filter,
+        color_space,
+        decode: None,
+        width,
+        height,
+    })
+}
+
+/// Encodes a PDF text string, which may contain any Unicode as UTF-16BE.
+fn text(s: &str) -> String {
+    let mut hex = String::from("<FEFF");
+    for unit in s.encode_utf16() {
+        hex += &format!("{:04X}", unit);
+    }
+    hex + ">"
+}
+
+/// Writes numbered objects one after another, remembering where each one starts.
+struct PdfWriter<W: Write> {
+    out: W,
+    position: usize,
+    /// Byte offset of each object, indexed by object number minus one.
+    offsets: Vec<usize>,
+}
+
+impl<W: Write> PdfWriter<W> {
+    fn new(out: W) -> io::Result<Self> {
+        let mut writer = Self {
+            out,
+            position: 0,
+            offsets: Vec::new(),
+        };
+        // The comment's high bytes tell tools that the file is binary.
+        writer.write(b"%PDF-1.4\n%\xE2\xE3\xCF\xD3\n")?;
+        Ok(writer)
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.out.write_all(bytes)?;
+        self.position += bytes.len();
+        Ok(())
+    }
+
+    /// Allocates an object number, to be written later.
+    fn reserve(&mut self) -> usize {
+        self.offsets.push(0);
+        self.offsets.len()
+    }
+
+    fn object(&mut self, id: usize, body: &str) -> io::Result<()> {
+        self.offsets[id - 1] = self.position;
+        self.write(format!("{} 0 obj\n{}\nendobj\n", id, body).as_bytes())
+    }
+
+    fn stream(&mut self, id: usize, dict: &str, data: &[u8]) -> io::Result<()> {
+        self.offsets[id - 1] = self.position;
+        let header = format!(
+            "{} 0 obj\n<< {} /Length {} >>\nstream\n",
+            id,
+            dict,
+            data.len()
+        );
+        self.write(header.as_bytes())?;
+        self.write(data)?;
+        self.write(b"\nendstream\nendobj\n")
+    }
+
+    fn finish(mut self, root: usize, info: usize) -> io::Result<W> {
+        let xref = self.position;
+        let mut table = format!("xref\n0 {}\n0000000000 65535 f \n", self.offsets.len() + 1);
+        for offset in &self.offsets {
+            table += &format!("{:010} 00000 n \n", offset);
+        }
+        table += &format!(
+            "trailer\n<< /Size {} /Root {} 0 R /Info {} 0 R >>\nstartxref\n{}\n%%EOF\n",
+            self.offsets.len() + 1,
+            root,
+            info,
+            xref
+        );
+        self.write(table.as_bytes())?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+pub(super) fn write(
+    info: &MangaInfo,
+    selection: &Selection<'_>,
+    jpeg_quality: Option<u8>,
+    path: &Path,
+) -> Result<()> {
+    let chapters = load(selection)?;
+    let attrs = &info.attributes;
+    let title = format!("{} - {}", language::title(attrs), selection.name());
+
+    let io_err = || IoErr { path };
+    fs::create_dir_all(path.parent().unwrap()).context(io_err())?;
+    let file = File::create(path).context(io_err())?;
+    let mut pdf = PdfWriter::new(BufWriter::new(file)).context(io_err())?;
+
+    let catalog = pdf.reserve();
+    let pages = pdf.reserve();
+    let doc_info = pdf.reserve();
+    let outlines = pdf.reserve();
+
+    let mut kids = Vec::new();
+    // The first page of each chapter, for its bookmark.
+    let mut bookmarks = Vec::new();
+    for files in &chapters {
+        let mut label = Selection::Chapter(files.chapter).name();
+        if !files.chapter.attributes.title.is_empty() {
+            label += &format!(" - {}", files.chapter.attributes.title);
+        }
+
+        for (index, (_, bytes)) in files.pages.iter().enumerate() {
+            let img = encode_page(bytes, jpeg_quality)?;
+            let (image_id, content_id, page_id) = (pdf.reserve(), pdf.reserve(), pdf.reserve());
+
+            let mut dict = format!(
+                "/Type /XObject /Subtype /Image /Width {} /Height {} /ColorSpace {} /BitsPerComponent 8 /Filter {}",
+                img.width, img.height, img.color_space, img.filter
+            );
+            if let Some(decode) = img.decode {
+                dict += &format!(" /Decode {}", decode);
+            }
+            pdf.stream(image_id, &dict, &img.data).context(io_err())?;
+
+            // One point per pixel; the image fills the whole page.
+            let content = format!("q {} 0 0 {} 0 0 cm /Im0 Do Q", img.width, img.height);
+            pdf.stream(content_id, "", content.as_bytes())
+                .context(io_err())?;
+
+            let page = format!(
+                "<< /Type /Page /Parent {} 0 R /MediaBox [0 0 {} {}] /Resources << /XObject << /Im0 {} 0 R >> >> /Contents {} 0 R >>",
+                pages, img.width, img.height, image_id, content_id
+            );
+            pdf.object(page_id, &page).context(io_err())?;
+
+            if index == 0 {
+                bookmarks.push((label.clone(), page_id));
+            }
+            kids.push(page_id);
+        }
+    }
+
+    let kid_refs: Vec<String> = kids.iter().map(|id| format!("{} 0 R", id)).collect();
+    let body = format!(
+        "<< /Type /Pages /Kids [{}] /Count {} >>",
+        kid_refs.join(" "),
+        kids.len()
+    );
+    pdf.object(pages, &body).context(io_err())?;
+
+    let bookmark_ids: Vec<usize> = bookmarks.iter().map(|_| pdf.reserve()).collect();
+    for (i, (label, page_id)) in bookmarks.iter().enumerate() {
+        let mut body = format!(
+            "<< /Title {} /Parent {} 0 R /Dest [{} 0 R /Fit]",
+            text(label),
+            outlines,
+            page_id
+        );
+        if i > 0 {
+            body += &format!(" /Prev {} 0 R", bookmark_ids[i - 1]);
+        }
+        if let Some(next) = bookmark_ids.get(i + 1) {
+            body += &format!(" /Next {} 0 R", next);
+        }
+        body += " >>";
+        pdf.object(bookmark_ids[i], &body).context(io_err())?;
+    }
+    let body = match (bookmark_ids.first(), bookmark_ids.last()) {
+        (Some(first), Some(last)) => format!(
+            "<< /Type /Outlines /First {} 0 R /Last {} 0 R /Count {} >>",
+            first,
+            last,
+            bookmark_ids.len()
+        ),
+        _ => "<< /Type /Outlines /Count 0 >>".into(),
+    };
+    pdf.object(outlines, &body).context(io_err())?;
+
+    let mut body = format!(
+        "<< /Title {} /Producer {} /CreationDate (D:{})",
+        text(&title),
+        text("md-rs"),
+        Utc::now().format("%Y%m%d%H%M%SZ")
+    );
+    if !info.authors.is_empty() {
+        body += &format!(" /Author {}", text(&info.authors.join(", ")));
+    }
+    let description = language::resolve(&attrs.description);
+    if !description.is_empty() {
+        body += &format!(" /Subject {}", text(&description));
+    }
+    let tags: Vec<String> = attrs
+        .tags
+        .iter()
+        .map(|tag| language::resolve(&tag.attributes.name))
+        .collect();
+    if !tags.is_empty() {
+        body += &format!(" /Keywords {}", text(&tags.join(", ")));
+    }
+    body += " >>";
+    pdf.object(doc_info, &body).context(io_err())?;
+
+    let mut body = format!(
+        "<< /Type /Catalog /Pages {} 0 R /Outlines {} 0 R /PageMode /UseOutlines",
+        pages, outlines
+    );
+    if let Some(first) = chapters.first() {
+        body += &format!(
+            " /Lang {}",
+            text(&first.chapter.attributes.translated_language)
+        );
+    }
+    if info.right_to_left {
+        body += " /ViewerPreferences << /Direction /R2L >>";
+    }
+    body += " >>";
+    pdf.object(catalog, &body).context(io_err())?;
+
+    pdf.finish(catalog, doc_info).context(io_err())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A JPEG's headers, up to the start of a frame with `components` channels.
+    fn headers(components: u8, adobe: bool) -> Vec<u8> {
+        let mut bytes = vec![0xFF, 0xD8];
+        // JFIF
+        bytes.extend(&[0xFF, 0xE0, 0x00, 0x10]);
+        bytes.extend(b"JFIF\0\x01\x01\0\0\x01\0\x01\0\0");
+        if adobe {
+            bytes.extend(&[0xFF, 0xEE, 0x00, 0x0E]);
+            bytes.extend(b"Adobe\0\x64\0\0\0\0\x02");
+        }
+        // A huffman table, whose marker sits among the frame markers.
+        bytes.extend(&[0xFF, 0xC4, 0x00, 0x03, 0x00]);
+        let len = 8 + 3 * components as u16;
+        bytes.extend(&[0xFF, 0xC0]);
+        bytes.extend(&len.to_be_bytes());
+        bytes.extend(&[8, 0x04, 0xB0, 0x03, 0x20, components]);
+        for id in 1..=components {
+            bytes.extend(&[id, 0x11, 0]);
+        }
+        bytes
+    }
+
+    #[test]
+    fn reads_the_frame() {
+        let frame = jpeg_frame(&headers(3, false)).unwrap();
+        assert_eq!(
+            frame,
+            JpegFrame {
+                width: 800,
+                height: 1200,
+                precision: 8,
+                components: 3,
+                adobe: false,
+            }
+        );
+    }
+
+    #[test]
+    fn notices_adobe_cmyk() {
+        let frame = jpeg_frame(&headers(4, true)).unwrap();
+        assert_eq!((frame.components, frame.adobe), (4, true));
+    }
+
+    #[test]
+    fn embeds_jpegs_in_their_own_color_space() {
+        let page = encode_page(&headers(1, false), None).unwrap();
+        assert_eq!((page.color_space, page.decode), ("/DeviceGray", None));
+        let page = encode_page(&headers(4, true), None).unwrap();
+        assert_eq!(
+            (page.color_space, page.decode),
+            ("/DeviceCMYK", Some("[1 0 1 0 1 0 1 0]"))
+        );
+        assert_eq!((page.width, page.height), (800, 1200));
+    }
+
+    #[test]
+    fn rejects_what_isnt_a_jpeg() {
+        assert_eq!(jpeg_frame(b"\x89PNG\r\n\x1a\n"), None);
+        assert_eq!(jpeg_frame(&[0xFF, 0xD8, 0xFF]), None);
+    }
+}
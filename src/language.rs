@@ -0,0 +1,127 @@
+//! Picking the best of whatever languages a string from the API comes in,
+//! going by the preferred languages in the settings.
+
+use crate::schema::{Language, LocalizedString, MangaAttributes};
+use crate::settings;
+
+/// The preferred languages, most preferred first. There's always at least one.
+pub fn preferred() -> Vec<Language> {
+    settings::get().languages
+}
+
+/// Reads a comma-separated list of language codes, as typed, keeping the order.
+pub fn parse(text: &str) -> Vec<Language> {
+    let mut languages: Vec<Language> = Vec::new();
+    for lang in text.split(',').map(|lang| lang.trim().to_lowercase()) {
+        if !lang.is_empty() && !languages.contains(&lang) {
+            languages.push(lang);
+        }
+    }
+    languages
+}
+
+fn get<'a>(s: &'a LocalizedString, language: &str) -> Option<&'a str> {
+    s.get(language)
+        .map(String::as_str)
+        .filter(|s| !s.is_empty())
+}
+
+/// Any non-empty string, always the same one for the same map.
+fn any(s: &LocalizedString) -> Option<&str> {
+    s.iter()
+        .filter(|(_, s)| !s.is_empty())
+        .min_by_key(|(language, _)| language.as_str())
+        .map(|(_, s)| s.as_str())
+}
+
+/// Picks the string in the most preferred language there is, or else any of them.
+pub fn resolve(s: &LocalizedString) -> String {
+    resolve_in(s, &preferred())
+}
+
+fn resolve_in(s: &LocalizedString, languages: &[Language]) -> String {
+    languages
+        .iter()
+        .find_map(|language| get(s, language))
+        .or_else(|| any(s))
+        .unwrap_or_default()
+        .to_owned()
+}
+
+/// Picks the title in the most preferred language, looking through the alternative titles too.
+/// Failing that, it's the title in the original language (or its romanization), then any title.
+pub fn title(attrs: &MangaAttributes) -> String {
+    let titles: Vec<&LocalizedString> = std::iter::once(&attrs.title)
+        .chain(&attrs.alt_titles)
+        .collect();
+    title_in(&titles, &attrs.original_language, &preferred())
+}
+
+fn title_in(titles: &[&LocalizedString], original: &str, languages: &[Language]) -> String {
+    let fallbacks = [original.to_owned(), format!("{}-ro", original)];
+    for language in languages.iter().chain(&fallbacks) {
+        if let Some(title) = titles.iter().find_map(|s| get(s, language)) {
+            return title.to_owned();
+        }
+    }
+    titles
+        .iter()
+        .find_map(|s| any(s))
+        .unwrap_or_default()
+        .to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn localized(pairs: &[(&str, &str)]) -> LocalizedString {
+        pairs
+            .iter()
+            .map(|(language, s)| (language.to_string(), s.to_string()))
+            .collect()
+    }
+
+    fn languages(codes: &[&str]) -> Vec<Language> {
+        codes.iter().map(|code| code.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_keeps_the_first_of_each_in_order() {
+        assert_eq!(parse("fr, EN,,ja , en, fr"), languages(&["fr", "en", "ja"]));
+        assert!(parse(" , ").is_empty());
+    }
+
+    #[test]
+    fn resolve_goes_by_preference() {
+        let s = localized(&[("en", "Hello"), ("fr", "Bonjour")]);
+        assert_eq!(resolve_in(&s, &languages(&["fr", "en"])), "Bonjour");
+        assert_eq!(resolve_in(&s, &languages(&["de", "en"])), "Hello");
+    }
+
+    #[test]
+    fn resolve_skips_empty_strings_and_falls_back_to_any() {
+        let s = localized(&[("en", ""), ("ko", "Annyeong"), ("de", "Hallo")]);
+        // The fallback is the first language alphabetically, so it doesn't change between runs.
+        assert_eq!(resolve_in(&s, &languages(&["en"])), "Hallo");
+        assert_eq!(resolve_in(&localized(&[]), &languages(&["en"])), "");
+    }
+
+    #[test]
+    fn title_looks_through_alternative_titles_first() {
+        let main = localized(&[("ja-ro", "Shingeki no Kyojin")]);
+        let alt = localized(&[("en", "Attack on Titan")]);
+        let title = title_in(&[&main, &alt], "ja", &languages(&["en"]));
+        assert_eq!(title, "Attack on Titan");
+    }
+
+    #[test]
+    fn title_falls_back_to_the_original_then_its_romanization() {
+        let main = localized(&[("ja-ro", "Shingeki no Kyojin"), ("ko", "Jingyeogui Geoin")]);
+        let alt = localized(&[("ja", "進撃の巨人")]);
+        let preferred = languages(&["fr"]);
+        assert_eq!(title_in(&[&main, &alt], "ja", &preferred), "進撃の巨人");
+        assert_eq!(title_in(&[&main], "ja", &preferred), "Shingeki no Kyojin");
+        assert_eq!(title_in(&[&main], "zh", &preferred), "Shingeki no Kyojin");
+    }
+}
@@ -1,9 +1,14 @@
 #![deny(rust_2018_idioms)]
 
 mod async_data;
+mod db;
 mod endpoint;
 mod error;
+mod export;
+mod language;
 mod schema;
+mod settings;
+mod store;
 mod types;
 mod ui;
 
@@ -21,15 +26,25 @@ use std::path::PathBuf;
 
 use druid::{AppLauncher, WindowDesc};
 
-fn data_dir() -> PathBuf {
-    let p_dirs = directories::ProjectDirs::from("", "The0x539", "md-rs")
-        .expect("Could not determine project directory");
+fn project_dirs() -> directories::ProjectDirs {
+    directories::ProjectDirs::from("", "The0x539", "md-rs")
+        .expect("Could not determine project directory")
+}
 
+fn data_dir() -> PathBuf {
+    let p_dirs = project_dirs();
     let dir = p_dirs.data_dir();
     std::fs::create_dir_all(dir).expect("Failed to ensure existence of project directory");
     dir.to_owned()
 }
 
+fn config_dir() -> PathBuf {
+    let p_dirs = project_dirs();
+    let dir = p_dirs.config_dir();
+    std::fs::create_dir_all(dir).expect("Failed to ensure existence of config directory");
+    dir.to_owned()
+}
+
 fn main() -> Result<()> {
     let (tx, rx) = mpsc::unbounded_channel();
 
@@ -39,7 +54,7 @@ fn main() -> Result<()> {
     let main_window = WindowDesc::new(move || ui::manga_list::manga_list(tx))
         .window_size((800., 300.))
         .set_position((100., 100.));
-    let data = ui::manga_list::MangaListData::default();
+    let data = ui::manga_list::MangaListData::new();
 
     let launcher = AppLauncher::with_window(main_window);
     #[cfg(debug_assertions)]
@@ -59,6 +74,13 @@ pub enum Message {
 }
 
 async fn async_main(mut rx: mpsc::UnboundedReceiver<Message>) {
+    // The download queue is worked through for as long as the app is open.
+    let downloads = tokio::spawn(endpoint::download::run());
+    let session = tokio::spawn(endpoint::auth::run());
+    let updates = tokio::spawn(endpoint::updates::run());
+    let settings = tokio::spawn(settings::run());
+    let disk_cache = tokio::spawn(endpoint::disk_cache::run());
+
     let futs = FuturesUnordered::new();
     while let Some(msg) = rx.recv().await {
         match msg {
@@ -68,5 +90,11 @@ async fn async_main(mut rx: mpsc::UnboundedReceiver<Message>) {
             } //Message::Error(e) => println!("Background error: {}", e),
         }
     }
+    downloads.abort();
+    session.abort();
+    updates.abort();
+    settings.abort();
+    disk_cache.abort();
     futs.for_each_concurrent(None, |_| async {}).await;
+    let _ = tokio::task::spawn_blocking(endpoint::disk_cache::flush).await;
 }
@@ -2,7 +2,7 @@
 
 use chrono::{DateTime, NaiveDateTime, Utc};
 use optfield::optfield;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use std::collections::{BTreeMap, HashMap};
 use url::Url;
 use uuid::Uuid;
@@ -35,6 +35,7 @@ id!(TagId);
 id!(ChapterId);
 id!(UserId);
 id!(CoverId);
+id!(AuthorId);
 
 wrapper!(Filename: String);
 wrapper!(ChapterHash: String);
@@ -72,7 +73,7 @@ pub enum PublicationStatus {
     Cancelled,
 }
 
-#[derive(Debug, Clone, Copy, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, druid::Data)]
 #[serde(rename_all = "snake_case")]
 pub enum ReadingStatus {
     Reading,
@@ -83,7 +84,7 @@ pub enum ReadingStatus {
     Completed,
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum ContentRating {
     Safe,
@@ -99,7 +100,7 @@ pub enum Visibility {
     Private,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum RelationshipType {
     Manga,
@@ -134,14 +135,17 @@ pub enum Success {
     Error,
 }
 
-#[derive(Debug, Clone, Copy, Serialize)]
+/// Either key may be left out, e.g. to sort by `createdAt` alone.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct SortOrder {
-    pub created_at: SortDirection,
-    pub updated_at: SortDirection,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<SortDirection>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<SortDirection>,
 }
 
-// TODO: figure out how to add #[serde(skip_serializing_if = "Option::is_none")] to the fields
+// Unset fields serialize as `null`; `endpoint::query_json` drops them when building the URL.
 #[optfield(
     pub MangaListQuery,
     attrs = add(derive(Default))
@@ -172,13 +176,27 @@ struct FullMangaListQuery {
 #[derive(Debug, Clone, Deserialize)]
 pub struct ListResponse<T> {
     pub results: Vec<T>,
-    pub limit: u16,
-    pub offset: u16,
-    pub total: u16,
+    pub limit: u32,
+    pub offset: u32,
+    pub total: u32,
 }
 
 pub type MangaListResponse = ListResponse<MangaResponse>;
 
+#[derive(Debug, Clone, Deserialize)]
+pub struct ErrorResponse {
+    pub result: Success,
+    pub errors: Vec<ApiError>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ApiError {
+    pub id: Uuid,
+    pub status: u16,
+    pub title: String,
+    pub detail: Option<String>,
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct ItemResponse<T> {
     pub result: Success,
@@ -188,16 +206,17 @@ pub struct ItemResponse<T> {
 
 pub type MangaResponse = ItemResponse<Manga>;
 
-#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum ItemType {
     Manga,
     Tag,
     Chapter,
     CoverArt,
+    Author,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Item<Id, Attrs> {
     pub id: Id,
     #[serde(rename = "type")]
@@ -214,10 +233,10 @@ pub struct Relationship {
     pub rel_type: RelationshipType,
 }
 
-type Language = String; // sigh
+pub type Language = String; // sigh
 pub type LocalizedString = HashMap<Language, String>;
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct MangaAttributes {
     pub title: LocalizedString,
@@ -238,7 +257,7 @@ pub struct MangaAttributes {
     pub updated_at: DateTime<Utc>,
 }
 
-#[derive(Debug, Clone, Hash, PartialEq, Eq, Deserialize)]
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Serialize, Deserialize)]
 pub enum MangaSource {
     #[serde(rename = "al")]
     Anilist,
@@ -270,7 +289,7 @@ pub enum MangaSource {
 
 pub type Tag = Item<TagId, TagAttributes>;
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TagAttributes {
     pub name: LocalizedString,
     // Documented to be a LocalizedString, but I only see empty arrays
@@ -300,11 +319,33 @@ pub struct AggregateChapter {
 
 pub type MangaViewResponse = ItemResponse<Manga>;
 
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct ChapterOrder {
+    pub volume: SortDirection,
+    pub chapter: SortDirection,
+}
+
+#[optfield(
+    pub MangaFeedQuery,
+    attrs = add(derive(Default))
+)]
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct FullMangaFeedQuery {
+    pub limit: u16,
+    pub offset: u32,
+    pub translated_language: Vec<Language>,
+    pub created_at_since: NaiveDateTime,
+    pub updated_at_since: NaiveDateTime,
+    pub publish_at_since: NaiveDateTime,
+    pub order: ChapterOrder,
+}
+
 pub type MangaFeedResponse = ListResponse<ChapterResponse>;
 pub type ChapterResponse = ItemResponse<Chapter>;
 pub type Chapter = Item<ChapterId, ChapterAttributes>;
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ChapterAttributes {
     pub title: String,
@@ -349,3 +390,71 @@ pub struct CoverAttributes {
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
+
+pub type AuthorResponse = ItemResponse<Author>;
+pub type Author = Item<AuthorId, AuthorAttributes>;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthorAttributes {
+    pub name: String,
+    pub image_url: Option<String>,
+    pub version: u32,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct EmptyResponse {
+    pub result: Success,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReadingStatusResponse {
+    pub result: Success,
+    pub status: Option<ReadingStatus>,
+}
+
+/// The API sends an empty object as `[]`, so that has to be accepted too.
+fn map_or_empty_list<'de, D, K, V>(deserializer: D) -> Result<HashMap<K, V>, D::Error>
+where
+    D: Deserializer<'de>,
+    K: Deserialize<'de> + Eq + std::hash::Hash,
+    V: Deserialize<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum MapOrList<K: Eq + std::hash::Hash, V> {
+        Map(HashMap<K, V>),
+        List(Vec<()>),
+    }
+    match MapOrList::deserialize(deserializer)? {
+        MapOrList::Map(map) => Ok(map),
+        MapOrList::List(_) => Ok(HashMap::new()),
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReadingStatusesResponse {
+    pub result: Success,
+    #[serde(deserialize_with = "map_or_empty_list")]
+    pub statuses: HashMap<MangaId, ReadingStatus>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UpdateReadingStatus {
+    pub status: Option<ReadingStatus>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReadMarkersResponse {
+    pub result: Success,
+    pub data: Vec<ChapterId>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateReadMarkers {
+    pub chapter_ids_read: Vec<ChapterId>,
+    pub chapter_ids_unread: Vec<ChapterId>,
+}
@@ -0,0 +1,395 @@
+//! The user's settings, kept in `settings.json` in the config directory, apart from the
+//! languages and content ratings, which each profile keeps in its own directory.
+//!
+//! Edits to the file are picked up while the app is running, as long as they're valid;
+//! otherwise the settings in effect stay as they were.
+
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use snafu::{ensure, ResultExt};
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use crate::endpoint::auth::profile;
+use crate::endpoint::{disk_cache, image_cache, Quality};
+use crate::error::{IoErr, JsonErr, SettingsErr};
+use crate::schema::{ContentRating, Language};
+use crate::store::{self, PerProfile};
+use crate::ui::reader::ReaderLayout;
+use crate::Result;
+
+const MIB: u64 = 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// The layout for series without one of their own, or `None` to guess from their metadata.
+    pub reader_layout: Option<ReaderLayout>,
+    /// Where chapters are downloaded to, or `None` for the data directory.
+    /// Chapters already downloaded stay where they are.
+    pub download_dir: Option<PathBuf>,
+    pub memory_cache_mib: u64,
+    pub disk_cache_mib: u64,
+    /// Most preferred first. Kept per profile; only read from this file to carry over
+    /// the languages chosen before they were.
+    #[serde(skip_serializing)]
+    pub languages: Vec<Language>,
+    /// The content ratings to include when browsing. Kept per profile, like `languages`.
+    #[serde(skip_serializing)]
+    pub content_rating: Vec<ContentRating>,
+    /// Whether to read and download the smaller, recompressed page images.
+    pub data_saver: bool,
+    /// API requests per second. Only read at startup.
+    pub rate_limit: u32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            reader_layout: None,
+            download_dir: None,
+            // Somewhere around 50-100 typical pages, decoded.
+            memory_cache_mib: 256,
+            disk_cache_mib: 1024,
+            languages: vec!["en".into()],
+            // The same as the API's own default.
+            content_rating: vec![
+                ContentRating::Safe,
+                ContentRating::Suggestive,
+                ContentRating::Erotica,
+            ],
+            data_saver: false,
+            rate_limit: 5,
+        }
+    }
+}
+
+impl Settings {
+    pub fn validate(&self) -> Result<()> {
+        ensure!(
+            self.download_dir.as_deref().is_none_or(Path::is_absolute),
+            SettingsErr {
+                detail: "the download location must be a full path",
+            }
+        );
+        ensure!(
+            (16..=16 * 1024).contains(&self.memory_cache_mib),
+            SettingsErr {
+                detail: "the memory cache must be between 16 MiB and 16 GiB",
+            }
+        );
+        ensure!(
+            (64..=1024 * 1024).contains(&self.disk_cache_mib),
+            SettingsErr {
+                detail: "the disk cache must be between 64 MiB and 1 TiB",
+            }
+        );
+        ensure!(
+            !self.languages.is_empty(),
+            SettingsErr {
+                detail: "at least one language is needed",
+            }
+        );
+        for language in &self.languages {
+            let valid = !language.is_empty()
+                && language
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+            ensure!(
+                valid,
+                SettingsErr {
+                    detail: format!("\"{}\" isn't a language code", language),
+                }
+            );
+        }
+        ensure!(
+            !self.content_rating.is_empty(),
+            SettingsErr {
+                detail: "at least one content rating is needed",
+            }
+        );
+        ensure!(
+            (1..=5).contains(&self.rate_limit),
+            SettingsErr {
+                detail: "MangaDex allows between 1 and 5 requests a second",
+            }
+        );
+        Ok(())
+    }
+
+    pub fn downloads_dir(&self) -> PathBuf {
+        self.download_dir
+            .clone()
+            .unwrap_or_else(|| crate::data_dir().join("downloads"))
+    }
+
+    pub fn memory_cache_bytes(&self) -> usize {
+        (self.memory_cache_mib * MIB) as usize
+    }
+
+    pub fn disk_cache_bytes(&self) -> u64 {
+        self.disk_cache_mib * MIB
+    }
+
+    pub fn quality(&self) -> Quality {
+        if self.data_saver {
+            Quality::DataSaver
+        } else {
+            Quality::Data
+        }
+    }
+}
+
+/// The settings that belong to each profile.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct ProfileSettings {
+    languages: Vec<Language>,
+    content_rating: Vec<ContentRating>,
+}
+
+impl ProfileSettings {
+    fn of(settings: &Settings) -> Self {
+        Self {
+            languages: settings.languages.clone(),
+            content_rating: settings.content_rating.clone(),
+        }
+    }
+
+    fn apply(&self, settings: &mut Settings) {
+        settings.languages = self.languages.clone();
+        settings.content_rating = self.content_rating.clone();
+    }
+
+    /// A profile without settings of its own starts with the ones in effect.
+    fn load() -> Self {
+        let saved = profile_settings_location()
+            .ok()
+            .and_then(|path| store::read_json::<Self>(&path))
+            .filter(|saved| {
+                let mut settings = Settings::default();
+                saved.apply(&mut settings);
+                settings.validate().is_ok()
+            });
+        saved.unwrap_or_else(|| {
+            let settings = Self::of(&SETTINGS.lock().unwrap());
+            let _ = settings.save();
+            settings
+        })
+    }
+
+    fn save(&self) -> Result<()> {
+        store::write_json(&profile_settings_location()?, self)
+    }
+}
+
+fn profile_settings_location() -> Result<PathBuf> {
+    Ok(profile::dir()?.join("settings.json"))
+}
+
+pub fn settings_location() -> PathBuf {
+    crate::config_dir().join("settings.json")
+}
+
+fn modified() -> Option<SystemTime> {
+    fs::metadata(settings_location())
+        .and_then(|m| m.modified())
+        .ok()
+}
+
+/// Reads the settings file, or returns `None` if there isn't one.
+fn read() -> Result<Option<Settings>> {
+    let path = settings_location();
+    let f = match File::open(&path) {
+        Ok(f) => f,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).context(IoErr { path }),
+    };
+    let settings: Settings = serde_json::from_reader(f).context(JsonErr {
+        type_name: pretty_type_name::pretty_type_name::<Settings>(),
+    })?;
+    settings.validate()?;
+    Ok(Some(settings))
+}
+
+fn write(settings: &Settings) -> Result<()> {
+    store::write_json(&settings_location(), settings)
+}
+
+/// Settings for a first run, carrying over the languages chosen before there were settings.
+fn first_run() -> Settings {
+    let old = crate::data_dir().join("languages.json");
+    let mut settings = Settings::default();
+    let languages: Option<Vec<Language>> = store::read_json(&old);
+    if let Some(languages) = languages.filter(|l| !l.is_empty()) {
+        settings.languages = languages;
+    }
+    if write(&settings).is_ok() {
+        let _ = fs::remove_file(old);
+    }
+    settings
+}
+
+static SETTINGS: Lazy<Mutex<Settings>> = Lazy::new(|| {
+    let settings = match read() {
+        Ok(Some(settings)) => settings,
+        Ok(None) => first_run(),
+        // The broken file is left alone for the user to fix.
+        Err(e) => {
+            *PROBLEM.lock().unwrap() = Some(e.to_string());
+            Settings::default()
+        }
+    };
+    *MODIFIED.lock().unwrap() = modified();
+    Mutex::new(settings)
+});
+/// When the settings file was last read or written by us.
+static MODIFIED: Lazy<Mutex<Option<SystemTime>>> = Lazy::new(Default::default);
+/// Why the settings file couldn't be used, the last time it was read.
+static PROBLEM: Lazy<Mutex<Option<String>>> = Lazy::new(Default::default);
+/// Keeps saving and reloading from racing each other.
+static SAVING: Lazy<Mutex<()>> = Lazy::new(Default::default);
+/// Bumped whenever the settings change, so the UI can tell when to reload them.
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+static PROFILE_SETTINGS: Lazy<PerProfile<ProfileSettings>> =
+    Lazy::new(|| PerProfile::new(ProfileSettings::load));
+
+pub fn get() -> Settings {
+    let mut settings = SETTINGS.lock().unwrap().clone();
+    PROFILE_SETTINGS.with(|profile| profile.apply(&mut settings));
+    settings
+}
+
+pub fn generation() -> u64 {
+    GENERATION.load(Ordering::Relaxed) + PROFILE_SETTINGS.reloads()
+}
+
+/// Why edits to the settings file weren't applied, if they weren't.
+pub fn problem() -> Option<String> {
+    Lazy::force(&SETTINGS);
+    PROBLEM.lock().unwrap().clone()
+}
+
+/// Validates and saves `new`, applying it straight away.
+pub fn set(new: Settings) -> Result<()> {
+    new.validate()?;
+    let _saving = SAVING.lock().unwrap();
+    replace(new, true)?;
+    *PROBLEM.lock().unwrap() = None;
+    Ok(())
+}
+
+fn replace(new: Settings, save: bool) -> Result<()> {
+    if save {
+        write(&new)?;
+        PROFILE_SETTINGS.with(|profile| {
+            *profile = ProfileSettings::of(&new);
+            profile.save()
+        })?;
+    }
+    *MODIFIED.lock().unwrap() = modified();
+
+    image_cache::set_budget(new.memory_cache_bytes());
+    disk_cache::set_size_cap(new.disk_cache_bytes());
+    *SETTINGS.lock().unwrap() = new;
+    GENERATION.fetch_add(1, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Applies the settings file if it's been edited since it was last read.
+fn reload() {
+    let _saving = SAVING.lock().unwrap();
+    let modified = modified();
+    if *MODIFIED.lock().unwrap() == modified {
+        return;
+    }
+    // Whatever happens, this edit has been dealt with.
+    *MODIFIED.lock().unwrap() = modified;
+    let res = match read() {
+        Ok(Some(mut settings)) => {
+            // The file only has a say in the settings that aren't kept per profile.
+            PROFILE_SETTINGS.with(|profile| profile.apply(&mut settings));
+            if settings != get() {
+                replace(settings, false)
+            } else {
+                Ok(())
+            }
+        }
+        Ok(None) => Ok(()),
+        Err(e) => Err(e),
+    };
+    *PROBLEM.lock().unwrap() = res.err().map(|e| e.to_string());
+    GENERATION.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Watches the settings file for edits, for as long as the app is open.
+pub async fn run() {
+    Lazy::force(&SETTINGS);
+    loop {
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        reload();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn valid(change: impl FnOnce(&mut Settings)) -> bool {
+        let mut settings = Settings::default();
+        change(&mut settings);
+        settings.validate().is_ok()
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        assert!(valid(|_| {}));
+    }
+
+    #[test]
+    fn cache_sizes_are_bounded() {
+        assert!(valid(|s| s.memory_cache_mib = 16));
+        assert!(valid(|s| s.memory_cache_mib = 16 * 1024));
+        assert!(!valid(|s| s.memory_cache_mib = 15));
+        assert!(!valid(|s| s.memory_cache_mib = 16 * 1024 + 1));
+        assert!(valid(|s| s.disk_cache_mib = 64));
+        assert!(valid(|s| s.disk_cache_mib = 1024 * 1024));
+        assert!(!valid(|s| s.disk_cache_mib = 63));
+        assert!(!valid(|s| s.disk_cache_mib = 1024 * 1024 + 1));
+    }
+
+    #[test]
+    fn rate_limit_is_what_the_api_allows() {
+        assert!(valid(|s| s.rate_limit = 1));
+        assert!(valid(|s| s.rate_limit = 5));
+        assert!(!valid(|s| s.rate_limit = 0));
+        assert!(!valid(|s| s.rate_limit = 6));
+    }
+
+    #[test]
+    fn languages_are_codes() {
+        assert!(valid(|s| s.languages = vec!["es-la".into(), "ja".into()]));
+        assert!(!valid(|s| s.languages = vec![]));
+        assert!(!valid(|s| s.languages = vec!["".into()]));
+        assert!(!valid(|s| s.languages = vec!["EN".into()]));
+        assert!(!valid(|s| s.languages = vec!["en, fr".into()]));
+    }
+
+    #[test]
+    fn needs_a_content_rating() {
+        assert!(valid(
+            |s| s.content_rating = vec![ContentRating::Pornographic]
+        ));
+        assert!(!valid(|s| s.content_rating = vec![]));
+    }
+
+    #[test]
+    fn download_location_is_a_full_path() {
+        let full = std::env::temp_dir().join("downloads");
+        assert!(valid(|s| s.download_dir = Some(full)));
+        assert!(!valid(|s| s.download_dir = Some("downloads".into())));
+    }
+}
@@ -0,0 +1,63 @@
+//! What the JSON files and per-profile stores have in common.
+
+use serde::{de::DeserializeOwned, Serialize};
+use snafu::ResultExt;
+use std::fs::{self, File};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use crate::endpoint::auth::profile;
+use crate::error::{IoErr, JsonErr};
+use crate::Result;
+
+/// Reads a JSON file, or returns `None` if it's missing or can't be parsed.
+pub fn read_json<T: DeserializeOwned>(path: &Path) -> Option<T> {
+    File::open(path)
+        .ok()
+        .and_then(|f| serde_json::from_reader(f).ok())
+}
+
+/// Writes a JSON file by way of a temporary one, so it's never left half-written.
+pub fn write_json<T: Serialize>(path: &Path, value: &T) -> Result<()> {
+    let tmp = path.with_extension("json.tmp");
+    let json = serde_json::to_vec_pretty(value).context(JsonErr {
+        type_name: pretty_type_name::pretty_type_name::<T>(),
+    })?;
+    fs::write(&tmp, json).context(IoErr { path: &tmp })?;
+    fs::rename(&tmp, path).context(IoErr { path })
+}
+
+/// Something kept for each profile, loaded again whenever the active profile changes.
+pub struct PerProfile<T> {
+    load: fn() -> T,
+    /// The value, along with the profile it belongs to.
+    loaded: Mutex<(String, T)>,
+    reloads: AtomicU64,
+}
+
+impl<T> PerProfile<T> {
+    pub fn new(load: fn() -> T) -> Self {
+        Self {
+            load,
+            loaded: Mutex::new((profile::active(), load())),
+            reloads: AtomicU64::new(0),
+        }
+    }
+
+    /// Runs `f` on the active profile's value, loading it first if the profile was switched.
+    pub fn with<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        let mut loaded = self.loaded.lock().unwrap();
+        let active = profile::active();
+        if loaded.0 != active {
+            *loaded = (active, (self.load)());
+            self.reloads.fetch_add(1, Ordering::Relaxed);
+        }
+        f(&mut loaded.1)
+    }
+
+    /// How many times the value was loaded for another profile.
+    pub fn reloads(&self) -> u64 {
+        self.reloads.load(Ordering::Relaxed)
+    }
+}
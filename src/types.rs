@@ -3,7 +3,7 @@ use serde::Deserialize;
 use std::collections::HashMap;
 use uuid::Uuid;
 
-pub use schema::{MangaAttributes, MangaId, RelationshipType};
+pub use schema::{ChapterAttributes, ChapterId, MangaAttributes, MangaId, RelationshipType};
 
 pub struct Manga {
     pub id: MangaId,
@@ -11,42 +11,78 @@ pub struct Manga {
     pub relationships: HashMap<RelationshipType, Uuid>,
 }
 
+pub struct Chapter {
+    pub id: ChapterId,
+    pub attributes: ChapterAttributes,
+    pub relationships: HashMap<RelationshipType, Uuid>,
+}
+
 #[derive(Deserialize)]
 #[serde(from = "schema::MangaListResponse")]
 pub struct MangaList {
     pub series: HashMap<MangaId, Manga>,
 }
 
+impl From<schema::MangaResponse> for Manga {
+    fn from(resp: schema::MangaResponse) -> Manga {
+        assert_eq!(resp.result, schema::Success::Ok);
+
+        let relationships = resp
+            .relationships
+            .into_iter()
+            .map(|rel| (rel.rel_type, rel.id))
+            .collect();
+
+        let schema::Manga {
+            item_type,
+            id,
+            attributes,
+        } = resp.data;
+        assert_eq!(item_type, schema::ItemType::Manga);
+
+        Manga {
+            id,
+            attributes,
+            relationships,
+        }
+    }
+}
+
 impl From<schema::MangaListResponse> for MangaList {
     fn from(value: schema::MangaListResponse) -> MangaList {
         let series = value
             .results
             .into_iter()
-            .map(|resp: schema::MangaResponse| {
-                assert_eq!(resp.result, schema::Success::Ok);
-
-                let relationships = resp
-                    .relationships
-                    .into_iter()
-                    .map(|rel| (rel.rel_type, rel.id))
-                    .collect();
-
-                let schema::Manga {
-                    item_type,
-                    id,
-                    attributes,
-                } = resp.data;
-                assert_eq!(item_type, schema::ItemType::Manga);
-
-                let manga = Manga {
-                    id,
-                    attributes,
-                    relationships,
-                };
-
-                (id, manga)
+            .map(|resp| {
+                let manga = Manga::from(resp);
+                (manga.id, manga)
             })
             .collect();
         MangaList { series }
     }
 }
+
+impl From<schema::ChapterResponse> for Chapter {
+    fn from(resp: schema::ChapterResponse) -> Chapter {
+        assert_eq!(resp.result, schema::Success::Ok);
+
+        let relationships = resp
+            .relationships
+            .into_iter()
+            .map(|rel| (rel.rel_type, rel.id))
+            .collect();
+
+        let schema::Chapter {
+            item_type,
+            id,
+            attributes,
+        } = resp.data;
+        assert_eq!(item_type, schema::ItemType::Chapter);
+
+        Chapter {
+            id,
+            attributes,
+            relationships,
+        }
+    }
+}
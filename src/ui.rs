@@ -1,5 +1,25 @@
+pub mod history;
+pub mod library;
+pub mod login;
 pub mod manga_list;
 pub mod manga_view;
+pub mod profiles;
+pub mod reader;
+pub mod settings;
+pub mod updates;
 
+use crate::schema::MangaId;
+use druid::Selector;
 use std::time::Duration;
 const REFRESH: Duration = Duration::from_millis(250);
+/// How often to check (and, while offline, probe) connectivity.
+const STATUS_REFRESH: Duration = Duration::from_secs(5);
+
+/// Sent to every window once requests start succeeding again after being offline.
+pub const WENT_ONLINE: Selector = Selector::new("md.went_online");
+/// Sent to every window after switching profiles, so that per-profile state gets reloaded.
+pub const PROFILE_CHANGED: Selector = Selector::new("md.profile_changed");
+/// Sent to every window when chapters of the title are marked read or unread.
+pub const PROGRESS_CHANGED: Selector<MangaId> = Selector::new("md.progress_changed");
+/// Sent to every window when the settings change, whether from the settings window or the file.
+pub const SETTINGS_CHANGED: Selector = Selector::new("md.settings_changed");
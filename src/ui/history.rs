@@ -0,0 +1,249 @@
+use crate::endpoint::history;
+use crate::{async_data::AsyncData, endpoint, types, Message, Result};
+
+use std::sync::Arc;
+
+use chrono::{Duration, Local};
+use tokio::sync::mpsc;
+
+use druid::im;
+use druid::widget::{Button, Controller, CrossAxisAlignment, Flex, Label, List, Scroll};
+use druid::{Data, Env, Event, EventCtx, Lens, Selector, Widget, WidgetExt};
+
+use super::reader::{default_layout, reader_window};
+use super::{PROFILE_CHANGED, PROGRESS_CHANGED, REFRESH};
+
+const RESUME: Selector<u64> = Selector::new("md.history.resume");
+const REMOVE: Selector<u64> = Selector::new("md.history.remove");
+const CLEAR: Selector = Selector::new("md.history.clear");
+
+#[derive(Default, Clone, Data, Lens)]
+pub struct HistoryData {
+    days: im::Vector<HistoryDay>,
+    resuming: bool,
+    error: Option<Arc<String>>,
+}
+
+#[derive(Clone, Data, Lens)]
+struct HistoryDay {
+    label: Arc<String>,
+    entries: im::Vector<HistoryEntry>,
+}
+
+#[derive(Clone, Data, Lens)]
+struct HistoryEntry {
+    id: u64,
+    label: Arc<String>,
+}
+
+impl HistoryData {
+    pub fn new() -> Self {
+        let mut data = Self::default();
+        data.reload();
+        data
+    }
+
+    fn reload(&mut self) {
+        let today = Local::today();
+        let mut days: im::Vector<HistoryDay> = im::Vector::new();
+        let mut current = None;
+        for entry in history::entries() {
+            let read_at = entry.read_at.with_timezone(&Local);
+            let day = read_at.date();
+            if current != Some(day) {
+                let label = if day == today {
+                    "Today".into()
+                } else if day == today - Duration::days(1) {
+                    "Yesterday".into()
+                } else {
+                    day.format("%A, %Y-%m-%d").to_string()
+                };
+                days.push_back(HistoryDay {
+                    label: Arc::new(label),
+                    entries: im::Vector::new(),
+                });
+                current = Some(day);
+            }
+            let label = format!(
+                "{} {}: {}, page {}",
+                read_at.format("%H:%M"),
+                entry.manga_title,
+                entry.chapter_label,
+                entry.page + 1
+            );
+            if let Some(day) = days.back_mut() {
+                day.entries.push_back(HistoryEntry {
+                    id: entry.id,
+                    label: Arc::new(label),
+                });
+            }
+        }
+        self.days = days;
+    }
+}
+
+pub fn history_view(tx: mpsc::UnboundedSender<Message>) -> impl Widget<HistoryData> {
+    let days = List::new(history_day)
+        .with_spacing(8.0)
+        .lens(HistoryData::days);
+    let header = Flex::row()
+        .with_child(Button::new("Clear history").on_click(|ctx, _, _| ctx.submit_command(CLEAR)))
+        .with_child(Label::dynamic(|data: &HistoryData, _env| {
+            if data.resuming {
+                "Opening chapter...".into()
+            } else if let Some(e) = &data.error {
+                e.to_string()
+            } else if data.days.is_empty() {
+                "Nothing read yet".into()
+            } else {
+                String::new()
+            }
+        }));
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(header)
+        .with_flex_child(Scroll::new(days).vertical(), 1.0)
+        .controller(HistoryController::new(tx))
+}
+
+fn history_day() -> impl Widget<HistoryDay> {
+    let entries = List::new(history_entry).lens(HistoryDay::entries);
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(Label::dynamic(|data: &HistoryDay, _env| {
+            (*data.label).clone()
+        }))
+        .with_child(entries)
+}
+
+fn history_entry() -> impl Widget<HistoryEntry> {
+    let resume = Button::new("Resume")
+        .on_click(|ctx, data: &mut HistoryEntry, _env| ctx.submit_command(RESUME.with(data.id)));
+    let remove = Button::new("Delete")
+        .on_click(|ctx, data: &mut HistoryEntry, _env| ctx.submit_command(REMOVE.with(data.id)));
+    Flex::row()
+        .with_child(resume)
+        .with_child(remove)
+        .with_child(Label::dynamic(|data: &HistoryEntry, _env| {
+            (*data.label).clone()
+        }))
+}
+
+/// A title with its chapters in the entry's language.
+type Resumed = (types::Manga, Vec<types::Chapter>);
+
+/// Fetches what the reader needs to pick up where the entry left off.
+async fn load(entry: &history::Entry) -> Result<Resumed> {
+    let manga = endpoint::get_manga(&entry.manga_id).await?;
+    let chapters = endpoint::get_chapters(&entry.manga_id, vec![entry.language.clone()]).await?;
+    Ok((manga, chapters))
+}
+
+struct HistoryController {
+    /// The entry being resumed, with its title and the chapters around it.
+    resume_info: AsyncData<(history::Entry, Result<Resumed>)>,
+    tx: mpsc::UnboundedSender<Message>,
+}
+
+impl HistoryController {
+    fn new(tx: mpsc::UnboundedSender<Message>) -> Self {
+        Self {
+            resume_info: Default::default(),
+            tx,
+        }
+    }
+
+    fn resume(&mut self, ctx: &mut EventCtx<'_, '_>, data: &mut HistoryData, id: u64) {
+        let entry = match history::get(id) {
+            Some(entry) => entry,
+            None => return,
+        };
+        if self.resume_info.is_in_progress() {
+            return;
+        }
+        let fut = async move {
+            let res = load(&entry).await;
+            (entry, res)
+        };
+        self.resume_info.start(&self.tx, fut);
+        data.resuming = true;
+        data.error = None;
+        ctx.request_timer(REFRESH);
+    }
+
+    fn open(
+        &mut self,
+        ctx: &mut EventCtx<'_, '_>,
+        entry: history::Entry,
+        manga: types::Manga,
+        chapters: Vec<types::Chapter>,
+    ) -> bool {
+        let index = match chapters.iter().position(|c| c.id == entry.chapter_id) {
+            Some(index) => index,
+            None => return false,
+        };
+        let window = reader_window(
+            self.tx.clone(),
+            entry.manga_id,
+            Arc::new(entry.manga_title),
+            default_layout(&manga.attributes),
+            Arc::new(chapters),
+            index,
+            Some(entry.page),
+        );
+        ctx.new_window(window);
+        true
+    }
+}
+
+impl<W: Widget<HistoryData>> Controller<HistoryData, W> for HistoryController {
+    fn event(
+        &mut self,
+        child: &mut W,
+        ctx: &mut EventCtx<'_, '_>,
+        event: &Event,
+        data: &mut HistoryData,
+        env: &Env,
+    ) {
+        match event {
+            Event::Command(cmd) if cmd.is(RESUME) => {
+                self.resume(ctx, data, *cmd.get_unchecked(RESUME));
+            }
+            Event::Command(cmd) if cmd.is(REMOVE) => {
+                let res = history::remove(*cmd.get_unchecked(REMOVE));
+                data.error = res.err().map(|e| Arc::new(e.to_string()));
+                data.reload();
+            }
+            Event::Command(cmd) if cmd.is(CLEAR) => {
+                let res = history::clear();
+                data.error = res.err().map(|e| Arc::new(e.to_string()));
+                data.reload();
+            }
+            Event::Command(cmd) if cmd.is(PROGRESS_CHANGED) || cmd.is(PROFILE_CHANGED) => {
+                data.reload();
+            }
+            Event::Timer(_) => match self.resume_info.poll() {
+                Some((entry, res)) => {
+                    data.resuming = false;
+                    data.error = match res {
+                        Ok((manga, chapters)) => {
+                            if self.open(ctx, entry, manga, chapters) {
+                                None
+                            } else {
+                                Some("That chapter is no longer available".into())
+                            }
+                        }
+                        Err(e) => Some(format!("Failed to open the chapter: {}", e)),
+                    }
+                    .map(Arc::new);
+                }
+                None if self.resume_info.is_in_progress() => {
+                    ctx.request_timer(REFRESH);
+                }
+                None => {}
+            },
+            _ => {}
+        }
+        child.event(ctx, event, data, env);
+    }
+}
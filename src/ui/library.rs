@@ -0,0 +1,382 @@
+use crate::endpoint::{self, library};
+use crate::schema::{self, MangaId, ReadingStatus};
+use crate::{async_data::AsyncData, db, language, types, Error, Message, Result};
+
+use std::sync::Arc;
+
+use tokio::sync::mpsc;
+
+use druid::im;
+use druid::piet::ImageFormat;
+use druid::widget::{
+    Button, Controller, CrossAxisAlignment, Flex, Image, Label, LineBreaking, List, Scroll,
+    SizedBox, ViewSwitcher,
+};
+use druid::{
+    Data, Env, Event, EventCtx, ImageBuf, Lens, LifeCycle, LifeCycleCtx, Selector, UpdateCtx,
+    Widget, WidgetExt,
+};
+
+use super::manga_view::manga_window;
+use super::{PROFILE_CHANGED, REFRESH};
+
+/// Reading statuses, in the order the library lists them.
+pub(super) const STATUSES: [(&str, ReadingStatus); 6] = [
+    ("Reading", ReadingStatus::Reading),
+    ("Re-reading", ReadingStatus::ReReading),
+    ("On hold", ReadingStatus::OnHold),
+    ("Plan to read", ReadingStatus::PlanToRead),
+    ("Completed", ReadingStatus::Completed),
+    ("Dropped", ReadingStatus::Dropped),
+];
+
+/// Sent to every window after a title is followed, unfollowed or given a new status.
+pub const LIBRARY_CHANGED: Selector = Selector::new("md.library.changed");
+const SYNC: Selector = Selector::new("md.library.sync");
+
+#[derive(Default, Clone, Data, Lens)]
+pub struct LibraryData {
+    groups: im::Vector<StatusGroup>,
+    loading: bool,
+    error: Option<Arc<String>>,
+}
+
+#[derive(Clone, Data, Lens)]
+struct StatusGroup {
+    label: Arc<String>,
+    titles: im::Vector<ShelfEntry>,
+}
+
+/// Just enough of a title to show it on a shelf. The rest is loaded when it's opened.
+#[derive(Clone, Data, Lens)]
+struct ShelfEntry {
+    manga: Arc<types::Manga>,
+    title: Arc<String>,
+    status: Arc<String>,
+    cover: Arc<Option<ImageBuf>>,
+}
+
+/// Sorts the titles onto shelves by status, keeping covers already loaded for `old` ones.
+fn group(titles: &[Arc<types::Manga>], old: &im::Vector<StatusGroup>) -> im::Vector<StatusGroup> {
+    let saved = library::saved();
+    let cover = |id| {
+        old.iter()
+            .flat_map(|group| group.titles.iter())
+            .find(|entry| entry.manga.id == id)
+            .map_or_else(|| Arc::new(None), |entry| entry.cover.clone())
+    };
+    let statuses = STATUSES
+        .iter()
+        .map(|&(label, status)| (label, Some(status)))
+        .chain(std::iter::once(("Followed", None)));
+    statuses
+        .filter_map(|(label, status)| {
+            let titles: im::Vector<ShelfEntry> = titles
+                .iter()
+                .filter(|manga| saved.statuses.get(&manga.id).copied() == status)
+                .filter(|manga| status.is_some() || saved.follows.contains(&manga.id))
+                .map(|manga| ShelfEntry {
+                    manga: manga.clone(),
+                    title: Arc::new(language::title(&manga.attributes)),
+                    status: Arc::new(if saved.follows.contains(&manga.id) {
+                        format!("{}, followed", label)
+                    } else {
+                        label.to_owned()
+                    }),
+                    cover: cover(manga.id),
+                })
+                .collect();
+            if titles.is_empty() {
+                return None;
+            }
+            Some(StatusGroup {
+                label: Arc::new(format!("{} ({})", label, titles.len())),
+                titles,
+            })
+        })
+        .collect()
+}
+
+pub fn library_view(tx: mpsc::UnboundedSender<Message>) -> impl Widget<LibraryData> {
+    let tx_clone = tx.clone();
+    let groups = List::new(move || status_group(tx_clone.clone()))
+        .with_spacing(8.0)
+        .lens(LibraryData::groups);
+    let header = Flex::row()
+        .with_child(Button::new("Sync").on_click(|ctx, _, _| ctx.submit_command(SYNC)))
+        .with_child(Label::dynamic(|data: &LibraryData, _env| {
+            if data.loading {
+                "Loading library...".into()
+            } else if let Some(e) = &data.error {
+                e.to_string()
+            } else if data.groups.is_empty() {
+                "Nothing followed yet".into()
+            } else {
+                String::new()
+            }
+        }));
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(header)
+        .with_flex_child(Scroll::new(groups), 1.0)
+        .controller(LibraryController::new(tx))
+}
+
+fn status_group(tx: mpsc::UnboundedSender<Message>) -> impl Widget<StatusGroup> {
+    let titles = List::new(move || shelf_entry(tx.clone()))
+        .horizontal()
+        .with_spacing(4.0)
+        .lens(StatusGroup::titles);
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(Label::dynamic(|data: &StatusGroup, _env| {
+            (*data.label).clone()
+        }))
+        .with_child(titles)
+}
+
+fn shelf_entry(tx: mpsc::UnboundedSender<Message>) -> impl Widget<ShelfEntry> {
+    let cover = ViewSwitcher::new(
+        |data: &ShelfEntry, _env| data.cover.clone(),
+        |buf, _data, _env| match &**buf {
+            Some(buf) => Box::new(Image::new(buf.clone())),
+            None => Box::new(SizedBox::empty()),
+        },
+    )
+    .fix_size(128., 182.);
+    let tx_clone = tx.clone();
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(cover)
+        .with_child(
+            Label::dynamic(|data: &ShelfEntry, _env| (*data.title).clone())
+                .with_line_break_mode(LineBreaking::WordWrap),
+        )
+        .with_child(Label::dynamic(|data: &ShelfEntry, _env| {
+            (*data.status).clone()
+        }))
+        .fix_width(128.)
+        .on_click(move |ctx, data: &mut ShelfEntry, _env| {
+            ctx.new_window(manga_window(tx_clone.clone(), &data.manga))
+        })
+        .controller(CoverController {
+            cover_info: Default::default(),
+            tx,
+        })
+}
+
+/// Loads an entry's cover, again whenever the entry is reused for another title.
+struct CoverController {
+    /// The title the cover is for, and the cover.
+    cover_info: AsyncData<(schema::MangaId, Result<image::RgbImage>)>,
+    tx: mpsc::UnboundedSender<Message>,
+}
+
+impl CoverController {
+    fn start(&mut self, data: &ShelfEntry) -> bool {
+        let cover_id = data
+            .manga
+            .relationships
+            .get(&types::RelationshipType::CoverArt)
+            .map(|id| schema::CoverId(*id));
+        match cover_id {
+            Some(cover_id) if !self.cover_info.is_in_progress() => {
+                let manga_id = data.manga.id;
+                let fut = async move {
+                    let cover = endpoint::get_cover_by_id(&manga_id, &cover_id, ".256.jpg").await;
+                    (manga_id, cover)
+                };
+                self.cover_info.start(&self.tx, fut);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+impl<W: Widget<ShelfEntry>> Controller<ShelfEntry, W> for CoverController {
+    fn event(
+        &mut self,
+        child: &mut W,
+        ctx: &mut EventCtx<'_, '_>,
+        event: &Event,
+        data: &mut ShelfEntry,
+        env: &Env,
+    ) {
+        if let Event::Timer(_) = event {
+            match self.cover_info.poll() {
+                // A missing cover isn't worth reporting; it's simply left out.
+                Some((manga_id, Ok(img))) if manga_id == data.manga.id => {
+                    let (w, h) = (img.width(), img.height());
+                    let pixels: Arc<[u8]> = img.into_raw().into();
+                    let buf = ImageBuf::from_raw(pixels, ImageFormat::Rgb, w as usize, h as usize);
+                    data.cover = Arc::new(Some(buf));
+                }
+                // The entry has moved on to another title since.
+                Some((manga_id, _)) if manga_id != data.manga.id && self.start(data) => {
+                    ctx.request_timer(REFRESH);
+                }
+                Some(_) => {}
+                None if self.cover_info.is_in_progress() => {
+                    ctx.request_timer(REFRESH);
+                }
+                None => {}
+            }
+        }
+        child.event(ctx, event, data, env);
+    }
+
+    fn lifecycle(
+        &mut self,
+        child: &mut W,
+        ctx: &mut LifeCycleCtx<'_, '_>,
+        event: &LifeCycle,
+        data: &ShelfEntry,
+        env: &Env,
+    ) {
+        if matches!(event, LifeCycle::WidgetAdded) && self.start(data) {
+            ctx.request_timer(REFRESH);
+        }
+        child.lifecycle(ctx, event, data, env);
+    }
+
+    fn update(
+        &mut self,
+        child: &mut W,
+        ctx: &mut UpdateCtx<'_, '_>,
+        old_data: &ShelfEntry,
+        data: &ShelfEntry,
+        env: &Env,
+    ) {
+        // An entry being in progress picks the new title up when its cover arrives.
+        if old_data.manga.id != data.manga.id && self.start(data) {
+            ctx.request_timer(REFRESH);
+        }
+        child.update(ctx, old_data, data, env);
+    }
+}
+
+struct LibraryController {
+    /// Whether syncing failed, and the titles from whatever was left.
+    library_info: AsyncData<(Option<Error>, Result<Vec<types::Manga>>)>,
+    /// Another load that was asked for while one was running, and whether it should sync.
+    queued: Option<bool>,
+    /// Every title loaded so far, including ones since taken out of the library.
+    titles: Vec<Arc<types::Manga>>,
+    tx: mpsc::UnboundedSender<Message>,
+}
+
+impl LibraryController {
+    fn new(tx: mpsc::UnboundedSender<Message>) -> Self {
+        Self {
+            library_info: Default::default(),
+            queued: None,
+            titles: Vec::new(),
+            tx,
+        }
+    }
+
+    /// Reloads the library's titles, first fetching it again if `sync` is set.
+    fn start(&mut self, ctx: &mut EventCtx<'_, '_>, data: &mut LibraryData, sync: bool) {
+        if self.library_info.is_in_progress() {
+            self.queued = Some(self.queued.unwrap_or(false) || sync);
+            return;
+        }
+        if data.groups.is_empty() {
+            // Show what was saved while the real thing loads.
+            self.titles = library::saved_titles().into_iter().map(Arc::new).collect();
+            data.groups = group(&self.titles, &data.groups);
+        }
+        let fut = async move {
+            let sync_err = if sync {
+                library::sync().await.err()
+            } else {
+                None
+            };
+            let ids = library::saved().ids();
+            (sync_err, library::titles(&ids).await)
+        };
+        self.library_info.start(&self.tx, fut);
+        data.loading = true;
+        ctx.request_timer(REFRESH);
+    }
+
+    /// Moves titles between shelves after a change made here, only loading titles
+    /// that are new to the library.
+    fn regroup(&mut self, ctx: &mut EventCtx<'_, '_>, data: &mut LibraryData) {
+        let missing: Vec<MangaId> = library::saved()
+            .ids()
+            .into_iter()
+            .filter(|id| !self.titles.iter().any(|manga| manga.id == *id))
+            .collect();
+        let found = db::manga(&missing).unwrap_or_default();
+        let complete = found.len() == missing.len();
+        self.titles.extend(found.into_iter().map(Arc::new));
+        data.groups = group(&self.titles, &data.groups);
+        if !complete {
+            self.start(ctx, data, false);
+        }
+    }
+}
+
+impl<W: Widget<LibraryData>> Controller<LibraryData, W> for LibraryController {
+    fn event(
+        &mut self,
+        child: &mut W,
+        ctx: &mut EventCtx<'_, '_>,
+        event: &Event,
+        data: &mut LibraryData,
+        env: &Env,
+    ) {
+        match event {
+            Event::Command(cmd) if cmd.is(SYNC) => {
+                self.start(ctx, data, true);
+            }
+            Event::Command(cmd) if cmd.is(PROFILE_CHANGED) => {
+                data.groups.clear();
+                self.start(ctx, data, true);
+            }
+            Event::Command(cmd) if cmd.is(LIBRARY_CHANGED) => {
+                self.regroup(ctx, data);
+            }
+            Event::Timer(_) => match self.library_info.poll() {
+                Some((sync_err, titles)) => {
+                    data.loading = false;
+                    data.error = match (&sync_err, &titles) {
+                        (_, Err(e)) => Some(format!("Failed to load the library: {}", e)),
+                        (Some(e), Ok(_)) => Some(format!("Showing the saved library: {}", e)),
+                        (None, Ok(_)) => None,
+                    }
+                    .map(Arc::new);
+                    if let Ok(titles) = titles {
+                        self.titles = titles.into_iter().map(Arc::new).collect();
+                        data.groups = group(&self.titles, &data.groups);
+                    }
+                    if let Some(sync) = self.queued.take() {
+                        self.start(ctx, data, sync);
+                    }
+                }
+                None if self.library_info.is_in_progress() => {
+                    ctx.request_timer(REFRESH);
+                }
+                None => {}
+            },
+            _ => {}
+        }
+        child.event(ctx, event, data, env);
+    }
+
+    fn lifecycle(
+        &mut self,
+        child: &mut W,
+        ctx: &mut LifeCycleCtx<'_, '_>,
+        event: &LifeCycle,
+        data: &LibraryData,
+        env: &Env,
+    ) {
+        if matches!(event, LifeCycle::WidgetAdded) {
+            ctx.submit_command(SYNC);
+        }
+        child.lifecycle(ctx, event, data, env);
+    }
+}
@@ -0,0 +1,231 @@
+use crate::{async_data::AsyncData, endpoint::auth, Message, Result};
+
+use std::sync::Arc;
+
+use futures::FutureExt;
+use tokio::sync::mpsc;
+
+use druid::widget::{Button, Controller, CrossAxisAlignment, Either, Flex, Label, TextBox};
+use druid::{
+    commands, Application, Data, Env, Event, EventCtx, HotKey, KbKey, Lens, Selector, SysMods,
+    Widget, WidgetExt, WindowDesc,
+};
+
+use super::manga_list::MangaListData;
+use super::REFRESH;
+
+#[derive(Default, Clone, Data, Lens)]
+pub struct LoginData {
+    username: String,
+    password: String,
+    /// Protects the saved session when there's no system keyring, or when the user prefers it.
+    passphrase: String,
+    /// Who is logged in, if anyone.
+    pub(super) user: Option<Arc<String>>,
+    /// Whether the saved session needs its passphrase before it can be used.
+    pub(super) locked: bool,
+    error: Option<Arc<String>>,
+    in_progress: bool,
+}
+
+impl LoginData {
+    pub fn new() -> Self {
+        Self {
+            user: auth::username().map(Arc::new),
+            locked: auth::is_locked(),
+            ..Default::default()
+        }
+    }
+}
+
+const SUBMIT: Selector = Selector::new("md.login.submit");
+
+/// Shows a secret as bullets. Edits to what's shown are thrown away; `SecretEntry` makes
+/// them to the secret itself.
+struct Bullets;
+
+impl Lens<String, String> for Bullets {
+    fn with<V, F: FnOnce(&String) -> V>(&self, data: &String, f: F) -> V {
+        f(&"\u{2022}".repeat(data.chars().count()))
+    }
+
+    fn with_mut<V, F: FnOnce(&mut String) -> V>(&self, data: &mut String, f: F) -> V {
+        f(&mut "\u{2022}".repeat(data.chars().count()))
+    }
+}
+
+/// Types into and deletes from the end of a secret, since where the caret is in the
+/// bullets can't be trusted to say where it is in the secret.
+struct SecretEntry;
+
+impl<W: Widget<String>> Controller<String, W> for SecretEntry {
+    fn event(
+        &mut self,
+        child: &mut W,
+        ctx: &mut EventCtx<'_, '_>,
+        event: &Event,
+        data: &mut String,
+        env: &Env,
+    ) {
+        let paste = HotKey::new(SysMods::Cmd, "v");
+        match event {
+            Event::KeyDown(key) if paste.matches(key) => {
+                if let Some(text) = Application::global().clipboard().get_string() {
+                    data.push_str(text.trim_end_matches(&['\r', '\n'][..]));
+                }
+                ctx.set_handled();
+            }
+            Event::Paste(clipboard) => {
+                if let Some(text) = clipboard.get_string() {
+                    data.push_str(text.trim_end_matches(&['\r', '\n'][..]));
+                }
+                ctx.set_handled();
+            }
+            Event::KeyDown(key) => match &key.key {
+                KbKey::Character(text) if !key.mods.ctrl() && !key.mods.meta() => {
+                    data.push_str(text);
+                    ctx.set_handled();
+                }
+                KbKey::Backspace if key.mods.ctrl() || key.mods.alt() => {
+                    data.clear();
+                    ctx.set_handled();
+                }
+                KbKey::Backspace => {
+                    data.pop();
+                    ctx.set_handled();
+                }
+                // Everything else, e.g. Tab to move on, works as in any other text box.
+                _ => child.event(ctx, event, data, env),
+            },
+            _ => child.event(ctx, event, data, env),
+        }
+    }
+}
+
+pub fn login_window(tx: mpsc::UnboundedSender<Message>) -> WindowDesc<MangaListData> {
+    WindowDesc::new(move || login_dialog(tx).lens(MangaListData::login))
+        .title("Log in to MangaDex")
+        .window_size((360., 260.))
+}
+
+fn masked_field() -> impl Widget<String> {
+    TextBox::new()
+        .lens(Bullets)
+        .controller(SecretEntry)
+        .expand_width()
+}
+
+fn login_form() -> impl Widget<LoginData> {
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(Label::new("Username"))
+        .with_child(TextBox::new().lens(LoginData::username).expand_width())
+        .with_child(Label::new("Password"))
+        .with_child(masked_field().lens(LoginData::password))
+        .with_child(Label::new(
+            "Passphrase (leave empty to use the system keyring)",
+        ))
+        .with_child(masked_field().lens(LoginData::passphrase))
+}
+
+fn unlock_form() -> impl Widget<LoginData> {
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(Label::new(
+            "The saved session is protected by a passphrase.",
+        ))
+        .with_child(Label::new("Passphrase"))
+        .with_child(masked_field().lens(LoginData::passphrase))
+}
+
+fn login_dialog(tx: mpsc::UnboundedSender<Message>) -> impl Widget<LoginData> {
+    let submit = Button::dynamic(|data: &LoginData, _env| {
+        if data.locked { "Unlock" } else { "Log in" }.into()
+    })
+    .on_click(|ctx, _data: &mut LoginData, _env| ctx.submit_command(SUBMIT));
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(Either::new(
+            |data: &LoginData, _env| data.locked,
+            unlock_form(),
+            login_form(),
+        ))
+        .with_spacer(8.0)
+        .with_child(submit)
+        .with_child(Label::dynamic(|data: &LoginData, _env| {
+            if data.in_progress && data.locked {
+                "Unlocking...".into()
+            } else if data.in_progress {
+                "Logging in...".into()
+            } else {
+                data.error.as_deref().cloned().unwrap_or_default()
+            }
+        }))
+        .padding(8.0)
+        .controller(LoginController::new(tx))
+}
+
+struct LoginController {
+    login_info: AsyncData<Result<()>>,
+    tx: mpsc::UnboundedSender<Message>,
+}
+
+impl LoginController {
+    fn new(tx: mpsc::UnboundedSender<Message>) -> Self {
+        Self {
+            login_info: Default::default(),
+            tx,
+        }
+    }
+}
+
+impl<W: Widget<LoginData>> Controller<LoginData, W> for LoginController {
+    fn event(
+        &mut self,
+        child: &mut W,
+        ctx: &mut EventCtx<'_, '_>,
+        event: &Event,
+        data: &mut LoginData,
+        env: &Env,
+    ) {
+        match event {
+            Event::Command(cmd) if cmd.is(SUBMIT) => {
+                let fut = if data.locked {
+                    Some(auth::unlock(data.passphrase.clone()).boxed())
+                } else if !data.username.trim().is_empty() {
+                    let (username, password) = (data.username.clone(), data.password.clone());
+                    Some(auth::login(username, password, data.passphrase.clone()).boxed())
+                } else {
+                    None
+                };
+                if let (Some(fut), false) = (fut, self.login_info.is_in_progress()) {
+                    self.login_info.start(&self.tx, fut);
+                    data.in_progress = true;
+                    data.error = None;
+                    ctx.request_timer(REFRESH);
+                }
+                ctx.set_handled();
+            }
+            Event::Timer(_) => match self.login_info.poll() {
+                Some(Ok(())) => {
+                    data.in_progress = false;
+                    data.password.clear();
+                    data.passphrase.clear();
+                    data.locked = false;
+                    data.user = auth::username().map(Arc::new);
+                    ctx.submit_command(commands::CLOSE_WINDOW);
+                }
+                Some(Err(e)) => {
+                    data.in_progress = false;
+                    data.error = Some(Arc::new(e.to_string()));
+                }
+                None if self.login_info.is_in_progress() => {
+                    ctx.request_timer(REFRESH);
+                }
+                None => {}
+            },
+            _ => {}
+        }
+        child.event(ctx, event, data, env);
+    }
+}
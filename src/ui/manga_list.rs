@@ -1,32 +1,173 @@
-use crate::{async_data::AsyncData, endpoint, schema, types, Message, Result};
+use crate::async_data::{self, AsyncData};
+use crate::endpoint::library;
+use crate::{db, endpoint, export, language, schema, settings, types, Message, Result};
 
 use std::sync::Arc;
 
+use futures::stream::{BoxStream, StreamExt};
 use tokio::sync::mpsc;
 
 use druid::im;
-use druid::widget::{Controller, List};
-use druid::{Env, Event, EventCtx, LifeCycle, LifeCycleCtx, Widget, WidgetExt};
+use druid::widget::{
+    Button, Controller, CrossAxisAlignment, Either, Flex, Label, List, Scroll, SizedBox, Tabs,
+};
+use druid::{
+    Env, Event, EventCtx, LifeCycle, LifeCycleCtx, Selector, Target, TimerToken, Widget, WidgetExt,
+};
 
+use super::history::{history_view, HistoryData};
+use super::library::{library_view, LibraryData};
+use super::login::{login_window, LoginData};
 use super::manga_view::{manga_view, MangaViewData};
-use super::REFRESH;
+use super::profiles::{profiles_window, ProfilesData};
+use super::reader::default_layout;
+use super::settings::{settings_window, SettingsData};
+use super::updates::{updates_view, UpdatesData};
+use super::{PROFILE_CHANGED, REFRESH, SETTINGS_CHANGED, STATUS_REFRESH, WENT_ONLINE};
+
+const PAGE_SIZE: usize = 10;
+
+pub const LOAD_MORE: Selector = Selector::new("md.manga_list.load_more");
+const FORGET_ACCOUNT: Selector = Selector::new("md.manga_list.forget_account");
+
+type MangaStream = BoxStream<'static, Vec<Result<types::Manga>>>;
 
 #[derive(Default, Clone, druid::Data, druid::Lens)]
 pub struct MangaListData {
     titles: im::Vector<MangaViewData>,
+    offline: bool,
+    error: Option<Arc<String>>,
+    pub(super) login: LoginData,
+    forget_error: Option<Arc<String>>,
+    pub(super) profiles: ProfilesData,
+    pub(super) settings: SettingsData,
+    library: LibraryData,
+    updates: UpdatesData,
+    history: HistoryData,
+}
+
+impl MangaListData {
+    pub fn new() -> Self {
+        // Whatever was seen last time fills in until the listing arrives.
+        let saved = db::recent_manga(PAGE_SIZE).unwrap_or_default();
+        Self {
+            titles: saved.iter().map(view_data).collect(),
+            login: LoginData::new(),
+            profiles: ProfilesData::new(),
+            settings: SettingsData::new(),
+            updates: UpdatesData::new(),
+            history: HistoryData::new(),
+            ..Default::default()
+        }
+    }
+}
+
+fn status_text(data: &MangaListData, _env: &Env) -> String {
+    if data.offline {
+        "Offline: showing saved data only. Chapters marked (saved) can still be read.".into()
+    } else if let Some(e) = &data.forget_error {
+        format!("Failed to remove the saved session: {}", e)
+    } else if let Some(e) = &data.error {
+        format!("Failed to load more titles: {}", e)
+    } else {
+        String::new()
+    }
 }
 
 pub fn manga_list(tx: mpsc::UnboundedSender<Message>) -> impl Widget<MangaListData> {
     let tx_clone = tx.clone();
-    List::new(move || manga_view(tx_clone.clone()))
+    let list = List::new(move || manga_view(tx_clone.clone()))
         .horizontal()
         .with_spacing(4.0)
-        .lens(MangaListData::titles)
+        .lens(MangaListData::titles);
+    let more = Button::new("More").on_click(|ctx, _, _| ctx.submit_command(LOAD_MORE));
+
+    let tx_settings = tx.clone();
+    let tx_clone = tx.clone();
+    let account = Flex::row()
+        .with_child(Label::dynamic(|data: &MangaListData, _env| {
+            format!("Profile: {}", data.profiles.active)
+        }))
+        .with_child(Button::new("Profiles").on_click(|ctx, _, _| ctx.new_window(profiles_window())))
+        .with_child(
+            Button::new("Settings")
+                .on_click(move |ctx, _, _| ctx.new_window(settings_window(tx_settings.clone()))),
+        )
+        .with_spacer(8.0)
+        .with_child(Label::dynamic(|data: &MangaListData, _env| {
+            match &data.login.user {
+                Some(user) => format!("Logged in as {}", user),
+                None if data.login.locked => "Saved session locked".into(),
+                None => "Not logged in".into(),
+            }
+        }))
+        .with_child(
+            Button::dynamic(|data: &MangaListData, _env| {
+                if data.login.locked {
+                    "Unlock"
+                } else {
+                    "Log in"
+                }
+                .into()
+            })
+            .on_click(move |ctx, _, _| ctx.new_window(login_window(tx_clone.clone()))),
+        )
+        .with_child(Either::new(
+            |data: &MangaListData, _env| data.login.user.is_some() || data.login.locked,
+            Button::new("Forget this account")
+                .on_click(|ctx, _, _| ctx.submit_command(FORGET_ACCOUNT)),
+            SizedBox::empty(),
+        ));
+    let header = Flex::row()
+        .with_flex_child(Label::dynamic(status_text).expand_width(), 1.0)
+        .with_child(Label::dynamic(|data: &MangaListData, _env| {
+            match data.updates.unread {
+                0 => String::new(),
+                1 => "1 new chapter".into(),
+                n => format!("{} new chapters", n),
+            }
+        }))
+        .with_spacer(8.0)
+        .with_child(account);
+
+    let row = Flex::row().with_child(list).with_child(more);
+    let tabs = Tabs::new()
+        .with_tab("Browse", Scroll::new(row).horizontal())
+        .with_tab(
+            "Library",
+            library_view(tx.clone()).lens(MangaListData::library),
+        )
+        .with_tab(
+            "Updates",
+            updates_view(tx.clone()).lens(MangaListData::updates),
+        )
+        .with_tab(
+            "History",
+            history_view(tx.clone()).lens(MangaListData::history),
+        );
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(header)
+        .with_flex_child(tabs, 1.0)
         .controller(MangaListController::new(tx))
 }
 
 struct MangaListController {
-    listing_info: AsyncData<Result<types::MangaList>>,
+    listing_info: AsyncData<(Option<Vec<Result<types::Manga>>>, MangaStream)>,
+    pages: Option<MangaStream>,
+    failed: bool,
+    /// Whether the titles shown were saved last time, and are still to be replaced.
+    cached: bool,
+    /// The content ratings the listing was started with.
+    content_rating: Vec<schema::ContentRating>,
+    /// Set when the content ratings change mid-page, so that page gets thrown away.
+    restart: bool,
+    settings_generation: u64,
+    status_timer: TimerToken,
+    forget_info: AsyncData<Result<()>>,
+    session_info: AsyncData<()>,
+    /// Set when the profile changes while its old session is still loading.
+    reload_session: bool,
     tx: mpsc::UnboundedSender<Message>,
 }
 
@@ -34,9 +175,105 @@ impl MangaListController {
     pub fn new(tx: mpsc::UnboundedSender<Message>) -> Self {
         Self {
             listing_info: Default::default(),
+            pages: None,
+            failed: false,
+            cached: false,
+            content_rating: Vec::new(),
+            restart: false,
+            settings_generation: settings::generation(),
+            status_timer: TimerToken::INVALID,
+            forget_info: Default::default(),
+            session_info: Default::default(),
+            reload_session: false,
             tx,
         }
     }
+
+    /// Reads the active profile's saved session, or rereads it once the current read is done.
+    fn load_session(&mut self) {
+        if self.session_info.is_in_progress() {
+            self.reload_session = true;
+        } else {
+            self.session_info.start(&self.tx, endpoint::auth::load());
+        }
+    }
+
+    /// (Re)starts the listing at `offset`, e.g. after a failed page.
+    fn start_listing(&mut self, offset: usize) {
+        self.content_rating = settings::get().content_rating;
+        let query = schema::MangaListQuery {
+            offset: Some(offset as u16),
+            content_rating: Some(self.content_rating.clone()),
+            ..Default::default()
+        };
+        let stream = endpoint::search_manga_all(&query).chunks(PAGE_SIZE).boxed();
+        self.fetch_next(stream);
+    }
+
+    /// Lists from the start again, leaving the current titles up until the first page arrives.
+    fn restart_listing(&mut self) {
+        self.pages = None;
+        self.cached = true;
+        self.start_listing(0);
+    }
+
+    /// How many titles have been listed so far, not counting saved ones still on show.
+    fn listed(&self, data: &MangaListData) -> usize {
+        if self.cached {
+            0
+        } else {
+            data.titles.len()
+        }
+    }
+
+    fn fetch_next(&mut self, mut stream: MangaStream) {
+        self.failed = false;
+        let fut = async move {
+            let chunk = stream.next().await;
+            (chunk, stream)
+        };
+        self.listing_info.start(&self.tx, fut);
+    }
+}
+
+fn creators(item: &types::Manga) -> Vec<schema::AuthorId> {
+    let mut ids = Vec::new();
+    for rel_type in &[
+        types::RelationshipType::Author,
+        types::RelationshipType::Artist,
+    ] {
+        if let Some(id) = item.relationships.get(rel_type) {
+            let id = schema::AuthorId(*id);
+            if !ids.contains(&id) {
+                ids.push(id);
+            }
+        }
+    }
+    ids
+}
+
+pub(super) fn view_data(item: &types::Manga) -> MangaViewData {
+    MangaViewData {
+        id: item.id.into(),
+        title: language::title(&item.attributes).into(),
+        cover_id: item
+            .relationships
+            .get(&types::RelationshipType::CoverArt)
+            .map(|id| Arc::new(schema::CoverId(*id))),
+        creators: Arc::new(creators(item)),
+        cover_buf: Arc::new(None),
+        chapters: im::Vector::new(),
+        layout: default_layout(&item.attributes),
+        chapters_failed: false,
+        attributes: Arc::new(item.attributes.clone()),
+        export_format: export::Format::Cbz,
+        pdf_reencode: false,
+        pdf_quality: 85.0,
+        export_status: None,
+        following: library::is_following(&item.id),
+        reading_status: library::reading_status(&item.id),
+        library_error: None,
+    }
 }
 
 impl<W: Widget<MangaListData>> Controller<MangaListData, W> for MangaListController {
@@ -48,23 +285,113 @@ impl<W: Widget<MangaListData>> Controller<MangaListData, W> for MangaListControl
         data: &mut MangaListData,
         env: &Env,
     ) {
-        if matches!(event, Event::Timer(_)) {
-            if let Some(response) = self.listing_info.poll() {
-                for item in response.unwrap().series.values() {
-                    let series = MangaViewData {
-                        id: item.id.into(),
-                        title: item.attributes.title["en"].clone().into(),
-                        cover_id: item
-                            .relationships
-                            .get(&types::RelationshipType::CoverArt)
-                            .map(|id| Arc::new(schema::CoverId(*id))),
-                        cover_buf: Arc::new(None),
-                    };
-                    data.titles.push_back(series);
+        match event {
+            Event::Timer(token) if *token == self.status_timer => {
+                let online = endpoint::is_online();
+                if online && data.offline {
+                    ctx.submit_command(WENT_ONLINE.to(Target::Global));
+                    if self.failed && !self.listing_info.is_in_progress() {
+                        self.start_listing(self.listed(data));
+                        ctx.request_timer(REFRESH);
+                    }
+                } else if !online {
+                    async_data::detach(&self.tx, endpoint::ping());
+                }
+                data.offline = !online;
+                if settings::generation() != self.settings_generation {
+                    // The settings file was edited.
+                    ctx.submit_command(SETTINGS_CHANGED.to(Target::Global));
                 }
-            } else {
+                self.status_timer = ctx.request_timer(STATUS_REFRESH);
+            }
+            Event::Timer(_) => {
+                match self.forget_info.poll() {
+                    Some(res) => data.forget_error = res.err().map(|e| Arc::new(e.to_string())),
+                    None if self.forget_info.is_in_progress() => {
+                        ctx.request_timer(REFRESH);
+                    }
+                    None => {}
+                }
+                match self.session_info.poll() {
+                    Some(()) if std::mem::take(&mut self.reload_session) => {
+                        self.load_session();
+                        ctx.request_timer(REFRESH);
+                    }
+                    Some(()) => data.login = LoginData::new(),
+                    None if self.session_info.is_in_progress() => {
+                        ctx.request_timer(REFRESH);
+                    }
+                    None => {}
+                }
+                if let Some((chunk, stream)) = self.listing_info.poll() {
+                    // A page from before the content ratings changed is no use,
+                    // and a `None` chunk means the listing is exhausted.
+                    if std::mem::take(&mut self.restart) {
+                        self.restart_listing();
+                        ctx.request_timer(REFRESH);
+                    } else if let Some(chunk) = chunk {
+                        self.pages = Some(stream);
+                        data.error = None;
+                        if std::mem::take(&mut self.cached) {
+                            data.titles.clear();
+                        }
+                        for item in chunk {
+                            match item {
+                                Ok(manga) => data.titles.push_back(view_data(&manga)),
+                                Err(e) => {
+                                    // The stream ends after an error, so it's no use anymore.
+                                    self.pages = None;
+                                    self.failed = true;
+                                    data.error = Some(Arc::new(e.to_string()));
+                                }
+                            }
+                        }
+                    }
+                    data.offline = !endpoint::is_online();
+                } else if self.listing_info.is_in_progress() {
+                    ctx.request_timer(REFRESH);
+                }
+            }
+            Event::Command(cmd) if cmd.is(SETTINGS_CHANGED) => {
+                // Unsaved edits in the Settings window are left alone.
+                if !data.settings.is_edited() {
+                    data.settings = SettingsData::new();
+                }
+                self.settings_generation = settings::generation();
+                if settings::get().content_rating != self.content_rating {
+                    if self.listing_info.is_in_progress() {
+                        self.restart = true;
+                    } else {
+                        self.restart_listing();
+                        ctx.request_timer(REFRESH);
+                    }
+                }
+            }
+            Event::Command(cmd) if cmd.is(PROFILE_CHANGED) => {
+                data.login = LoginData::new();
+                data.forget_error = None;
+                self.load_session();
+                ctx.request_timer(REFRESH);
+                // Each profile has its own languages and content ratings.
+                ctx.submit_command(SETTINGS_CHANGED.to(Target::Global));
+            }
+            Event::Command(cmd) if cmd.is(FORGET_ACCOUNT) && !self.forget_info.is_in_progress() => {
+                // The session is gone as far as the UI is concerned; the rest is cleanup.
+                data.login.user = None;
+                data.login.locked = false;
+                self.forget_info.start(&self.tx, endpoint::auth::forget());
                 ctx.request_timer(REFRESH);
             }
+            Event::Command(cmd) if cmd.is(LOAD_MORE) => {
+                if let Some(stream) = self.pages.take() {
+                    self.fetch_next(stream);
+                    ctx.request_timer(REFRESH);
+                } else if self.failed && !self.listing_info.is_in_progress() {
+                    self.start_listing(self.listed(data));
+                    ctx.request_timer(REFRESH);
+                }
+            }
+            _ => {}
         }
         child.event(ctx, event, data, env);
     }
@@ -78,9 +405,11 @@ impl<W: Widget<MangaListData>> Controller<MangaListData, W> for MangaListControl
         env: &Env,
     ) {
         if matches!(event, LifeCycle::WidgetAdded) {
-            let fut = endpoint::get_json("https://api.mangadex.org/manga");
-            self.listing_info.start(&self.tx, fut);
+            self.cached = !data.titles.is_empty();
+            self.start_listing(0);
+            self.load_session();
             ctx.request_timer(REFRESH);
+            self.status_timer = ctx.request_timer(STATUS_REFRESH);
         }
         child.lifecycle(ctx, event, data, env);
     }
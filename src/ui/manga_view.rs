@@ -1,25 +1,177 @@
-use crate::{async_data::AsyncData, endpoint, schema, Message, Result};
+use crate::endpoint::download::{self, JobState};
+use crate::endpoint::{auth, library, progress};
+use crate::error::NotDownloadedErr;
+use crate::{
+    async_data::AsyncData, db, endpoint, export, language, schema, settings, types, Message, Result,
+};
 
+use std::path::PathBuf;
 use std::sync::Arc;
 
 use tokio::sync::mpsc;
 
 use druid::im;
 use druid::piet::ImageFormat;
-use druid::widget::{Controller, Flex, Image, Label};
+use druid::widget::{
+    Button, Checkbox, Controller, CrossAxisAlignment, Flex, Image, Label, List, RadioGroup, Scroll,
+    SizedBox, Slider, ViewSwitcher,
+};
 use druid::{
-    Data, Env, Event, EventCtx, ImageBuf, Lens, LifeCycle, LifeCycleCtx, UpdateCtx, Widget,
-    WidgetExt,
+    BoxConstraints, Data, Env, Event, EventCtx, ImageBuf, LayoutCtx, Lens, LifeCycle, LifeCycleCtx,
+    PaintCtx, Point, Selector, Size, Target, TimerToken, UpdateCtx, Widget, WidgetExt, WidgetPod,
+    WindowDesc,
 };
 
-use super::REFRESH;
+use super::library::{LIBRARY_CHANGED, STATUSES};
+use super::manga_list::{view_data, MangaListData};
+use super::reader::{default_layout, reader_window, saved_layout, Direction, ReaderLayout};
+use super::{PROFILE_CHANGED, PROGRESS_CHANGED, REFRESH, SETTINGS_CHANGED, WENT_ONLINE};
 
 #[derive(Clone, Data, Lens)]
 pub struct MangaViewData {
     pub(super) id: Arc<schema::MangaId>,
     pub(super) title: Arc<String>,
     pub(super) cover_id: Option<Arc<schema::CoverId>>,
+    /// The manga's author and artist.
+    pub(super) creators: Arc<Vec<schema::AuthorId>>,
     pub(super) cover_buf: Arc<Option<ImageBuf>>,
+    pub(super) chapters: im::Vector<ChapterData>,
+    pub(super) layout: ReaderLayout,
+    pub(super) chapters_failed: bool,
+    pub(super) attributes: Arc<types::MangaAttributes>,
+    pub(super) export_format: export::Format,
+    /// Whether PDF pages are re-encoded as JPEG at `pdf_quality` to save space.
+    pub(super) pdf_reencode: bool,
+    pub(super) pdf_quality: f64,
+    pub(super) export_status: Option<Arc<String>>,
+    pub(super) following: bool,
+    pub(super) reading_status: Option<schema::ReadingStatus>,
+    pub(super) library_error: Option<Arc<String>>,
+}
+
+#[derive(Clone, Data, Lens)]
+pub struct ChapterData {
+    pub(super) id: Arc<schema::ChapterId>,
+    pub(super) label: Arc<String>,
+    /// Whether every page is stored locally, so the chapter can be read offline.
+    pub(super) saved: bool,
+    pub(super) download: DownloadStatus,
+    pub(super) read: bool,
+    /// The page to resume at, if the chapter was started but not finished.
+    pub(super) resume_page: Option<usize>,
+}
+
+impl ChapterData {
+    fn set_progress(&mut self, progress: Option<&progress::ChapterProgress>) {
+        self.read = progress.is_some_and(|p| p.read);
+        self.resume_page = progress
+            .filter(|p| !p.read && p.last_page > 0)
+            .map(|p| p.last_page);
+    }
+}
+
+/// A chapter's place in the download queue, as shown next to it.
+#[derive(Clone, PartialEq, Data)]
+pub enum DownloadStatus {
+    NotQueued,
+    Queued(usize, usize),
+    Running(usize, usize),
+    Paused(usize, usize),
+    Done,
+    Failed(Arc<String>),
+}
+
+impl DownloadStatus {
+    fn of(chapter_id: &schema::ChapterId) -> Self {
+        let job = match download::status(chapter_id) {
+            Some(job) => job,
+            None => return Self::NotQueued,
+        };
+        let (done, total) = (job.downloaded(), job.page_count());
+        match job.state {
+            JobState::Queued => Self::Queued(done, total),
+            JobState::Running => Self::Running(done, total),
+            JobState::Paused => Self::Paused(done, total),
+            JobState::Done => Self::Done,
+            JobState::Failed(e) => Self::Failed(Arc::new(e)),
+        }
+    }
+
+    /// Whether the status is still going to change by itself.
+    fn is_active(&self) -> bool {
+        matches!(self, Self::Queued(..) | Self::Running(..))
+    }
+
+    fn text(&self) -> String {
+        match self {
+            Self::NotQueued => String::new(),
+            Self::Queued(done, total) => format!("Queued ({}/{})", done, total),
+            Self::Running(done, total) => format!("Downloading {}/{}", done, total),
+            Self::Paused(done, total) => format!("Paused ({}/{})", done, total),
+            Self::Done => "Downloaded".into(),
+            Self::Failed(e) => format!("Download failed: {}", e),
+        }
+    }
+}
+
+pub const OPEN_CHAPTER: Selector<schema::ChapterId> = Selector::new("md.manga_view.open_chapter");
+pub const DOWNLOAD_CHAPTER: Selector<schema::ChapterId> =
+    Selector::new("md.manga_view.download_chapter");
+/// Downloads every chapter in the same volume as the given one.
+pub const DOWNLOAD_VOLUME: Selector<schema::ChapterId> =
+    Selector::new("md.manga_view.download_volume");
+pub const EXPORT_CHAPTER: Selector<schema::ChapterId> =
+    Selector::new("md.manga_view.export_chapter");
+/// Exports every chapter in the same volume as the given one.
+pub const EXPORT_VOLUME: Selector<schema::ChapterId> = Selector::new("md.manga_view.export_volume");
+/// Sent after the download queue was changed from the UI, so progress gets polled again.
+const DOWNLOADS_CHANGED: Selector = Selector::new("md.manga_view.downloads_changed");
+/// Marks a chapter read or unread. The same title may be shown more than once,
+/// so this says which rather than toggling.
+const MARK_READ: Selector<(schema::ChapterId, bool)> = Selector::new("md.manga_view.mark_read");
+
+pub(super) fn chapter_label(chapter: &types::Chapter) -> String {
+    let attrs = &chapter.attributes;
+    let mut label = String::new();
+    if let Some(volume) = &attrs.volume {
+        label += &format!("Vol. {} ", volume);
+    }
+    match &attrs.chapter {
+        Some(num) => label += &format!("Ch. {}", num),
+        None => label += "Oneshot",
+    }
+    if !attrs.title.is_empty() {
+        label += &format!(" - {}", attrs.title);
+    }
+    label
+}
+
+/// The languages chapters are listed in.
+fn chapter_languages() -> Vec<schema::Language> {
+    language::preferred()
+}
+
+fn find_chapter<'a>(chapters: &'a [types::Chapter], id: &schema::ChapterId) -> &'a types::Chapter {
+    chapters
+        .iter()
+        .find(|c| c.id == *id)
+        .expect("Exported chapter isn't listed")
+}
+
+impl From<&types::Chapter> for ChapterData {
+    fn from(chapter: &types::Chapter) -> Self {
+        let pages = endpoint::ChapterPages::new(chapter, download::quality_for(&chapter.id));
+        let mut data = Self {
+            id: Arc::new(chapter.id),
+            label: Arc::new(chapter_label(chapter)),
+            saved: pages.is_saved(),
+            download: DownloadStatus::of(&chapter.id),
+            read: false,
+            resume_page: None,
+        };
+        data.set_progress(progress::get(&chapter.id).as_ref());
+        data
+    }
 }
 
 fn arc_to_owned<D: AsRef<str>>(data: &Arc<D>, _env: &Env) -> String {
@@ -28,13 +180,242 @@ fn arc_to_owned<D: AsRef<str>>(data: &Arc<D>, _env: &Env) -> String {
 
 pub fn manga_view(tx: mpsc::UnboundedSender<Message>) -> impl Widget<MangaViewData> {
     let title_label = Label::dynamic(arc_to_owned).lens(MangaViewData::title);
+    let cover = ViewSwitcher::new(
+        |data: &MangaViewData, _env| data.cover_buf.clone(),
+        |buf, _data, _env| match &**buf {
+            Some(buf) => Box::new(Image::new(buf.clone())),
+            None => Box::new(SizedBox::empty()),
+        },
+    );
+    let chapters = List::new(chapter_entry)
+        .with_spacing(2.0)
+        .lens(MangaViewData::chapters);
+    let follow = Button::dynamic(|data: &MangaViewData, _env| {
+        if data.following { "Unfollow" } else { "Follow" }.into()
+    })
+    .on_click(|_ctx, data: &mut MangaViewData, _env| data.following = !data.following);
+    let statuses = std::iter::once(("No status", None)).chain(
+        STATUSES
+            .iter()
+            .map(|&(label, status)| (label, Some(status))),
+    );
     Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
         .with_child(title_label)
+        .with_child(cover)
+        .with_child(follow)
+        .with_child(RadioGroup::new(statuses).lens(MangaViewData::reading_status))
+        .with_child(Label::dynamic(|data: &MangaViewData, _env| {
+            data.library_error.as_ref().map_or_else(String::new, |e| {
+                format!("Couldn't update the library: {}", e)
+            })
+        }))
+        .with_child(chapters)
+        .with_child(Label::dynamic(|data: &MangaViewData, _env| {
+            if data.chapters_failed && !data.chapters.is_empty() {
+                "Showing the chapters saved last time".into()
+            } else if data.chapters_failed {
+                "Chapters are unavailable offline".into()
+            } else {
+                String::new()
+            }
+        }))
+        .with_child(
+            Flex::row().with_child(Label::new("Export as")).with_child(
+                RadioGroup::new(vec![
+                    ("CBZ", export::Format::Cbz),
+                    ("EPUB", export::Format::Epub),
+                    ("PDF", export::Format::Pdf),
+                ])
+                .lens(MangaViewData::export_format),
+            ),
+        )
+        .with_child(
+            Flex::row()
+                .with_child(
+                    Checkbox::new("Re-encode PDF pages as JPEG").lens(MangaViewData::pdf_reencode),
+                )
+                .with_child(
+                    Slider::new()
+                        .with_range(10.0, 100.0)
+                        .lens(MangaViewData::pdf_quality),
+                )
+                .with_child(Label::dynamic(|data: &MangaViewData, _env| {
+                    format!("Quality {:.0}", data.pdf_quality)
+                })),
+        )
+        .with_child(Label::dynamic(|data: &MangaViewData, _env| {
+            data.export_status
+                .as_ref()
+                .map_or_else(String::new, |s| (**s).clone())
+        }))
         .controller(MangaViewController::new(tx))
 }
 
+/// A window of its own for a title, for places that only list titles briefly.
+pub fn manga_window(
+    tx: mpsc::UnboundedSender<Message>,
+    item: &types::Manga,
+) -> WindowDesc<MangaListData> {
+    let data = view_data(item);
+    let title = (*data.title).clone();
+    WindowDesc::new(move || {
+        let view = Scroll::new(manga_view(tx)).vertical();
+        Standalone {
+            data,
+            inner: WidgetPod::new(Box::new(view)),
+        }
+        .lens(druid::lens::Unit)
+    })
+    .title(title)
+    .window_size((480., 800.))
+}
+
+/// Keeps a title's data to itself, since nothing else in the app shows it.
+struct Standalone {
+    data: MangaViewData,
+    inner: WidgetPod<MangaViewData, Box<dyn Widget<MangaViewData>>>,
+}
+
+impl Widget<()> for Standalone {
+    fn event(&mut self, ctx: &mut EventCtx<'_, '_>, event: &Event, _data: &mut (), env: &Env) {
+        let old = self.data.clone();
+        self.inner.event(ctx, event, &mut self.data, env);
+        if !old.same(&self.data) {
+            ctx.request_update();
+        }
+    }
+
+    fn lifecycle(
+        &mut self,
+        ctx: &mut LifeCycleCtx<'_, '_>,
+        event: &LifeCycle,
+        _data: &(),
+        env: &Env,
+    ) {
+        self.inner.lifecycle(ctx, event, &self.data, env);
+    }
+
+    fn update(&mut self, ctx: &mut UpdateCtx<'_, '_>, _old_data: &(), _data: &(), env: &Env) {
+        self.inner.update(ctx, &self.data, env);
+    }
+
+    fn layout(
+        &mut self,
+        ctx: &mut LayoutCtx<'_, '_>,
+        bc: &BoxConstraints,
+        _data: &(),
+        env: &Env,
+    ) -> Size {
+        let size = self.inner.layout(ctx, bc, &self.data, env);
+        self.inner.set_origin(ctx, &self.data, env, Point::ORIGIN);
+        size
+    }
+
+    fn paint(&mut self, ctx: &mut PaintCtx<'_, '_, '_>, _data: &(), env: &Env) {
+        self.inner.paint(ctx, &self.data, env);
+    }
+}
+
+fn chapter_entry() -> impl Widget<ChapterData> {
+    let label = Label::dynamic(|data: &ChapterData, _env| {
+        let mut label = (*data.label).clone();
+        if data.read {
+            label = format!("\u{2713} {}", label);
+        } else if let Some(page) = data.resume_page {
+            label += &format!(" (page {})", page + 1);
+        }
+        if data.saved {
+            label += " (saved)";
+        }
+        label
+    })
+    .on_click(|ctx, data: &mut ChapterData, _env| ctx.submit_command(OPEN_CHAPTER.with(*data.id)));
+    let status = Label::dynamic(|data: &ChapterData, _env| data.download.text());
+    let actions = ViewSwitcher::new(
+        |data: &ChapterData, _env| data.download.clone(),
+        |status, _data, _env| Box::new(download_actions(status)),
+    );
+    let toggle_read = Button::dynamic(|data: &ChapterData, _env| {
+        if data.read {
+            "Mark unread"
+        } else {
+            "Mark read"
+        }
+        .into()
+    })
+    .on_click(|ctx, data: &mut ChapterData, _env| {
+        ctx.submit_command(MARK_READ.with((*data.id, !data.read)))
+    });
+    Flex::row()
+        .with_child(label)
+        .with_spacer(4.0)
+        .with_child(toggle_read)
+        .with_child(status)
+        .with_child(actions)
+}
+
+fn download_actions(status: &DownloadStatus) -> Flex<ChapterData> {
+    fn queue_action(text: &'static str, f: fn(&schema::ChapterId)) -> impl Widget<ChapterData> {
+        Button::new(text).on_click(move |ctx, data: &mut ChapterData, _env| {
+            f(&data.id);
+            ctx.submit_command(DOWNLOADS_CHANGED);
+        })
+    }
+
+    let row = Flex::row();
+    match status {
+        DownloadStatus::NotQueued => row
+            .with_child(
+                Button::new("Download").on_click(|ctx, data: &mut ChapterData, _env| {
+                    ctx.submit_command(DOWNLOAD_CHAPTER.with(*data.id))
+                }),
+            )
+            .with_child(
+                Button::new("Volume").on_click(|ctx, data: &mut ChapterData, _env| {
+                    ctx.submit_command(DOWNLOAD_VOLUME.with(*data.id))
+                }),
+            ),
+        DownloadStatus::Queued(..) | DownloadStatus::Running(..) => row
+            .with_child(queue_action("Pause", download::pause))
+            .with_child(queue_action("Cancel", download::cancel)),
+        DownloadStatus::Paused(..) => row
+            .with_child(queue_action("Resume", download::resume))
+            .with_child(queue_action("Cancel", download::cancel)),
+        DownloadStatus::Failed(_) => row
+            .with_child(queue_action("Retry", download::resume))
+            .with_child(queue_action("Cancel", download::cancel)),
+        DownloadStatus::Done => row
+            .with_child(
+                Button::new("Export").on_click(|ctx, data: &mut ChapterData, _env| {
+                    ctx.submit_command(EXPORT_CHAPTER.with(*data.id))
+                }),
+            )
+            .with_child(Button::new("Export volume").on_click(
+                |ctx, data: &mut ChapterData, _env| {
+                    ctx.submit_command(EXPORT_VOLUME.with(*data.id))
+                },
+            ))
+            .with_child(queue_action("Delete", download::cancel)),
+    }
+}
+
 struct MangaViewController {
     cover_info: AsyncData<Result<image::RgbImage>>,
+    chapter_info: AsyncData<Result<Vec<types::Chapter>>>,
+    /// Where the export went, and which chapters were left out of it.
+    export_info: AsyncData<Result<(PathBuf, Vec<String>)>>,
+    library_info: AsyncData<Result<()>>,
+    /// Whether syncing changed any read markers.
+    progress_info: AsyncData<Result<bool>>,
+    /// The follow and reading status as the API last knew them.
+    synced: (bool, Option<schema::ReadingStatus>),
+    chapters: Arc<Vec<types::Chapter>>,
+    /// Chapters from the database, shown until the fetched ones arrive.
+    saved_chapters: Option<Vec<types::Chapter>>,
+    /// The languages chapters were last requested in.
+    languages: Vec<schema::Language>,
+    download_timer: TimerToken,
     tx: mpsc::UnboundedSender<Message>,
 }
 
@@ -42,9 +423,210 @@ impl MangaViewController {
     pub fn new(tx: mpsc::UnboundedSender<Message>) -> Self {
         Self {
             cover_info: Default::default(),
+            chapter_info: Default::default(),
+            export_info: Default::default(),
+            library_info: Default::default(),
+            progress_info: Default::default(),
+            synced: (false, None),
+            chapters: Default::default(),
+            saved_chapters: None,
+            languages: Vec::new(),
+            download_timer: TimerToken::INVALID,
             tx,
         }
     }
+
+    fn start_cover(&mut self, data: &MangaViewData) {
+        if let Some(cover_id) = &data.cover_id {
+            let (manga_id, cover_id) = (*data.id, **cover_id);
+            let fut =
+                async move { endpoint::get_cover_by_id(&manga_id, &cover_id, ".256.jpg").await };
+            self.cover_info.start(&self.tx, fut);
+        }
+    }
+
+    fn start_chapters(&mut self, data: &MangaViewData) {
+        let manga_id = *data.id;
+        self.languages = chapter_languages();
+        let languages = self.languages.clone();
+        let fut = async move { endpoint::get_chapters(&manga_id, languages).await };
+        self.chapter_info.start(&self.tx, fut);
+    }
+
+    fn set_chapters(
+        &mut self,
+        ctx: &mut EventCtx<'_, '_>,
+        data: &mut MangaViewData,
+        chapters: Vec<types::Chapter>,
+    ) {
+        self.chapters = Arc::new(chapters);
+        data.chapters = self.chapters.iter().map(ChapterData::from).collect();
+        // Downloads queued in an earlier session may still be going.
+        if data.chapters.iter().any(|c| c.download.is_active()) {
+            self.watch_downloads(ctx);
+        }
+    }
+
+    /// Exports a downloaded chapter, or its whole volume, in the chosen format.
+    fn start_export(
+        &mut self,
+        data: &mut MangaViewData,
+        chapter_id: schema::ChapterId,
+        whole_volume: bool,
+    ) {
+        if self.export_info.is_in_progress() {
+            return;
+        }
+        let manga_id = *data.id;
+        let attrs = data.attributes.clone();
+        let options = export::Options {
+            format: data.export_format,
+            jpeg_quality: if data.pdf_reencode {
+                Some(data.pdf_quality.round() as u8)
+            } else {
+                None
+            },
+        };
+        let creators = data.creators.clone();
+        let layout = saved_layout(&manga_id).unwrap_or(data.layout);
+        let cover_id = data.cover_id.as_deref().copied();
+        let chapters = self.chapters.clone();
+        let fut = async move {
+            let aggregate = if whole_volume {
+                let chapter = find_chapter(&chapters, &chapter_id);
+                let languages = [chapter.attributes.translated_language.clone()];
+                Some(endpoint::get_aggregate(&manga_id, &languages).await?)
+            } else {
+                None
+            };
+            // Exports can do without these if they can't be had.
+            let mut authors = Vec::new();
+            for author_id in creators.iter() {
+                if let Ok(author) = endpoint::get_author(author_id).await {
+                    authors.push(author.name);
+                }
+            }
+            // Only EPUBs get a cover.
+            let cover = match (options.format, cover_id) {
+                (export::Format::Epub, Some(cover_id)) => {
+                    endpoint::get_cover_by_id(&manga_id, &cover_id, "")
+                        .await
+                        .ok()
+                }
+                _ => None,
+            };
+            let info = export::MangaInfo {
+                id: manga_id,
+                attributes: attrs,
+                authors,
+                right_to_left: layout.direction == Direction::RightToLeft,
+                cover,
+            };
+            endpoint::blocking(move || {
+                let chapter = find_chapter(&chapters, &chapter_id);
+                let (selection, missing) = match &aggregate {
+                    Some(aggregate) => {
+                        let volume = chapter.attributes.volume.clone();
+                        let group = chapter
+                            .relationships
+                            .get(&types::RelationshipType::ScanlationGroup)
+                            .copied();
+                        export::Selection::volume(aggregate, volume, &chapters, group)
+                    }
+                    None => (export::Selection::Chapter(chapter), Vec::new()),
+                };
+                if selection.chapters().is_empty() {
+                    return NotDownloadedErr { chapter_id }.fail();
+                }
+                export::export(options, &info, &selection).map(|path| (path, missing))
+            })
+            .await
+        };
+        self.export_info.start(&self.tx, fut);
+        data.export_status = Some(Arc::new("Exporting...".into()));
+    }
+
+    fn enqueue<'a>(
+        &self,
+        data: &mut MangaViewData,
+        chapters: impl Iterator<Item = &'a types::Chapter>,
+    ) {
+        for chapter in chapters {
+            download::enqueue(*data.id, chapter, settings::get().quality());
+        }
+        self.refresh_downloads(data);
+    }
+
+    /// Updates every chapter's download status, returning whether any are still in progress.
+    fn refresh_downloads(&self, data: &mut MangaViewData) -> bool {
+        let mut active = false;
+        for chapter in data.chapters.iter_mut() {
+            let status = DownloadStatus::of(&chapter.id);
+            if status != chapter.download {
+                if let Some(c) = self.chapters.iter().find(|c| c.id == *chapter.id) {
+                    let pages = endpoint::ChapterPages::new(c, download::quality_for(&c.id));
+                    chapter.saved = pages.is_saved();
+                }
+                chapter.download = status;
+            }
+            active |= chapter.download.is_active();
+        }
+        active
+    }
+
+    /// Sends follow and reading status changes made in the UI on to the API.
+    fn sync_library(&mut self, ctx: &mut EventCtx<'_, '_>, data: &MangaViewData) {
+        let wanted = (data.following, data.reading_status);
+        if wanted == self.synced || self.library_info.is_in_progress() {
+            return;
+        }
+        let manga_id = *data.id;
+        let synced = self.synced;
+        let fut = async move {
+            if wanted.0 != synced.0 {
+                library::set_following(manga_id, wanted.0).await?;
+            }
+            if wanted.1 != synced.1 {
+                library::set_reading_status(manga_id, wanted.1).await?;
+            }
+            Ok(())
+        };
+        self.library_info.start(&self.tx, fut);
+        ctx.request_timer(REFRESH);
+    }
+
+    /// Shows the follow and reading status from the saved library.
+    fn reset_library(&mut self, data: &mut MangaViewData) {
+        self.synced = (
+            library::is_following(&data.id),
+            library::reading_status(&data.id),
+        );
+        data.following = self.synced.0;
+        data.reading_status = self.synced.1;
+    }
+
+    fn refresh_progress(&self, data: &mut MangaViewData) {
+        let progress = progress::for_manga(&data.id);
+        for chapter in data.chapters.iter_mut() {
+            chapter.set_progress(progress.get(&chapter.id));
+        }
+    }
+
+    /// Trades read markers with the API, if logged in.
+    fn start_progress_sync(&mut self, ctx: &mut EventCtx<'_, '_>, data: &MangaViewData) {
+        if auth::username().is_none() || self.progress_info.is_in_progress() {
+            return;
+        }
+        self.progress_info.start(&self.tx, progress::sync(*data.id));
+        ctx.request_timer(REFRESH);
+    }
+
+    /// Polls the download queue until none of this title's chapters are waiting on it.
+    fn watch_downloads(&mut self, ctx: &mut EventCtx<'_, '_>) {
+        if self.download_timer == TimerToken::INVALID {
+            self.download_timer = ctx.request_timer(REFRESH);
+        }
+    }
 }
 
 impl Controller<MangaViewData, Flex<MangaViewData>> for MangaViewController {
@@ -56,18 +638,178 @@ impl Controller<MangaViewData, Flex<MangaViewData>> for MangaViewController {
         data: &mut MangaViewData,
         env: &Env,
     ) {
+        if let Event::Command(cmd) = event {
+            if let Some(chapter_id) = cmd.get(OPEN_CHAPTER) {
+                if let Some(index) = self.chapters.iter().position(|c| c.id == *chapter_id) {
+                    let window = reader_window(
+                        self.tx.clone(),
+                        *data.id,
+                        data.title.clone(),
+                        data.layout,
+                        self.chapters.clone(),
+                        index,
+                        None,
+                    );
+                    ctx.new_window(window);
+                    ctx.set_handled();
+                }
+            } else if let Some(chapter_id) = cmd.get(DOWNLOAD_CHAPTER) {
+                let chapters = self.chapters.clone();
+                if let Some(chapter) = chapters.iter().find(|c| c.id == *chapter_id) {
+                    self.enqueue(data, std::iter::once(chapter));
+                    self.watch_downloads(ctx);
+                    ctx.set_handled();
+                }
+            } else if let Some(chapter_id) = cmd.get(DOWNLOAD_VOLUME) {
+                let chapters = self.chapters.clone();
+                if let Some(chapter) = chapters.iter().find(|c| c.id == *chapter_id) {
+                    let volume = &chapter.attributes.volume;
+                    let same_volume = chapters.iter().filter(|c| c.attributes.volume == *volume);
+                    // One release per chapter, sticking with ones already queued, and otherwise
+                    // with the preferred language and the group of the chapter clicked on.
+                    let languages = language::preferred();
+                    let group = chapter
+                        .relationships
+                        .get(&types::RelationshipType::ScanlationGroup)
+                        .copied();
+                    let picked = export::one_per_chapter(same_volume, |c| {
+                        let queued = download::status(&c.id).is_some();
+                        (queued, export::release_rank(c, &languages, group))
+                    });
+                    self.enqueue(data, picked.into_iter());
+                    self.watch_downloads(ctx);
+                    ctx.set_handled();
+                }
+            } else if let Some(chapter_id) = cmd.get(EXPORT_CHAPTER) {
+                if self.chapters.iter().any(|c| c.id == *chapter_id) {
+                    self.start_export(data, *chapter_id, false);
+                    ctx.request_timer(REFRESH);
+                    ctx.set_handled();
+                }
+            } else if let Some(chapter_id) = cmd.get(EXPORT_VOLUME) {
+                if self.chapters.iter().any(|c| c.id == *chapter_id) {
+                    self.start_export(data, *chapter_id, true);
+                    ctx.request_timer(REFRESH);
+                    ctx.set_handled();
+                }
+            } else if let Some((chapter_id, read)) = cmd.get(MARK_READ) {
+                let chapter = data.chapters.iter().find(|c| *c.id == *chapter_id);
+                if let Some(chapter) = chapter {
+                    let changed = chapter.read != *read;
+                    // A failed save still leaves the change in place for this session.
+                    let _ = progress::set_read(*data.id, *chapter_id, *read);
+                    self.start_progress_sync(ctx, data);
+                    if changed {
+                        ctx.submit_command(PROGRESS_CHANGED.with(*data.id).to(Target::Global));
+                    }
+                }
+            } else if cmd.get(PROGRESS_CHANGED) == Some(&*data.id) {
+                self.refresh_progress(data);
+            } else if cmd.is(DOWNLOADS_CHANGED) {
+                // Every title checks, since we don't know whose chapter it was.
+                self.refresh_downloads(data);
+                self.watch_downloads(ctx);
+            }
+        }
+        if let Event::Command(cmd) = event {
+            if cmd.is(PROFILE_CHANGED) {
+                if !self.library_info.is_in_progress() {
+                    self.reset_library(data);
+                }
+                self.refresh_progress(data);
+                self.start_progress_sync(ctx, data);
+            }
+            if cmd.is(WENT_ONLINE) {
+                // Retry whatever failed while we were offline.
+                self.start_progress_sync(ctx, data);
+                if data.cover_buf.is_none() && !self.cover_info.is_in_progress() {
+                    self.start_cover(data);
+                }
+                if data.chapters_failed && !self.chapter_info.is_in_progress() {
+                    self.start_chapters(data);
+                }
+                ctx.request_timer(REFRESH);
+            }
+            if cmd.is(SETTINGS_CHANGED) {
+                data.title = Arc::new(language::title(&data.attributes));
+                data.layout = default_layout(&data.attributes);
+                if self.languages != chapter_languages() && !self.chapter_info.is_in_progress() {
+                    self.start_chapters(data);
+                    ctx.request_timer(REFRESH);
+                }
+            }
+        }
+        if let Event::Timer(token) = event {
+            if *token == self.download_timer {
+                self.download_timer = TimerToken::INVALID;
+                if self.refresh_downloads(data) {
+                    self.watch_downloads(ctx);
+                }
+                return child.event(ctx, event, data, env);
+            }
+        }
         if matches!(event, Event::Timer(_)) {
-            if let Some(response) = self.cover_info.poll() {
-                let img = response.unwrap();
+            // A missing cover isn't worth reporting; it's simply left out.
+            if let Some(Ok(img)) = self.cover_info.poll() {
                 let (w, h) = (img.width(), img.height());
                 let pixels: Arc<[u8]> = img.into_raw().into();
                 let buf = ImageBuf::from_raw(pixels, ImageFormat::Rgb, w as usize, h as usize);
                 data.cover_buf = Arc::new(Some(buf));
-            } else {
+            }
+            if let Some(chapters) = self.saved_chapters.take() {
+                if !chapters.is_empty() && data.chapters.is_empty() {
+                    self.set_chapters(ctx, data, chapters);
+                }
+            }
+            match self.chapter_info.poll() {
+                Some(Ok(chapters)) => {
+                    self.set_chapters(ctx, data, chapters);
+                    data.chapters_failed = false;
+                    self.start_progress_sync(ctx, data);
+                }
+                Some(Err(_)) => data.chapters_failed = true,
+                None => {}
+            }
+            match self.export_info.poll() {
+                Some(Ok((path, missing))) => {
+                    let mut status = format!("Exported to {}", path.display());
+                    if !missing.is_empty() {
+                        status += &format!(" without {} (not downloaded)", missing.join(", "));
+                    }
+                    data.export_status = Some(Arc::new(status));
+                }
+                Some(Err(e)) => {
+                    data.export_status = Some(Arc::new(format!("Export failed: {}", e)))
+                }
+                None => {}
+            }
+            // Read markers just stay as they were if they can't be synced.
+            if let Some(Ok(true)) = self.progress_info.poll() {
+                ctx.submit_command(PROGRESS_CHANGED.with(*data.id).to(Target::Global));
+            }
+            if let Some(res) = self.library_info.poll() {
+                // Either way, the saved library now says what the API has.
+                data.library_error = res.err().map(|e| Arc::new(e.to_string()));
+                let wanted = (data.following, data.reading_status);
+                self.reset_library(data);
+                if data.library_error.is_none() {
+                    // Anything changed again in the meantime still needs sending.
+                    data.following = wanted.0;
+                    data.reading_status = wanted.1;
+                }
+                ctx.submit_command(LIBRARY_CHANGED.to(Target::Global));
+            }
+            if self.cover_info.is_in_progress()
+                || self.chapter_info.is_in_progress()
+                || self.export_info.is_in_progress()
+                || self.library_info.is_in_progress()
+                || self.progress_info.is_in_progress()
+            {
                 ctx.request_timer(REFRESH);
             }
         }
         child.event(ctx, event, data, env);
+        self.sync_library(ctx, data);
     }
 
     fn lifecycle(
@@ -79,46 +821,12 @@ impl Controller<MangaViewData, Flex<MangaViewData>> for MangaViewController {
         env: &Env,
     ) {
         if matches!(event, LifeCycle::WidgetAdded) {
-            if let Some(cover_id) = &data.cover_id {
-                let url = format!("https://api.mangadex.org/cover/{}", cover_id);
-                let manga_id = *data.id;
-                let fut = async move {
-                    let resp = endpoint::get_json::<_, schema::CoverResponse>(url).await?;
-
-                    assert_eq!(resp.result, schema::Success::Ok);
-                    assert_eq!(resp.data.item_type, schema::ItemType::CoverArt);
-
-                    let filename = resp.data.attributes.file_name;
-
-                    let img = endpoint::get_cover(&manga_id, &filename, ".256.jpg").await?;
-                    Ok(img)
-                };
-                self.cover_info.start(&self.tx, fut);
-                ctx.request_timer(REFRESH);
-            }
+            self.synced = (data.following, data.reading_status);
+            self.start_cover(data);
+            self.saved_chapters = db::chapters(&data.id, &chapter_languages()).ok();
+            self.start_chapters(data);
+            ctx.request_timer(REFRESH);
         }
         child.lifecycle(ctx, event, data, env);
     }
-
-    fn update(
-        &mut self,
-        child: &mut Flex<MangaViewData>,
-        ctx: &mut UpdateCtx<'_, '_>,
-        old_data: &MangaViewData,
-        data: &MangaViewData,
-        env: &Env,
-    ) {
-        child.update(ctx, old_data, data, env);
-        // I do not understand why this goes *after* the other thing.
-        if let (Some(buf), None) = (&*data.cover_buf, &*old_data.cover_buf) {
-            let view = Image::new(buf.clone());
-            child.add_child(view);
-            ctx.children_changed();
-        }
-    }
-}
-
-#[derive(Default, Clone, druid::Data, druid::Lens)]
-pub struct MangaListData {
-    titles: im::Vector<MangaViewData>,
 }
@@ -0,0 +1,137 @@
+use crate::endpoint::auth::{self, profile};
+
+use std::sync::Arc;
+
+use druid::im;
+use druid::widget::{
+    Button, Controller, CrossAxisAlignment, Either, Flex, Label, List, Scroll, SizedBox, TextBox,
+};
+use druid::{Data, Env, Event, EventCtx, Lens, Selector, Target, Widget, WidgetExt, WindowDesc};
+
+use super::manga_list::MangaListData;
+use super::PROFILE_CHANGED;
+
+#[derive(Default, Clone, Data, Lens)]
+pub struct ProfilesData {
+    pub(super) active: Arc<String>,
+    entries: im::Vector<ProfileEntry>,
+    new_name: String,
+    error: Option<Arc<String>>,
+}
+
+#[derive(Clone, Data, Lens)]
+struct ProfileEntry {
+    name: Arc<String>,
+    active: bool,
+}
+
+impl ProfilesData {
+    pub fn new() -> Self {
+        let mut data = Self::default();
+        data.reload();
+        data
+    }
+
+    fn reload(&mut self) {
+        let active = profile::active();
+        self.entries = profile::list()
+            .into_iter()
+            .map(|name| ProfileEntry {
+                active: name == active,
+                name: Arc::new(name),
+            })
+            .collect();
+        self.active = Arc::new(active);
+    }
+}
+
+const SWITCH: Selector<Arc<String>> = Selector::new("md.profiles.switch");
+const CREATE: Selector = Selector::new("md.profiles.create");
+
+pub fn profiles_window() -> WindowDesc<MangaListData> {
+    WindowDesc::new(|| profiles_dialog().lens(MangaListData::profiles))
+        .title("Profiles")
+        .window_size((320., 300.))
+}
+
+fn profile_entry() -> impl Widget<ProfileEntry> {
+    let label = Label::dynamic(|data: &ProfileEntry, _env| {
+        if data.active {
+            format!("{} (active)", data.name)
+        } else {
+            data.name.to_string()
+        }
+    });
+    let switch = Either::new(
+        |data: &ProfileEntry, _env| data.active,
+        SizedBox::empty(),
+        Button::new("Switch").on_click(|ctx, data: &mut ProfileEntry, _env| {
+            ctx.submit_command(SWITCH.with(data.name.clone()))
+        }),
+    );
+    Flex::row()
+        .with_flex_child(label.expand_width(), 1.0)
+        .with_child(switch)
+}
+
+fn profiles_dialog() -> impl Widget<ProfilesData> {
+    let list = List::new(profile_entry).lens(ProfilesData::entries);
+    let create = Flex::row()
+        .with_flex_child(
+            TextBox::new().lens(ProfilesData::new_name).expand_width(),
+            1.0,
+        )
+        .with_child(Button::new("Add").on_click(|ctx, _, _| ctx.submit_command(CREATE)));
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_flex_child(Scroll::new(list).vertical().expand_width(), 1.0)
+        .with_spacer(8.0)
+        .with_child(Label::new("New profile"))
+        .with_child(create)
+        .with_child(Label::dynamic(|data: &ProfilesData, _env| {
+            data.error.as_deref().cloned().unwrap_or_default()
+        }))
+        .padding(8.0)
+        .controller(ProfilesController)
+}
+
+struct ProfilesController;
+
+impl<W: Widget<ProfilesData>> Controller<ProfilesData, W> for ProfilesController {
+    fn event(
+        &mut self,
+        child: &mut W,
+        ctx: &mut EventCtx<'_, '_>,
+        event: &Event,
+        data: &mut ProfilesData,
+        env: &Env,
+    ) {
+        match event {
+            Event::Command(cmd) if cmd.is(SWITCH) => {
+                let name = cmd.get_unchecked(SWITCH);
+                match auth::switch_profile(name) {
+                    Ok(()) => {
+                        data.error = None;
+                        data.reload();
+                        ctx.submit_command(PROFILE_CHANGED.to(Target::Global));
+                    }
+                    Err(e) => data.error = Some(Arc::new(e.to_string())),
+                }
+                ctx.set_handled();
+            }
+            Event::Command(cmd) if cmd.is(CREATE) => {
+                match profile::create(data.new_name.trim()) {
+                    Ok(()) => {
+                        data.error = None;
+                        data.new_name.clear();
+                        data.reload();
+                    }
+                    Err(e) => data.error = Some(Arc::new(e.to_string())),
+                }
+                ctx.set_handled();
+            }
+            _ => {}
+        }
+        child.event(ctx, event, data, env);
+    }
+}
@@ -0,0 +1,807 @@
+use crate::async_data::{self, AsyncData};
+use crate::endpoint::{auth, download, history, progress};
+use crate::{endpoint, schema, types, Message, Result};
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::mpsc;
+
+use druid::piet::{
+    ImageFormat, InterpolationMode, PietImage, Text as _, TextLayout as _, TextLayoutBuilder as _,
+};
+use druid::{
+    BoxConstraints, Color, Env, Event, EventCtx, FontFamily, ImageBuf, KbKey, LayoutCtx, LifeCycle,
+    LifeCycleCtx, PaintCtx, Point, Rect, RenderContext, Size, Target, TimerToken, UpdateCtx, Vec2,
+    Widget, WidgetExt, WindowDesc,
+};
+
+use super::manga_list::MangaListData;
+use super::manga_view::chapter_label;
+use super::{PROGRESS_CHANGED, REFRESH, WENT_ONLINE};
+
+mod layout;
+
+pub use layout::{
+    default_for as default_layout, saved as saved_layout, Direction, PageLayout, ReaderLayout,
+};
+
+const ZOOM_STEP: f64 = 1.25;
+const MIN_ZOOM: f64 = 0.25;
+const MAX_ZOOM: f64 = 8.0;
+const SCROLL_STEP: f64 = 60.0;
+/// How many pages past the ones on screen to download ahead of time.
+const PREFETCH_PAGES: usize = 5;
+/// How many pages of the next chapter to download once the end of this one is near.
+const PREFETCH_NEXT_CHAPTER: usize = 3;
+/// Height-to-width ratio assumed for long strip pages that haven't loaded yet.
+const PLACEHOLDER_RATIO: f64 = 1.5;
+/// How long the page has to stay put before it's saved as the place to resume at.
+const SAVE_DELAY: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fit {
+    Width,
+    Height,
+    Page,
+}
+
+impl Fit {
+    fn next(self) -> Self {
+        match self {
+            Self::Page => Self::Width,
+            Self::Width => Self::Height,
+            Self::Height => Self::Page,
+        }
+    }
+}
+
+/// Opens a reader at `chapter`, starting at `start_page` if given, or else where it was left.
+pub fn reader_window(
+    tx: mpsc::UnboundedSender<Message>,
+    manga_id: schema::MangaId,
+    manga_title: Arc<String>,
+    default_layout: ReaderLayout,
+    chapters: Arc<Vec<types::Chapter>>,
+    chapter: usize,
+    start_page: Option<usize>,
+) -> WindowDesc<MangaListData> {
+    let title = chapter_label(&chapters[chapter]);
+    let layout = layout::saved(&manga_id).unwrap_or(default_layout);
+    WindowDesc::new(move || {
+        let mut reader = Reader::new(tx, manga_id, manga_title, layout, chapters, chapter);
+        reader.start_page = start_page;
+        reader.lens(druid::lens::Unit)
+    })
+    .title(title)
+    .window_size((800., 1000.))
+}
+
+pub struct Reader {
+    tx: mpsc::UnboundedSender<Message>,
+    manga_id: schema::MangaId,
+    manga_title: Arc<String>,
+    chapters: Arc<Vec<types::Chapter>>,
+    chapter: usize,
+    /// Overrides `resume_page` for the first chapter shown.
+    start_page: Option<usize>,
+    pages: Arc<endpoint::ChapterPages>,
+    next_pages: Option<Arc<endpoint::ChapterPages>>,
+    page: usize,
+    loading: HashMap<usize, AsyncData<Result<image::RgbImage>>>,
+    images: HashMap<usize, ImageBuf>,
+    errors: HashMap<usize, String>,
+    painted: HashMap<usize, PietImage>,
+    layout: ReaderLayout,
+    fit: Fit,
+    zoom: f64,
+    scroll: Vec2,
+    /// The last position seen, to avoid recording the same one over and over.
+    recorded: Option<(schema::ChapterId, usize, bool)>,
+    /// A page turned to but not yet saved to `progress`, which waits for `save_timer`.
+    unsaved: Option<(schema::ChapterId, usize)>,
+    save_timer: TimerToken,
+    /// The history entry for the current chapter.
+    history_entry: Option<(schema::ChapterId, u64)>,
+    /// Why the last layout change couldn't be saved.
+    layout_error: Option<String>,
+}
+
+impl Reader {
+    pub fn new(
+        tx: mpsc::UnboundedSender<Message>,
+        manga_id: schema::MangaId,
+        manga_title: Arc<String>,
+        layout: ReaderLayout,
+        chapters: Arc<Vec<types::Chapter>>,
+        chapter: usize,
+    ) -> Self {
+        let pages = Arc::new(endpoint::ChapterPages::new(
+            &chapters[chapter],
+            download::quality_for(&chapters[chapter].id),
+        ));
+        Self {
+            tx,
+            manga_id,
+            manga_title,
+            chapters,
+            chapter,
+            start_page: None,
+            pages,
+            next_pages: None,
+            page: 0,
+            loading: HashMap::new(),
+            images: HashMap::new(),
+            errors: HashMap::new(),
+            painted: HashMap::new(),
+            layout,
+            fit: Fit::Page,
+            zoom: 1.0,
+            scroll: Vec2::ZERO,
+            recorded: None,
+            unsaved: None,
+            save_timer: TimerToken::INVALID,
+            history_entry: None,
+            layout_error: None,
+        }
+    }
+
+    fn open_chapter(&mut self, chapter: usize) {
+        let next_pages = self.next_pages.take();
+        self.pages = match next_pages {
+            // Reuse the prefetched chapter so its base URL doesn't need resolving again.
+            Some(pages) if chapter == self.chapter + 1 => pages,
+            _ => Arc::new(endpoint::ChapterPages::new(
+                &self.chapters[chapter],
+                download::quality_for(&self.chapters[chapter].id),
+            )),
+        };
+        self.chapter = chapter;
+        self.page = 0;
+        self.loading.clear();
+        self.images.clear();
+        self.errors.clear();
+        self.painted.clear();
+    }
+
+    /// Where to start reading the current chapter: wherever it was left, unless it was finished.
+    fn resume_page(&self) -> usize {
+        let chapter_id = self.chapters[self.chapter].id;
+        progress::get(&chapter_id)
+            .filter(|p| !p.read)
+            .map_or(0, |p| p.last_page.min(self.pages.len().saturating_sub(1)))
+    }
+
+    /// Saves the current page to the history, starting a new entry for a new chapter.
+    fn record_history(&mut self) {
+        let chapter = &self.chapters[self.chapter];
+        match self.history_entry {
+            Some((chapter_id, id)) if chapter_id == chapter.id => history::set_page(id, self.page),
+            _ => {
+                let id = history::open(
+                    self.manga_id,
+                    (*self.manga_title).clone(),
+                    chapter.id,
+                    chapter_label(chapter),
+                    chapter.attributes.translated_language.clone(),
+                    self.page,
+                );
+                self.history_entry = Some((chapter.id, id));
+            }
+        }
+    }
+
+    /// Saves the current page, and marks the chapter read once its last page is on screen.
+    fn record_progress(&mut self, ctx: &mut EventCtx<'_, '_>) {
+        if self.pages.is_empty() {
+            return;
+        }
+        let finished = if self.layout.pages == PageLayout::LongStrip {
+            self.strip_at_end(ctx.size())
+        } else {
+            let last_shown = self.spread().last().copied().unwrap_or(self.page);
+            last_shown + 1 >= self.pages.len()
+        };
+        let chapter_id = self.chapters[self.chapter].id;
+        let position = (chapter_id, self.page, finished);
+        if self.recorded == Some(position) {
+            return;
+        }
+        self.recorded = Some(position);
+        self.record_history();
+
+        if self.unsaved.is_some_and(|(id, _)| id != chapter_id) {
+            // Moved on to another chapter, so the last one is done with.
+            self.save_progress();
+        }
+        let newly_read = finished && !progress::get(&chapter_id).is_some_and(|p| p.read);
+        if !newly_read {
+            // Paging back and forth only needs saving once it settles.
+            self.unsaved = Some((chapter_id, self.page));
+            if self.save_timer == TimerToken::INVALID {
+                self.save_timer = ctx.request_timer(SAVE_DELAY);
+            }
+            return;
+        }
+
+        self.unsaved = None;
+        progress::set_page(self.manga_id, chapter_id, self.page, true);
+        history::flush();
+        if auth::username().is_some() {
+            let manga_id = self.manga_id;
+            async_data::detach(&self.tx, async move {
+                // If this fails, the chapter list syncs it later.
+                let _ = progress::sync(manga_id).await;
+            });
+        }
+        ctx.submit_command(PROGRESS_CHANGED.with(self.manga_id).to(Target::Global));
+    }
+
+    /// Saves the page waiting to be saved, if there is one, along with the history.
+    fn save_progress(&mut self) {
+        if let Some((chapter_id, page)) = self.unsaved.take() {
+            progress::set_page(self.manga_id, chapter_id, page, false);
+        }
+        history::flush();
+    }
+
+    fn set_layout(&mut self, ctx: &mut EventCtx<'_, '_>, layout: ReaderLayout) {
+        self.layout = layout;
+        self.layout_error = layout::save(self.manga_id, layout)
+            .err()
+            .map(|e| e.to_string());
+        let page = self.page;
+        self.go_to(ctx, page);
+    }
+
+    fn load(&mut self, index: usize) {
+        if index >= self.pages.len()
+            || self.images.contains_key(&index)
+            || self.loading.contains_key(&index)
+        {
+            return;
+        }
+        let pages = self.pages.clone();
+        let fut = async move { pages.load_page(index).await };
+        let mut data = AsyncData::new();
+        data.start(&self.tx, fut);
+        self.loading.insert(index, data);
+    }
+
+    /// Starts loading upcoming pages ahead of time. They go through `loading` like any other
+    /// page, so one that's turned to while still on its way isn't fetched a second time.
+    fn prefetch(&mut self, from: usize) {
+        for index in from..from + PREFETCH_PAGES {
+            self.load(index);
+        }
+
+        let near_end = from + PREFETCH_PAGES >= self.pages.len();
+        let next = self.chapter + 1;
+        if near_end && self.next_pages.is_none() && next < self.chapters.len() {
+            let pages = Arc::new(endpoint::ChapterPages::new(
+                &self.chapters[next],
+                download::quality_for(&self.chapters[next].id),
+            ));
+            let count = pages.len().min(PREFETCH_NEXT_CHAPTER);
+            let next_pages = pages.clone();
+            async_data::detach(&self.tx, async move {
+                for index in 0..count {
+                    let _ = next_pages.load_page(index).await;
+                }
+            });
+            self.next_pages = Some(pages);
+        }
+    }
+
+    /// Moves all finished downloads into `images`. Returns whether anything arrived.
+    fn poll(&mut self) -> bool {
+        let mut finished = Vec::new();
+        for (&index, data) in &mut self.loading {
+            if let Some(res) = data.poll() {
+                finished.push((index, res));
+            }
+        }
+
+        let changed = !finished.is_empty();
+        for (index, res) in finished {
+            self.loading.remove(&index);
+            match res {
+                Ok(img) => {
+                    let (w, h) = (img.width(), img.height());
+                    let pixels: Arc<[u8]> = img.into_raw().into();
+                    let buf = ImageBuf::from_raw(pixels, ImageFormat::Rgb, w as usize, h as usize);
+                    self.images.insert(index, buf);
+                }
+                Err(e) => {
+                    self.errors.insert(index, e.to_string());
+                }
+            }
+        }
+        changed
+    }
+
+    fn is_wide(&self, index: usize) -> bool {
+        self.images.get(&index).is_some_and(|buf| {
+            let size = buf.size();
+            size.width > size.height
+        })
+    }
+
+    /// The pages shown together starting at `self.page`, in reading order.
+    /// Wide images are double-page spreads already, so they are always shown alone.
+    fn spread(&self) -> Vec<usize> {
+        let first = self.page;
+        let second = first + 1;
+        if self.layout.pages != PageLayout::Double
+            || second >= self.pages.len()
+            || self.is_wide(first)
+            || self.is_wide(second)
+        {
+            vec![first]
+        } else {
+            vec![first, second]
+        }
+    }
+
+    fn go_to(&mut self, ctx: &mut EventCtx<'_, '_>, page: usize) {
+        self.page = page;
+        self.errors.remove(&page);
+        if self.layout.pages == PageLayout::LongStrip {
+            let offsets = self.strip_offsets(self.strip_width(ctx.size()));
+            self.scroll = Vec2::new(self.scroll.x, offsets[page.min(offsets.len() - 1)]);
+            self.load_visible(ctx.size());
+        } else {
+            self.scroll = Vec2::ZERO;
+            for index in page..page + 3 {
+                self.load(index);
+            }
+            self.prefetch(page + 3);
+        }
+        if !self.loading.is_empty() {
+            ctx.request_timer(REFRESH);
+        }
+        ctx.request_paint();
+    }
+
+    fn next_page(&mut self, ctx: &mut EventCtx<'_, '_>) {
+        let step = self.spread().len();
+        if self.page + step < self.pages.len() {
+            self.go_to(ctx, self.page + step);
+        } else {
+            self.next_chapter(ctx);
+        }
+    }
+
+    fn prev_page(&mut self, ctx: &mut EventCtx<'_, '_>) {
+        if self.page == 0 {
+            self.prev_chapter(ctx);
+            return;
+        }
+        let mut target = self.page - 1;
+        if self.layout.pages == PageLayout::Double
+            && target > 0
+            && !self.is_wide(target)
+            && !self.is_wide(target - 1)
+        {
+            target -= 1;
+        }
+        self.go_to(ctx, target);
+    }
+
+    fn next_chapter(&mut self, ctx: &mut EventCtx<'_, '_>) {
+        if self.chapter + 1 < self.chapters.len() {
+            self.open_chapter(self.chapter + 1);
+            let page = self.resume_page();
+            self.go_to(ctx, page);
+        }
+    }
+
+    fn prev_chapter(&mut self, ctx: &mut EventCtx<'_, '_>) {
+        if self.chapter > 0 {
+            self.open_chapter(self.chapter - 1);
+            let last = match self.layout.pages {
+                PageLayout::LongStrip => 0,
+                _ => self.pages.len().saturating_sub(1),
+            };
+            self.go_to(ctx, last);
+        }
+    }
+
+    /// Advances by one "screen": a page or spread, or most of the viewport in long strip mode.
+    fn forward(&mut self, ctx: &mut EventCtx<'_, '_>) {
+        if self.layout.pages == PageLayout::LongStrip {
+            if self.strip_at_end(ctx.size()) {
+                self.next_chapter(ctx);
+            } else {
+                let height = ctx.size().height;
+                self.scroll_by(ctx, Vec2::new(0.0, height * 0.9));
+            }
+        } else {
+            self.next_page(ctx);
+        }
+    }
+
+    fn back(&mut self, ctx: &mut EventCtx<'_, '_>) {
+        if self.layout.pages == PageLayout::LongStrip {
+            if self.scroll.y <= 0.0 {
+                self.prev_chapter(ctx);
+            } else {
+                let height = ctx.size().height;
+                self.scroll_by(ctx, Vec2::new(0.0, -height * 0.9));
+            }
+        } else {
+            self.prev_page(ctx);
+        }
+    }
+
+    fn set_zoom(&mut self, ctx: &mut EventCtx<'_, '_>, zoom: f64) {
+        self.zoom = zoom.clamp(MIN_ZOOM, MAX_ZOOM);
+        if self.layout.pages == PageLayout::LongStrip {
+            self.load_visible(ctx.size());
+            if !self.loading.is_empty() {
+                ctx.request_timer(REFRESH);
+            }
+        }
+        ctx.request_paint();
+    }
+
+    fn scroll_by(&mut self, ctx: &mut EventCtx<'_, '_>, delta: Vec2) {
+        self.scroll += delta;
+        if self.layout.pages == PageLayout::LongStrip {
+            self.load_visible(ctx.size());
+            if !self.loading.is_empty() {
+                ctx.request_timer(REFRESH);
+            }
+        }
+        ctx.request_paint();
+    }
+
+    /// Where the current page (or spread) should be drawn, given the widget and content sizes.
+    /// Also clamps `scroll` so that the page can't be dragged out of view.
+    fn page_rect(&mut self, size: Size, content: Size) -> Rect {
+        let base = match self.fit {
+            Fit::Width => size.width / content.width,
+            Fit::Height => size.height / content.height,
+            Fit::Page => (size.width / content.width).min(size.height / content.height),
+        };
+        let drawn = content * base * self.zoom;
+
+        let overflow = Vec2::new(
+            (drawn.width - size.width).max(0.0),
+            (drawn.height - size.height).max(0.0),
+        );
+        self.scroll.x = self.scroll.x.max(0.0).min(overflow.x);
+        self.scroll.y = self.scroll.y.max(0.0).min(overflow.y);
+
+        let origin = Point::new(
+            ((size.width - drawn.width) / 2.0).max(0.0) - self.scroll.x,
+            ((size.height - drawn.height) / 2.0).max(0.0) - self.scroll.y,
+        );
+        Rect::from_origin_size(origin, drawn)
+    }
+
+    fn strip_width(&self, size: Size) -> f64 {
+        size.width * self.zoom
+    }
+
+    /// The top edge of every page in the long strip, followed by the total strip height.
+    fn strip_offsets(&self, width: f64) -> Vec<f64> {
+        let mut offsets = Vec::with_capacity(self.pages.len() + 1);
+        let mut y = 0.0;
+        for index in 0..self.pages.len() {
+            offsets.push(y);
+            y += match self.images.get(&index) {
+                Some(buf) => width * buf.size().height / buf.size().width,
+                None => width * PLACEHOLDER_RATIO,
+            };
+        }
+        offsets.push(y);
+        offsets
+    }
+
+    fn clamp_strip_scroll(&mut self, size: Size, offsets: &[f64]) {
+        let total = offsets.last().copied().unwrap_or(0.0);
+        let overflow = Vec2::new(
+            (self.strip_width(size) - size.width).max(0.0),
+            (total - size.height).max(0.0),
+        );
+        self.scroll.x = self.scroll.x.max(0.0).min(overflow.x);
+        self.scroll.y = self.scroll.y.max(0.0).min(overflow.y);
+    }
+
+    fn strip_at_end(&mut self, size: Size) -> bool {
+        let offsets = self.strip_offsets(self.strip_width(size));
+        self.clamp_strip_scroll(size, &offsets);
+        let total = offsets.last().copied().unwrap_or(0.0);
+        self.scroll.y + size.height >= total - 1.0
+    }
+
+    /// Starts loading every long strip page in (or just below) the viewport,
+    /// and updates `self.page` to whichever page is in the middle of the screen.
+    fn load_visible(&mut self, size: Size) {
+        let offsets = self.strip_offsets(self.strip_width(size));
+        self.clamp_strip_scroll(size, &offsets);
+
+        let top = self.scroll.y;
+        let bottom = top + size.height;
+        let center = (top + bottom) / 2.0;
+
+        let mut visible = Vec::new();
+        for (index, span) in offsets.windows(2).enumerate() {
+            if span[0] <= center && center < span[1] {
+                self.page = index;
+            }
+            if span[1] >= top && span[0] <= bottom {
+                visible.push(index);
+            }
+        }
+        if let Some(&last) = visible.last() {
+            visible.push(last + 1);
+        }
+        let from = visible.last().map_or(0, |last| last + 1);
+        for index in visible {
+            self.load(index);
+        }
+        self.prefetch(from);
+    }
+
+    fn paint_pages(&mut self, ctx: &mut PaintCtx<'_, '_, '_>, size: Size) {
+        let mut drawn = Vec::new();
+        if self.layout.pages == PageLayout::LongStrip {
+            let width = self.strip_width(size);
+            let offsets = self.strip_offsets(width);
+            self.clamp_strip_scroll(size, &offsets);
+
+            let x = ((size.width - width) / 2.0).max(0.0) - self.scroll.x;
+            for (index, span) in offsets.windows(2).enumerate() {
+                let (top, bottom) = (span[0] - self.scroll.y, span[1] - self.scroll.y);
+                if bottom >= 0.0 && top <= size.height {
+                    self.paint_page(ctx, index, Rect::new(x, top, x + width, bottom));
+                    drawn.push(index);
+                }
+            }
+        } else {
+            let mut spread: Vec<(usize, Size)> = self
+                .spread()
+                .into_iter()
+                .filter_map(|index| self.images.get(&index).map(|buf| (index, buf.size())))
+                .collect();
+            if self.layout.direction == Direction::RightToLeft {
+                spread.reverse();
+            }
+
+            if !spread.is_empty() {
+                // Scale every page to a common height before laying them side by side.
+                let width = spread.iter().map(|(_, s)| s.width / s.height).sum();
+                let rect = self.page_rect(size, Size::new(width, 1.0));
+                let mut x = rect.x0;
+                for (index, page_size) in spread {
+                    let w = rect.height() * page_size.width / page_size.height;
+                    self.paint_page(ctx, index, Rect::new(x, rect.y0, x + w, rect.y1));
+                    drawn.push(index);
+                    x += w;
+                }
+            }
+        }
+        self.painted.retain(|index, _| drawn.contains(index));
+    }
+
+    fn paint_page(&mut self, ctx: &mut PaintCtx<'_, '_, '_>, index: usize, rect: Rect) {
+        let buf = match self.images.get(&index) {
+            Some(buf) => buf,
+            None => return,
+        };
+        let img = self
+            .painted
+            .entry(index)
+            .or_insert_with(|| buf.to_image(ctx.render_ctx));
+        ctx.draw_image(img, rect, InterpolationMode::Bilinear);
+    }
+
+    fn status_text(&self) -> String {
+        let label = chapter_label(&self.chapters[self.chapter]);
+        let page = if self.pages.is_empty() {
+            "no pages".to_owned()
+        } else {
+            let spread = self.spread();
+            match (spread.first(), spread.last()) {
+                (Some(a), Some(b)) if a != b => {
+                    format!("{}-{} / {}", a + 1, b + 1, self.pages.len())
+                }
+                _ => format!("{} / {}", self.page + 1, self.pages.len()),
+            }
+        };
+        match self.errors.get(&self.page) {
+            Some(_) if !endpoint::is_online() => {
+                format!("{} - {} - offline, and this page isn't saved", label, page)
+            }
+            Some(e) => format!("{} - {} - {}", label, page, e),
+            None if !self.images.contains_key(&self.page) => {
+                format!("{} - {} - loading...", label, page)
+            }
+            None => match &self.layout_error {
+                Some(e) => format!("{} - {} - couldn't save the layout: {}", label, page, e),
+                None => format!("{} - {}", label, page),
+            },
+        }
+    }
+}
+
+impl Drop for Reader {
+    fn drop(&mut self) {
+        self.save_progress();
+    }
+}
+
+impl Widget<()> for Reader {
+    fn event(&mut self, ctx: &mut EventCtx<'_, '_>, event: &Event, _data: &mut (), _env: &Env) {
+        let rtl = self.layout.direction == Direction::RightToLeft
+            && self.layout.pages != PageLayout::LongStrip;
+        match event {
+            Event::WindowConnected => {
+                ctx.request_focus();
+                let page = match self.start_page.take() {
+                    Some(page) => page.min(self.pages.len().saturating_sub(1)),
+                    None => self.resume_page(),
+                };
+                self.go_to(ctx, page);
+            }
+            Event::Command(cmd) if cmd.is(WENT_ONLINE) && !self.errors.is_empty() => {
+                self.errors.clear();
+                let page = self.page;
+                self.go_to(ctx, page);
+            }
+            Event::Timer(token) if *token == self.save_timer => {
+                self.save_timer = TimerToken::INVALID;
+                self.save_progress();
+            }
+            Event::Timer(_) => {
+                if self.poll() {
+                    if self.layout.pages == PageLayout::LongStrip {
+                        // Page heights may have changed, and with them what's on screen.
+                        self.load_visible(ctx.size());
+                    }
+                    ctx.request_paint();
+                }
+                if !self.loading.is_empty() {
+                    ctx.request_timer(REFRESH);
+                }
+            }
+            Event::KeyDown(key) => {
+                match &key.key {
+                    KbKey::ArrowRight if rtl => self.back(ctx),
+                    KbKey::ArrowLeft if rtl => self.forward(ctx),
+                    KbKey::ArrowRight | KbKey::PageDown => self.forward(ctx),
+                    KbKey::ArrowLeft | KbKey::PageUp | KbKey::Backspace => self.back(ctx),
+                    KbKey::ArrowDown => self.scroll_by(ctx, Vec2::new(0.0, SCROLL_STEP)),
+                    KbKey::ArrowUp => self.scroll_by(ctx, Vec2::new(0.0, -SCROLL_STEP)),
+                    KbKey::Home => self.go_to(ctx, 0),
+                    KbKey::End => self.go_to(ctx, self.pages.len().saturating_sub(1)),
+                    KbKey::Character(c) => match c.as_str() {
+                        " " => self.forward(ctx),
+                        "f" => {
+                            self.fit = self.fit.next();
+                            self.zoom = 1.0;
+                            ctx.request_paint();
+                        }
+                        "w" => {
+                            self.fit = Fit::Width;
+                            ctx.request_paint();
+                        }
+                        "h" => {
+                            self.fit = Fit::Height;
+                            ctx.request_paint();
+                        }
+                        "l" => {
+                            let pages = self.layout.pages.next();
+                            self.set_layout(
+                                ctx,
+                                ReaderLayout {
+                                    pages,
+                                    ..self.layout
+                                },
+                            );
+                        }
+                        "r" => {
+                            let direction = match self.layout.direction {
+                                Direction::LeftToRight => Direction::RightToLeft,
+                                Direction::RightToLeft => Direction::LeftToRight,
+                            };
+                            self.set_layout(
+                                ctx,
+                                ReaderLayout {
+                                    direction,
+                                    ..self.layout
+                                },
+                            );
+                        }
+                        "+" | "=" => self.set_zoom(ctx, self.zoom * ZOOM_STEP),
+                        "-" => self.set_zoom(ctx, self.zoom / ZOOM_STEP),
+                        "0" => self.set_zoom(ctx, 1.0),
+                        _ => return,
+                    },
+                    _ => return,
+                }
+                ctx.set_handled();
+            }
+            Event::MouseDown(mouse) => {
+                ctx.request_focus();
+                if mouse.button.is_left() && self.layout.pages != PageLayout::LongStrip {
+                    let right_half = mouse.pos.x >= ctx.size().width / 2.0;
+                    if right_half != rtl {
+                        self.forward(ctx);
+                    } else {
+                        self.back(ctx);
+                    }
+                }
+            }
+            Event::Wheel(mouse) => {
+                if mouse.mods.ctrl() {
+                    let factor = if mouse.wheel_delta.y < 0.0 {
+                        ZOOM_STEP
+                    } else {
+                        1.0 / ZOOM_STEP
+                    };
+                    self.set_zoom(ctx, self.zoom * factor);
+                } else {
+                    self.scroll_by(ctx, mouse.wheel_delta);
+                }
+                ctx.set_handled();
+            }
+            _ => {}
+        }
+        self.record_progress(ctx);
+    }
+
+    fn lifecycle(
+        &mut self,
+        ctx: &mut LifeCycleCtx<'_, '_>,
+        event: &LifeCycle,
+        _data: &(),
+        _env: &Env,
+    ) {
+        if matches!(event, LifeCycle::WidgetAdded) {
+            ctx.register_for_focus();
+        }
+    }
+
+    fn update(&mut self, _ctx: &mut UpdateCtx<'_, '_>, _old_data: &(), _data: &(), _env: &Env) {}
+
+    fn layout(
+        &mut self,
+        _ctx: &mut LayoutCtx<'_, '_>,
+        bc: &BoxConstraints,
+        _data: &(),
+        _env: &Env,
+    ) -> Size {
+        bc.max()
+    }
+
+    fn paint(&mut self, ctx: &mut PaintCtx<'_, '_, '_>, _data: &(), _env: &Env) {
+        let size = ctx.size();
+        ctx.fill(size.to_rect(), &Color::BLACK);
+        ctx.with_save(|ctx| {
+            ctx.clip(size.to_rect());
+            self.paint_pages(ctx, size);
+        });
+
+        let status = ctx
+            .text()
+            .new_text_layout(self.status_text())
+            .font(FontFamily::SYSTEM_UI, 14.0)
+            .text_color(Color::WHITE)
+            .build()
+            .unwrap();
+        let status_size = status.size();
+        let origin = Point::new(
+            (size.width - status_size.width) / 2.0,
+            size.height - status_size.height - 4.0,
+        );
+        ctx.fill(
+            Rect::from_origin_size(origin, status_size).inflate(4.0, 2.0),
+            &Color::rgba8(0, 0, 0, 0xA0),
+        );
+        ctx.draw_text(&status, origin);
+    }
+}
@@ -0,0 +1,116 @@
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use uuid::Uuid;
+
+use crate::endpoint::auth::profile;
+use crate::schema::{MangaAttributes, MangaId, TagId};
+use crate::store::{self, PerProfile};
+use crate::Result;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, druid::Data, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    LeftToRight,
+    RightToLeft,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, druid::Data, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PageLayout {
+    Single,
+    Double,
+    LongStrip,
+}
+
+impl PageLayout {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Single => Self::Double,
+            Self::Double => Self::LongStrip,
+            Self::LongStrip => Self::Single,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, druid::Data, Serialize, Deserialize)]
+pub struct ReaderLayout {
+    pub direction: Direction,
+    pub pages: PageLayout,
+}
+
+impl Default for ReaderLayout {
+    fn default() -> Self {
+        Self {
+            direction: Direction::LeftToRight,
+            pages: PageLayout::Single,
+        }
+    }
+}
+
+/// The "Long Strip" format tag, which webtoons carry.
+const LONG_STRIP: TagId = TagId(Uuid::from_u128(0x3e2b8dae_350e_4ab8_a8ce_016e844b9f0d));
+
+impl ReaderLayout {
+    /// Guesses a sensible layout from the series' metadata.
+    /// Webtoons are tagged "Long Strip"; Japanese originals read right-to-left.
+    pub fn guess(attrs: &MangaAttributes) -> Self {
+        let long_strip = attrs.tags.iter().any(|tag| tag.id == LONG_STRIP);
+
+        if long_strip {
+            Self {
+                direction: Direction::LeftToRight,
+                pages: PageLayout::LongStrip,
+            }
+        } else if attrs.original_language == "ja" {
+            Self {
+                direction: Direction::RightToLeft,
+                pages: PageLayout::Single,
+            }
+        } else {
+            Self::default()
+        }
+    }
+}
+
+fn layouts_file_location() -> Result<PathBuf> {
+    Ok(profile::dir()?.join("reader_layouts.json"))
+}
+
+fn load_saved() -> HashMap<MangaId, ReaderLayout> {
+    let path = match layouts_file_location() {
+        Ok(path) => path,
+        Err(_) => return HashMap::new(),
+    };
+    // Before profiles, layouts were saved at the top level.
+    if !path.exists() && profile::active() == profile::DEFAULT {
+        let _ = fs::rename(crate::data_dir().join("reader_layouts.json"), &path);
+    }
+    store::read_json(&path).unwrap_or_default()
+}
+
+static SAVED: Lazy<PerProfile<HashMap<MangaId, ReaderLayout>>> =
+    Lazy::new(|| PerProfile::new(load_saved));
+
+/// The layout to start a series in until the user picks one for it:
+/// the one from the settings, or else a guess.
+pub fn default_for(attrs: &MangaAttributes) -> ReaderLayout {
+    crate::settings::get()
+        .reader_layout
+        .unwrap_or_else(|| ReaderLayout::guess(attrs))
+}
+
+/// The layout the user last picked for this series, if they ever changed it.
+pub fn saved(manga_id: &MangaId) -> Option<ReaderLayout> {
+    SAVED.with(|saved| saved.get(manga_id).copied())
+}
+
+/// Remembers the layout for this series. It's kept for the session even if saving fails.
+pub fn save(manga_id: MangaId, layout: ReaderLayout) -> Result<()> {
+    SAVED.with(|saved| {
+        saved.insert(manga_id, layout);
+        store::write_json(&layouts_file_location()?, saved)
+    })
+}
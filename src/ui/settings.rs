@@ -0,0 +1,312 @@
+use crate::async_data::AsyncData;
+use crate::endpoint::disk_cache;
+use crate::language;
+use crate::schema::ContentRating;
+use crate::settings::{self, Settings};
+use crate::{Message, Result};
+
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use tokio::sync::mpsc;
+
+use druid::widget::{
+    Button, Checkbox, Controller, CrossAxisAlignment, Either, Flex, Label, RadioGroup, SizedBox,
+    TextBox,
+};
+use druid::{Data, Env, Event, EventCtx, Lens, Selector, Target, Widget, WidgetExt, WindowDesc};
+
+use super::manga_list::MangaListData;
+use super::reader::{Direction, PageLayout, ReaderLayout};
+use super::{REFRESH, SETTINGS_CHANGED};
+
+/// The settings as shown in the window, with numbers and lists as typed.
+#[derive(Clone, Data, Lens)]
+pub struct SettingsData {
+    guess_layout: bool,
+    direction: Direction,
+    pages: PageLayout,
+    download_dir: String,
+    data_saver: bool,
+    memory_cache: String,
+    disk_cache: String,
+    languages: String,
+    safe: bool,
+    suggestive: bool,
+    erotica: bool,
+    pornographic: bool,
+    rate_limit: String,
+    error: Option<Arc<String>>,
+    cache_status: Option<Arc<String>>,
+    /// What the window was filled in from, to tell whether anything has been edited since.
+    #[data(ignore)]
+    shown: Arc<Settings>,
+}
+
+impl Default for SettingsData {
+    fn default() -> Self {
+        Self::from_settings(&Settings::default())
+    }
+}
+
+impl SettingsData {
+    pub fn new() -> Self {
+        let mut data = Self::from_settings(&settings::get());
+        data.error = settings::problem()
+            .map(|e| Arc::new(format!("The settings file wasn't applied: {}", e)));
+        data
+    }
+
+    fn from_settings(settings: &Settings) -> Self {
+        let layout = settings.reader_layout.unwrap_or_default();
+        let rated = |rating| settings.content_rating.contains(&rating);
+        let mut data = Self {
+            guess_layout: settings.reader_layout.is_none(),
+            direction: layout.direction,
+            pages: layout.pages,
+            download_dir: settings
+                .download_dir
+                .as_ref()
+                .map(|dir| dir.display().to_string())
+                .unwrap_or_default(),
+            data_saver: settings.data_saver,
+            memory_cache: settings.memory_cache_mib.to_string(),
+            disk_cache: settings.disk_cache_mib.to_string(),
+            languages: settings.languages.join(", "),
+            safe: rated(ContentRating::Safe),
+            suggestive: rated(ContentRating::Suggestive),
+            erotica: rated(ContentRating::Erotica),
+            pornographic: rated(ContentRating::Pornographic),
+            rate_limit: settings.rate_limit.to_string(),
+            error: None,
+            cache_status: None,
+            shown: Arc::new(settings.clone()),
+        };
+        // Compared as the window would save it, so that e.g. the order of ratings doesn't count.
+        if let Ok(shown) = data.to_settings() {
+            data.shown = Arc::new(shown);
+        }
+        data
+    }
+
+    /// Whether the window has changes that haven't been saved.
+    pub fn is_edited(&self) -> bool {
+        !matches!(self.to_settings(), Ok(settings) if settings == *self.shown)
+    }
+
+    fn restore_defaults(&mut self) {
+        let shown = self.shown.clone();
+        *self = Self {
+            shown,
+            ..Self::default()
+        };
+    }
+
+    fn to_settings(&self) -> std::result::Result<Settings, String> {
+        let reader_layout = if self.guess_layout {
+            None
+        } else {
+            Some(ReaderLayout {
+                direction: self.direction,
+                pages: self.pages,
+            })
+        };
+        let download_dir = Some(self.download_dir.trim())
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from);
+        let content_rating = [
+            (self.safe, ContentRating::Safe),
+            (self.suggestive, ContentRating::Suggestive),
+            (self.erotica, ContentRating::Erotica),
+            (self.pornographic, ContentRating::Pornographic),
+        ]
+        .iter()
+        .filter(|(on, _)| *on)
+        .map(|(_, rating)| *rating)
+        .collect();
+        Ok(Settings {
+            reader_layout,
+            download_dir,
+            memory_cache_mib: number(&self.memory_cache, "The memory cache size")?,
+            disk_cache_mib: number(&self.disk_cache, "The disk cache size")?,
+            languages: language::parse(&self.languages),
+            content_rating,
+            data_saver: self.data_saver,
+            rate_limit: number(&self.rate_limit, "The rate limit")?,
+        })
+    }
+}
+
+fn number<T: FromStr>(text: &str, what: &str) -> std::result::Result<T, String> {
+    text.trim()
+        .parse()
+        .map_err(|_| format!("{} must be a whole number", what))
+}
+
+const SAVE: Selector = Selector::new("md.settings.save");
+const CLEAR_CACHE: Selector = Selector::new("md.settings.clear_cache");
+
+pub fn settings_window(tx: mpsc::UnboundedSender<Message>) -> WindowDesc<MangaListData> {
+    WindowDesc::new(move || settings_dialog(tx).lens(MangaListData::settings))
+        .title("Settings")
+        .window_size((440., 720.))
+}
+
+fn heading(text: &str) -> Label<SettingsData> {
+    Label::new(text).with_text_size(16.0)
+}
+
+fn settings_dialog(tx: mpsc::UnboundedSender<Message>) -> impl Widget<SettingsData> {
+    let layouts = Flex::row()
+        .with_child(
+            RadioGroup::new(vec![
+                ("Left to right", Direction::LeftToRight),
+                ("Right to left", Direction::RightToLeft),
+            ])
+            .lens(SettingsData::direction),
+        )
+        .with_child(
+            RadioGroup::new(vec![
+                ("Single page", PageLayout::Single),
+                ("Two pages", PageLayout::Double),
+                ("Long strip", PageLayout::LongStrip),
+            ])
+            .lens(SettingsData::pages),
+        );
+    let layout = Either::new(
+        |data: &SettingsData, _env| data.guess_layout,
+        SizedBox::empty(),
+        layouts,
+    );
+
+    let ratings = Flex::row()
+        .with_child(Checkbox::new("Safe").lens(SettingsData::safe))
+        .with_child(Checkbox::new("Suggestive").lens(SettingsData::suggestive))
+        .with_child(Checkbox::new("Erotica").lens(SettingsData::erotica))
+        .with_child(Checkbox::new("Pornographic").lens(SettingsData::pornographic));
+
+    let buttons = Flex::row()
+        .with_child(Button::new("Save").on_click(|ctx, _, _| ctx.submit_command(SAVE)))
+        .with_child(
+            Button::new("Restore defaults")
+                .on_click(|_, data: &mut SettingsData, _| data.restore_defaults()),
+        );
+
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(heading("Reader"))
+        .with_child(
+            Checkbox::new("Guess the layout for each series").lens(SettingsData::guess_layout),
+        )
+        .with_child(layout)
+        .with_spacer(8.0)
+        .with_child(heading("Languages"))
+        .with_child(Label::new(
+            "Most preferred first, e.g. en, es-la, ja (for this profile)",
+        ))
+        .with_child(TextBox::new().lens(SettingsData::languages).expand_width())
+        .with_spacer(8.0)
+        .with_child(heading("Browsing"))
+        .with_child(Label::new("Content ratings to include (for this profile)"))
+        .with_child(ratings)
+        .with_spacer(8.0)
+        .with_child(heading("Downloads"))
+        .with_child(Label::new(
+            "Download new chapters to (leave empty for the default)",
+        ))
+        .with_child(
+            TextBox::new()
+                .lens(SettingsData::download_dir)
+                .expand_width(),
+        )
+        .with_child(
+            Checkbox::new("Data saver: smaller, recompressed pages").lens(SettingsData::data_saver),
+        )
+        .with_spacer(8.0)
+        .with_child(heading("Caches"))
+        .with_child(Label::new("Decoded pages in memory (MiB)"))
+        .with_child(TextBox::new().lens(SettingsData::memory_cache))
+        .with_child(Label::new("Images on disk (MiB)"))
+        .with_child(TextBox::new().lens(SettingsData::disk_cache))
+        .with_child(
+            Flex::row()
+                .with_child(
+                    Button::new("Clear image cache")
+                        .on_click(|ctx, _, _| ctx.submit_command(CLEAR_CACHE)),
+                )
+                .with_child(Label::dynamic(|data: &SettingsData, _env| {
+                    data.cache_status.as_deref().cloned().unwrap_or_default()
+                })),
+        )
+        .with_spacer(8.0)
+        .with_child(heading("Network"))
+        .with_child(Label::new(
+            "Requests per second (takes effect after a restart)",
+        ))
+        .with_child(TextBox::new().lens(SettingsData::rate_limit))
+        .with_spacer(8.0)
+        .with_child(buttons)
+        .with_child(Label::dynamic(|data: &SettingsData, _env| {
+            data.error.as_deref().cloned().unwrap_or_default()
+        }))
+        .with_child(Label::new(format!(
+            "Also editable in {}",
+            settings::settings_location().display()
+        )))
+        .padding(8.0)
+        .controller(SettingsController {
+            clear_info: Default::default(),
+            tx,
+        })
+}
+
+struct SettingsController {
+    clear_info: AsyncData<Result<()>>,
+    tx: mpsc::UnboundedSender<Message>,
+}
+
+impl<W: Widget<SettingsData>> Controller<SettingsData, W> for SettingsController {
+    fn event(
+        &mut self,
+        child: &mut W,
+        ctx: &mut EventCtx<'_, '_>,
+        event: &Event,
+        data: &mut SettingsData,
+        env: &Env,
+    ) {
+        if let Event::Command(cmd) = event {
+            if cmd.is(SAVE) {
+                let res = data
+                    .to_settings()
+                    .and_then(|new| settings::set(new).map_err(|e| e.to_string()));
+                match res {
+                    Ok(()) => {
+                        *data = SettingsData::new();
+                        ctx.submit_command(SETTINGS_CHANGED.to(Target::Global));
+                    }
+                    Err(e) => data.error = Some(Arc::new(e)),
+                }
+                ctx.set_handled();
+            } else if cmd.is(CLEAR_CACHE) {
+                if !self.clear_info.is_in_progress() {
+                    self.clear_info.start(&self.tx, disk_cache::clear());
+                    data.cache_status = Some(Arc::new("Clearing...".into()));
+                    ctx.request_timer(REFRESH);
+                }
+                ctx.set_handled();
+            }
+        }
+        if let Event::Timer(_) = event {
+            match self.clear_info.poll() {
+                Some(Ok(())) => data.cache_status = Some(Arc::new("Cleared".into())),
+                Some(Err(e)) => data.cache_status = Some(Arc::new(e.to_string())),
+                None if self.clear_info.is_in_progress() => {
+                    ctx.request_timer(REFRESH);
+                }
+                None => {}
+            }
+        }
+        child.event(ctx, event, data, env);
+    }
+}
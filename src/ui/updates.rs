@@ -0,0 +1,216 @@
+use crate::endpoint::updates;
+use crate::{async_data::AsyncData, schema, Message, Result};
+
+use std::sync::Arc;
+
+use chrono::Local;
+use tokio::sync::mpsc;
+
+use druid::im;
+use druid::widget::{
+    Button, Controller, CrossAxisAlignment, Either, Flex, Label, List, Scroll, SizedBox,
+};
+use druid::{
+    Data, Env, Event, EventCtx, Lens, LifeCycle, LifeCycleCtx, Selector, TimerToken, Widget,
+    WidgetExt,
+};
+
+use super::{PROFILE_CHANGED, REFRESH, SETTINGS_CHANGED, STATUS_REFRESH};
+
+const CHECK: Selector = Selector::new("md.updates.check");
+const MARK_ALL_READ: Selector = Selector::new("md.updates.mark_all_read");
+const MARK_READ: Selector<schema::ChapterId> = Selector::new("md.updates.mark_read");
+
+#[derive(Default, Clone, Data, Lens)]
+pub struct UpdatesData {
+    entries: im::Vector<UpdateEntry>,
+    pub(super) unread: usize,
+    checking: bool,
+    error: Option<Arc<String>>,
+}
+
+#[derive(Clone, Data, Lens)]
+struct UpdateEntry {
+    chapter_id: Arc<schema::ChapterId>,
+    label: Arc<String>,
+    unread: bool,
+}
+
+impl UpdatesData {
+    pub fn new() -> Self {
+        let mut data = Self::default();
+        data.reload();
+        data
+    }
+
+    fn reload(&mut self) {
+        self.entries = updates::updates()
+            .into_iter()
+            .map(|update| {
+                let number = update
+                    .chapter
+                    .map_or_else(|| "Oneshot".into(), |n| format!("Ch. {}", n));
+                let mut label = format!("{}: {}", update.manga_title, number);
+                if !update.title.is_empty() {
+                    label += &format!(" - {}", update.title);
+                }
+                label += &format!(
+                    " [{}] {}",
+                    update.language,
+                    update
+                        .published
+                        .with_timezone(&Local)
+                        .format("%Y-%m-%d %H:%M")
+                );
+                UpdateEntry {
+                    chapter_id: Arc::new(update.chapter_id),
+                    label: Arc::new(label),
+                    unread: update.unread,
+                }
+            })
+            .collect();
+        self.unread = updates::unread_count();
+    }
+}
+
+pub fn updates_view(tx: mpsc::UnboundedSender<Message>) -> impl Widget<UpdatesData> {
+    let entries = List::new(update_entry).lens(UpdatesData::entries);
+    let header = Flex::row()
+        .with_child(Button::new("Check now").on_click(|ctx, _, _| ctx.submit_command(CHECK)))
+        .with_child(
+            Button::new("Mark all read").on_click(|ctx, _, _| ctx.submit_command(MARK_ALL_READ)),
+        )
+        .with_spacer(8.0)
+        .with_child(Label::dynamic(|data: &UpdatesData, _env| {
+            if data.checking {
+                "Checking for new chapters...".into()
+            } else if let Some(e) = &data.error {
+                e.to_string()
+            } else if data.entries.is_empty() {
+                "No new chapters yet".into()
+            } else {
+                format!("{} unread", data.unread)
+            }
+        }));
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(header)
+        .with_flex_child(Scroll::new(entries).vertical(), 1.0)
+        .controller(UpdatesController::new(tx))
+}
+
+fn update_entry() -> impl Widget<UpdateEntry> {
+    let label = Label::dynamic(|data: &UpdateEntry, _env| {
+        let marker = if data.unread { "● " } else { "" };
+        format!("{}{}", marker, data.label)
+    });
+    let mark = Either::new(
+        |data: &UpdateEntry, _env| data.unread,
+        Button::new("Mark read").on_click(|ctx, data: &mut UpdateEntry, _env| {
+            ctx.submit_command(MARK_READ.with(*data.chapter_id))
+        }),
+        SizedBox::empty(),
+    );
+    Flex::row().with_child(mark).with_child(label)
+}
+
+struct UpdatesController {
+    check_info: AsyncData<Result<usize>>,
+    /// The inbox generation last shown, so changes from the background checker get picked up.
+    generation: Option<u64>,
+    poll_timer: TimerToken,
+    tx: mpsc::UnboundedSender<Message>,
+}
+
+impl UpdatesController {
+    fn new(tx: mpsc::UnboundedSender<Message>) -> Self {
+        Self {
+            check_info: Default::default(),
+            generation: None,
+            poll_timer: TimerToken::INVALID,
+            tx,
+        }
+    }
+
+    fn reload(&mut self, data: &mut UpdatesData) {
+        self.generation = Some(updates::generation());
+        data.reload();
+    }
+
+    /// Shows the outcome of a change made here, reloading the inbox if it went through.
+    fn apply(&mut self, data: &mut UpdatesData, res: Result<()>) {
+        data.error = res
+            .err()
+            .map(|e| Arc::new(format!("Failed to save updates: {}", e)));
+        self.reload(data);
+    }
+}
+
+impl<W: Widget<UpdatesData>> Controller<UpdatesData, W> for UpdatesController {
+    fn event(
+        &mut self,
+        child: &mut W,
+        ctx: &mut EventCtx<'_, '_>,
+        event: &Event,
+        data: &mut UpdatesData,
+        env: &Env,
+    ) {
+        match event {
+            Event::Timer(token) if *token == self.poll_timer => {
+                if self.generation != Some(updates::generation()) {
+                    self.reload(data);
+                }
+                self.poll_timer = ctx.request_timer(STATUS_REFRESH);
+            }
+            Event::Timer(_) => match self.check_info.poll() {
+                Some(res) => {
+                    data.checking = false;
+                    data.error = res
+                        .err()
+                        .map(|e| Arc::new(format!("Failed to check for updates: {}", e)));
+                    self.reload(data);
+                }
+                None if self.check_info.is_in_progress() => {
+                    ctx.request_timer(REFRESH);
+                }
+                None => {}
+            },
+            Event::Command(cmd) if cmd.is(CHECK) && !self.check_info.is_in_progress() => {
+                self.check_info.start(&self.tx, updates::check());
+                data.checking = true;
+                ctx.request_timer(REFRESH);
+            }
+            Event::Command(cmd) if cmd.is(MARK_ALL_READ) => {
+                self.apply(data, updates::mark_all_read());
+            }
+            Event::Command(cmd) if cmd.is(MARK_READ) => {
+                let chapter_id = cmd.get_unchecked(MARK_READ);
+                self.apply(data, updates::mark_read(chapter_id));
+            }
+            Event::Command(cmd) if cmd.is(SETTINGS_CHANGED) => {
+                // Only updates in the preferred languages are shown.
+                self.reload(data);
+            }
+            Event::Command(cmd) if cmd.is(PROFILE_CHANGED) => {
+                data.error = None;
+                self.reload(data);
+            }
+            _ => {}
+        }
+        child.event(ctx, event, data, env);
+    }
+
+    fn lifecycle(
+        &mut self,
+        child: &mut W,
+        ctx: &mut LifeCycleCtx<'_, '_>,
+        event: &LifeCycle,
+        data: &UpdatesData,
+        env: &Env,
+    ) {
+        if matches!(event, LifeCycle::WidgetAdded) {
+            self.poll_timer = ctx.request_timer(STATUS_REFRESH);
+        }
+        child.lifecycle(ctx, event, data, env);
+    }
+}